@@ -0,0 +1,121 @@
+use crate::budget::{Budget, format_ms};
+use crate::trace::{self, FsCalls};
+use crate::{Env, Error, render};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+// ============================================================================
+// Benchmark
+// ============================================================================
+//
+// `git-worktree-prompt bench` calls `render()` repeatedly in-process for the
+// start directory and reports latency percentiles, so regressions show up
+// without hyperfine. Caching and the daemon apply as they would in a shell,
+// so the numbers reflect the steady state of a prompt.
+
+/// Iterations run when `-n` is not given
+pub(crate) const DEFAULT_ITERATIONS: usize = 1000;
+
+/// Latencies from a benchmark run
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Report {
+    /// Sorted per-run latencies
+    samples: Vec<Duration>,
+    /// Filesystem calls across all runs
+    fs: FsCalls,
+    /// Output of the last run, to show what was measured
+    output: Option<String>,
+}
+
+impl Report {
+    fn new(mut samples: Vec<Duration>, fs: FsCalls, output: Option<String>) -> Report {
+        samples.sort();
+        Report {
+            samples,
+            fs,
+            output,
+        }
+    }
+
+    /// Nearest-rank percentile, `p` in 0..=100
+    pub(crate) fn percentile(&self, p: u32) -> Duration {
+        if self.samples.is_empty() {
+            return Duration::ZERO;
+        }
+        let rank = (p as usize * self.samples.len()).div_ceil(100).max(1);
+        self.samples[rank.min(self.samples.len()) - 1]
+    }
+
+    fn mean(&self) -> Duration {
+        let total: Duration = self.samples.iter().sum();
+        total / self.samples.len().max(1) as u32
+    }
+
+    pub(crate) fn render(&self) -> String {
+        let runs = self.samples.len();
+        let per_run = |n: u32| n as f64 / runs.max(1) as f64;
+        format!(
+            "output: {}\nruns:   {}\nmin:    {}\np50:    {}\np90:    {}\np99:    {}\nmax:    {}\nmean:   {}\nfs calls per run: {:.1} ({:.1} stat, {:.1} read)\n",
+            self.output.as_deref().unwrap_or("(none)"),
+            runs,
+            format_ms(self.percentile(0)),
+            format_ms(self.percentile(50)),
+            format_ms(self.percentile(90)),
+            format_ms(self.percentile(99)),
+            format_ms(self.percentile(100)),
+            format_ms(self.mean()),
+            per_run(self.fs.total()),
+            per_run(self.fs.stat),
+            per_run(self.fs.read),
+        )
+    }
+}
+
+/// Renders the prompt for `start` `iterations` times and collects latencies
+pub(crate) fn bench(start: &Path, iterations: usize, env: &Env) -> Result<Report, Error> {
+    let mut samples = Vec::with_capacity(iterations);
+    let mut fs = FsCalls::default();
+    let mut output = None;
+
+    for _ in 0..iterations {
+        trace::enable();
+        let started = Instant::now();
+        let result = render(start, env, &mut Budget::from_env(env));
+        samples.push(started.elapsed());
+        if let Some(trace) = trace::take() {
+            fs.stat += trace.fs.stat;
+            fs.read += trace.fs.read;
+        }
+        output = result?;
+    }
+
+    Ok(Report::new(samples, fs, output))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(ms: &[u64]) -> Report {
+        let samples = ms.iter().map(|&ms| Duration::from_millis(ms)).collect();
+        Report::new(samples, FsCalls::default(), None)
+    }
+
+    #[test]
+    fn test_percentiles() {
+        let report = report(&[5, 1, 4, 2, 3, 10, 9, 8, 7, 6]);
+        assert_eq!(report.percentile(0), Duration::from_millis(1));
+        assert_eq!(report.percentile(50), Duration::from_millis(5));
+        assert_eq!(report.percentile(90), Duration::from_millis(9));
+        assert_eq!(report.percentile(99), Duration::from_millis(10));
+        assert_eq!(report.percentile(100), Duration::from_millis(10));
+        assert_eq!(report.mean(), Duration::from_micros(5500));
+    }
+
+    #[test]
+    fn test_empty_report() {
+        let report = report(&[]);
+        assert_eq!(report.percentile(50), Duration::ZERO);
+        assert!(report.render().contains("runs:   0"));
+    }
+}
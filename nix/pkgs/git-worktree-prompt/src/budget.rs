@@ -0,0 +1,193 @@
+use crate::Env;
+use crate::trace::Trace;
+use std::time::{Duration, Instant};
+
+// ============================================================================
+// Time Budget
+// ============================================================================
+//
+// Every invocation gets a fixed time budget measured from process start.
+// Cheap phases (cache lookup, discovery) always run; expensive phases check
+// the remaining budget before and while running, and are cut when it runs
+// out so a huge repository never stalls the shell.
+
+/// Environment variable overriding the per-invocation budget in milliseconds
+pub(crate) const ENV_BUDGET_MS: &str = "GIT_WORKTREE_PROMPT_BUDGET_MS";
+
+/// Default per-invocation budget
+const DEFAULT_BUDGET_MS: u64 = 8;
+
+/// Timing record for one evaluation phase
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Phase {
+    pub(crate) name: &'static str,
+    pub(crate) elapsed: Duration,
+    /// True when the phase was skipped or aborted because the budget ran out
+    pub(crate) cut: bool,
+}
+
+/// Result of a budgeted segment: computed in time, last-known, or skipped
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Segment<T> {
+    Ready(T),
+    /// Previously computed value that may no longer be accurate
+    Stale(T),
+    Skipped,
+}
+
+/// Tracks elapsed time against the invocation's budget
+#[derive(Debug)]
+pub(crate) struct Budget {
+    started: Instant,
+    limit: Duration,
+    phases: Vec<Phase>,
+}
+
+impl Budget {
+    pub(crate) fn new(limit: Duration) -> Self {
+        Budget {
+            started: Instant::now(),
+            limit,
+            phases: Vec::new(),
+        }
+    }
+
+    /// Creates a budget from GIT_WORKTREE_PROMPT_BUDGET_MS or the default
+    pub(crate) fn from_env(env: &Env) -> Self {
+        let ms = env
+            .get(ENV_BUDGET_MS)
+            .and_then(|v| v.trim().parse().ok())
+            .unwrap_or(DEFAULT_BUDGET_MS);
+        Budget::new(Duration::from_millis(ms))
+    }
+
+    /// Point in time at which expensive phases must give up
+    pub(crate) fn deadline(&self) -> Instant {
+        self.started + self.limit
+    }
+
+    /// Time left before the deadline, zero once exhausted
+    pub(crate) fn remaining(&self) -> Duration {
+        self.deadline().saturating_duration_since(Instant::now())
+    }
+
+    pub(crate) fn exhausted(&self) -> bool {
+        Instant::now() >= self.deadline()
+    }
+
+    /// Records a phase that began at `started` and ends now
+    pub(crate) fn record(&mut self, name: &'static str, started: Instant, cut: bool) {
+        self.phases.push(Phase {
+            name,
+            elapsed: started.elapsed(),
+            cut,
+        });
+    }
+
+    #[cfg(test)]
+    pub(crate) fn phases(&self) -> &[Phase] {
+        &self.phases
+    }
+
+    /// Human-readable timing lines for --debug output
+    pub(crate) fn report(&self) -> Vec<String> {
+        let mut lines: Vec<String> = self
+            .phases
+            .iter()
+            .map(|phase| {
+                let state = if phase.cut { " (cut)" } else { "" };
+                format!(
+                    "phase {}: {}{}",
+                    phase.name,
+                    format_ms(phase.elapsed),
+                    state
+                )
+            })
+            .collect();
+        lines.push(format!(
+            "budget: {} used of {}",
+            format_ms(self.started.elapsed()),
+            format_ms(self.limit)
+        ));
+        lines
+    }
+}
+
+/// Human-readable lines for --debug output from a discovery trace
+pub(crate) fn trace_report(trace: &Trace) -> Vec<String> {
+    let mut lines: Vec<String> = trace
+        .steps
+        .iter()
+        .map(|(name, elapsed)| format!("step {}: {}", name, format_ms(*elapsed)))
+        .collect();
+    lines.push(format!(
+        "fs calls: {} ({} stat, {} read)",
+        trace.fs.total(),
+        trace.fs.stat,
+        trace.fs.read
+    ));
+    lines
+}
+
+/// Formats a duration as milliseconds with microsecond precision
+pub(crate) fn format_ms(duration: Duration) -> String {
+    format!("{:.3}ms", duration.as_secs_f64() * 1000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trace::FsCalls;
+
+    #[test]
+    fn test_zero_budget_is_exhausted() {
+        let budget = Budget::new(Duration::ZERO);
+        assert!(budget.exhausted());
+
+        let budget = Budget::new(Duration::from_secs(60));
+        assert!(!budget.exhausted());
+    }
+
+    #[test]
+    fn test_report_marks_cut_phases() {
+        let mut budget = Budget::new(Duration::from_millis(5));
+        budget.record("discovery", Instant::now(), false);
+        budget.record("status", Instant::now(), true);
+
+        let report = budget.report();
+        assert!(report[0].starts_with("phase discovery: "));
+        assert!(!report[0].ends_with("(cut)"));
+        assert!(report[1].starts_with("phase status: "));
+        assert!(report[1].ends_with("(cut)"));
+        assert!(report[2].ends_with("used of 5.000ms"));
+    }
+
+    #[test]
+    fn test_trace_report() {
+        let trace = Trace {
+            steps: vec![("probe", Duration::from_micros(1500))],
+            fs: FsCalls { stat: 2, read: 1 },
+        };
+        assert_eq!(
+            trace_report(&trace),
+            ["step probe: 1.500ms", "fs calls: 3 (2 stat, 1 read)"]
+        );
+    }
+
+    #[test]
+    fn test_budget_from_env() {
+        let env = |value: &str| Env::new().with(ENV_BUDGET_MS, value);
+        assert_eq!(
+            Budget::from_env(&env("250")).limit,
+            Duration::from_millis(250)
+        );
+        assert_eq!(
+            Budget::from_env(&env("soon")).limit,
+            Duration::from_millis(DEFAULT_BUDGET_MS)
+        );
+        assert_eq!(
+            Budget::from_env(&Env::new()).limit,
+            Duration::from_millis(DEFAULT_BUDGET_MS)
+        );
+    }
+}
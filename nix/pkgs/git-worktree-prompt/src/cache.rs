@@ -0,0 +1,577 @@
+use crate::jj::Jj;
+use crate::operation::{self, Kind, Operation};
+use crate::settings::RepoSettings;
+use crate::shape::{Shape, Sparse};
+use crate::submodule::Submodule;
+use crate::trace;
+use crate::upstream::Upstream;
+use crate::{Discovery, Env, Head, Layout, MAX_BARE_SEARCH_DEPTH, get_runtime_dir};
+use std::collections::HashMap;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::process;
+
+// ============================================================================
+// Discovery Result Cache
+// ============================================================================
+//
+// Discovery results are cached per start directory under
+// $XDG_RUNTIME_DIR/git-worktree-prompt/. Each entry records the stat
+// signature of every file discovery depended on (HEAD, the index, the
+// current branch ref, its upstream's tracking ref, packed-refs, FETCH_HEAD,
+// the .git pointer, the config files settings were read from and the
+// .git/.bare candidates that were probed), so a cache hit costs a handful of
+// stat calls and no file reads beyond the cache entry itself.
+
+/// Environment variable that disables the cache when set to "0"
+pub(crate) const ENV_CACHE: &str = "GIT_WORKTREE_PROMPT_CACHE";
+
+/// Version tag written as the first line of every cache entry
+const CACHE_FORMAT_VERSION: &str = "git-worktree-prompt-cache v9";
+
+/// Stat signature used to detect changes to a validated path
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Signature {
+    Missing,
+    /// Directories only record their inode: their mtime changes on every lock file
+    Dir {
+        ino: u64,
+    },
+    File {
+        ino: u64,
+        mtime_ns: i128,
+        len: u64,
+    },
+}
+
+impl Signature {
+    pub(crate) fn of(path: &Path) -> Signature {
+        match trace::metadata(path) {
+            Ok(meta) if meta.is_dir() => Signature::Dir { ino: meta.ino() },
+            Ok(meta) => Signature::File {
+                ino: meta.ino(),
+                mtime_ns: meta.mtime() as i128 * 1_000_000_000 + meta.mtime_nsec() as i128,
+                len: meta.len(),
+            },
+            Err(_) => Signature::Missing,
+        }
+    }
+
+    pub(crate) fn encode(&self) -> String {
+        match self {
+            Signature::Missing => "-".to_string(),
+            Signature::Dir { ino } => format!("d {}", ino),
+            Signature::File { ino, mtime_ns, len } => format!("f {} {} {}", ino, mtime_ns, len),
+        }
+    }
+
+    pub(crate) fn decode(s: &str) -> Option<Signature> {
+        let fields: Vec<&str> = s.split(' ').collect();
+        match fields.as_slice() {
+            ["-"] => Some(Signature::Missing),
+            ["d", ino] => Some(Signature::Dir {
+                ino: ino.parse().ok()?,
+            }),
+            ["f", ino, mtime_ns, len] => Some(Signature::File {
+                ino: ino.parse().ok()?,
+                mtime_ns: mtime_ns.parse().ok()?,
+                len: len.parse().ok()?,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Resolves the cache directory, or None when caching is disabled or unavailable
+pub(crate) fn cache_dir(env: &Env) -> Option<PathBuf> {
+    if env.get(ENV_CACHE) == Some("0") {
+        return None;
+    }
+    get_runtime_dir(env)
+}
+
+/// Maps a start directory to its cache entry file
+fn entry_path(dir: &Path, start: &Path) -> PathBuf {
+    dir.join(format!(
+        "{:016x}",
+        fnv1a(start.as_os_str().as_encoded_bytes())
+    ))
+}
+
+/// 64-bit FNV-1a hash; stable across builds unlike `DefaultHasher`
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+/// Lists every path whose change could alter the discovery result
+pub(crate) fn validated_paths(start: &Path, discovery: &Discovery) -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+
+    // A new .git between start and work_dir would change which repo is found
+    for dir in start.ancestors() {
+        if dir == discovery.work_dir {
+            break;
+        }
+        paths.push(dir.join(".git"));
+    }
+    paths.push(discovery.work_dir.join(".git"));
+
+    // Rewritten when a linked worktree is moved or repaired
+    paths.push(discovery.git_dir.join("gitdir"));
+    paths.push(discovery.git_dir.join("HEAD"));
+    paths.push(discovery.git_dir.join("index"));
+    if let Some(symref) = &discovery.head.symref {
+        paths.push(discovery.common_dir.join(symref));
+    }
+    paths.push(discovery.common_dir.join("packed-refs"));
+    paths.push(discovery.common_dir.join("FETCH_HEAD"));
+    paths.push(discovery.common_dir.join("shallow"));
+    // A `.jj` directory only matters next to a detached HEAD
+    if discovery.head.symref.is_none() {
+        paths.push(discovery.work_dir.join(".jj"));
+    }
+    if let Some(jj) = &discovery.jj {
+        paths.push(discovery.work_dir.join(".jj/working_copy/checkout"));
+        paths.extend(jj.op_head.iter().cloned());
+    }
+    for marker in operation::MARKERS {
+        paths.push(discovery.git_dir.join(marker));
+    }
+    paths.push(discovery.git_dir.join("info/sparse-checkout"));
+    if let Some(Upstream::Gone(tracking_ref) | Upstream::Tracking(tracking_ref)) =
+        &discovery.upstream
+    {
+        paths.push(discovery.common_dir.join(tracking_ref));
+    }
+    paths.extend(discovery.settings.sources.iter().cloned());
+
+    // The parent's index records the submodule commit; its .git locates the parent
+    if let Some(submodule) = &discovery.submodule {
+        paths.push(submodule.superproject.join(".git"));
+        paths.push(submodule.super_git_dir.join("index"));
+    }
+
+    // Every .bare candidate find_bare_parent probes
+    for dir in discovery.work_dir.ancestors().take(MAX_BARE_SEARCH_DEPTH) {
+        paths.push(dir.join(".bare"));
+    }
+
+    paths
+}
+
+/// Returns cached discovery results for `start` if every validator still matches
+pub(crate) fn load(start: &Path, env: &Env) -> Option<Discovery> {
+    let dir = cache_dir(env)?;
+    let content = trace::read_to_string(&entry_path(&dir, start)).ok()?;
+    let (discovery, validators) = decode(start, &content)?;
+
+    for (path, signature) in &validators {
+        if Signature::of(path) != *signature {
+            return None;
+        }
+    }
+
+    Some(discovery)
+}
+
+/// Writes discovery results for `start`; failures are silently ignored
+pub(crate) fn store(start: &Path, env: &Env, discovery: &Discovery) {
+    let Some(dir) = cache_dir(env) else {
+        return;
+    };
+    let Some(content) = encode(start, discovery) else {
+        return;
+    };
+
+    if fs::create_dir_all(&dir).is_err() {
+        return;
+    }
+
+    // Write to a private temp file and rename so readers never see partial entries
+    let entry = entry_path(&dir, start);
+    let tmp = entry.with_extension(format!("{}.tmp", process::id()));
+    if fs::write(&tmp, content).is_ok() && fs::rename(&tmp, &entry).is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+}
+
+/// Serializes discovery results and validator signatures as `key=value` lines
+fn encode(start: &Path, discovery: &Discovery) -> Option<String> {
+    let mut lines = vec![CACHE_FORMAT_VERSION.to_string()];
+    lines.push(format!("start={}", path_str(start)?));
+    lines.extend(encode_discovery(discovery)?);
+
+    for path in validated_paths(start, discovery) {
+        lines.push(format!(
+            "stat={}\t{}",
+            path_str(&path)?,
+            Signature::of(&path).encode()
+        ));
+    }
+
+    let mut content = lines.join("\n");
+    content.push('\n');
+    Some(content)
+}
+
+/// Serializes discovery results as `key=value` lines, or None if a value spans lines
+pub(crate) fn encode_discovery(discovery: &Discovery) -> Option<Vec<String>> {
+    let mut lines = Vec::new();
+    lines.push(format!("work_dir={}", path_str(&discovery.work_dir)?));
+    lines.push(format!("git_dir={}", path_str(&discovery.git_dir)?));
+    lines.push(format!("common_dir={}", path_str(&discovery.common_dir)?));
+    lines.push(format!("head={}", clean(&discovery.head.name)?));
+    if let Some(symref) = &discovery.head.symref {
+        lines.push(format!("symref={}", clean(symref)?));
+    }
+    match &discovery.layout {
+        Layout::Regular => {}
+        Layout::BareParent(bare_parent) => {
+            lines.push(format!("bare_parent={}", path_str(bare_parent)?));
+        }
+        Layout::Worktree { bare_parent, path } => {
+            lines.push(format!("bare_parent={}", path_str(bare_parent)?));
+            lines.push(format!("worktree={}", clean(path)?));
+        }
+    }
+
+    if let Some(upstream) = &discovery.upstream {
+        lines.push(format!("upstream={}", clean(&upstream.encode())?));
+    }
+
+    if let Some(last_fetch) = discovery.last_fetch {
+        lines.push(format!("last_fetch={}", last_fetch));
+    }
+
+    if !discovery.remotes.is_empty() {
+        let mut fields = Vec::new();
+        for (name, url) in &discovery.remotes {
+            fields.push(clean(name)?);
+            fields.push(clean(url)?);
+        }
+        lines.push(format!("remotes={}", fields.join("\t")));
+    }
+
+    if let Some(operation) = &discovery.operation {
+        lines.push(format!("operation={}", operation.kind.label()));
+        if let Some((step, total)) = operation.progress {
+            lines.push(format!("progress={}/{}", step, total));
+        }
+        lines.push(format!("conflicts={}", operation.conflicts));
+    }
+
+    if let Some(jj) = &discovery.jj {
+        lines.push(format!(
+            "jj={}",
+            clean(jj.change_id.as_deref().unwrap_or_default())?
+        ));
+        if !jj.bookmarks.is_empty() {
+            let bookmarks: Option<Vec<&str>> = jj.bookmarks.iter().map(|b| clean(b)).collect();
+            lines.push(format!("jj_bookmarks={}", bookmarks?.join("\t")));
+        }
+        if let Some(op_head) = &jj.op_head {
+            lines.push(format!("jj_op_head={}", path_str(op_head)?));
+        }
+    }
+
+    if discovery.shape.shallow {
+        lines.push("shallow=1".to_string());
+    }
+    if let Some(filter) = &discovery.shape.partial {
+        lines.push(format!("partial={}", clean(filter)?));
+    }
+    if let Some(sparse) = discovery.shape.sparse {
+        lines.push(format!("sparse={}", sparse.name()));
+    }
+
+    // Superproject, its git dir, path, HEAD id and recorded id; unknown ids stay empty
+    if let Some(submodule) = &discovery.submodule {
+        let fields = [
+            path_str(&submodule.superproject)?,
+            path_str(&submodule.super_git_dir)?,
+            clean(&submodule.path)?,
+            clean(submodule.head.as_deref().unwrap_or_default())?,
+            clean(submodule.recorded.as_deref().unwrap_or_default())?,
+        ];
+        lines.push(format!("submodule={}", fields.join("\t")));
+    }
+
+    // Settings and their sources are tab-separated on one line each
+    let settings = &discovery.settings;
+    if !settings.entries.is_empty() {
+        let mut fields = Vec::new();
+        for (key, value) in &settings.entries {
+            fields.push(clean(key)?);
+            fields.push(clean(value)?);
+        }
+        lines.push(format!("settings={}", fields.join("\t")));
+    }
+    if !settings.sources.is_empty() {
+        let sources: Option<Vec<&str>> = settings.sources.iter().map(|p| path_str(p)).collect();
+        lines.push(format!("config_sources={}", sources?.join("\t")));
+    }
+    Some(lines)
+}
+
+/// Parses a cache entry, rejecting entries written for a different start directory
+fn decode(start: &Path, content: &str) -> Option<(Discovery, Vec<(PathBuf, Signature)>)> {
+    let mut lines = content.lines();
+    if lines.next()? != CACHE_FORMAT_VERSION {
+        return None;
+    }
+
+    let mut fields = HashMap::new();
+    let mut validators = Vec::new();
+    for line in lines {
+        let (key, value) = line.split_once('=')?;
+        if key == "stat" {
+            let (path, signature) = value.split_once('\t')?;
+            validators.push((PathBuf::from(path), Signature::decode(signature)?));
+        } else {
+            fields.insert(key, value);
+        }
+    }
+
+    // Guard against hash collisions between start directories
+    if Path::new(fields.get("start")?) != start {
+        return None;
+    }
+
+    Some((decode_discovery(&fields)?, validators))
+}
+
+/// Rebuilds discovery results from fields written by `encode_discovery`
+pub(crate) fn decode_discovery(fields: &HashMap<&str, &str>) -> Option<Discovery> {
+    let bare_parent = fields.get("bare_parent").map(PathBuf::from);
+    let layout = match (bare_parent, fields.get("worktree")) {
+        (None, _) => Layout::Regular,
+        (Some(bare_parent), None) => Layout::BareParent(bare_parent),
+        (Some(bare_parent), Some(path)) => Layout::Worktree {
+            bare_parent,
+            path: path.to_string(),
+        },
+    };
+
+    let mut settings = RepoSettings::default();
+    if let Some(line) = fields.get("settings") {
+        let values: Vec<&str> = line.split('\t').collect();
+        for pair in values.chunks(2) {
+            let [key, value] = pair else {
+                return None;
+            };
+            settings.entries.push((key.to_string(), value.to_string()));
+        }
+    }
+    if let Some(line) = fields.get("config_sources") {
+        settings.sources = line.split('\t').map(PathBuf::from).collect();
+    }
+
+    let mut remotes = Vec::new();
+    if let Some(line) = fields.get("remotes") {
+        let values: Vec<&str> = line.split('\t').collect();
+        for pair in values.chunks(2) {
+            let [name, url] = pair else {
+                return None;
+            };
+            remotes.push((name.to_string(), url.to_string()));
+        }
+    }
+
+    let submodule = match fields.get("submodule") {
+        Some(line) => {
+            let values: Vec<&str> = line.split('\t').collect();
+            let [superproject, super_git_dir, path, head, recorded] = values[..] else {
+                return None;
+            };
+            let id = |value: &str| (!value.is_empty()).then(|| value.to_string());
+            Some(Submodule {
+                superproject: PathBuf::from(superproject),
+                super_git_dir: PathBuf::from(super_git_dir),
+                path: path.to_string(),
+                head: id(head),
+                recorded: id(recorded),
+            })
+        }
+        None => None,
+    };
+
+    let operation = match fields.get("operation") {
+        Some(label) => Some(Operation {
+            kind: Kind::parse(label)?,
+            progress: match fields.get("progress") {
+                Some(value) => {
+                    let (step, total) = value.split_once('/')?;
+                    Some((step.parse().ok()?, total.parse().ok()?))
+                }
+                None => None,
+            },
+            conflicts: fields.get("conflicts")?.parse().ok()?,
+        }),
+        None => None,
+    };
+
+    let jj = fields.get("jj").map(|change_id| Jj {
+        change_id: (!change_id.is_empty()).then(|| change_id.to_string()),
+        bookmarks: fields
+            .get("jj_bookmarks")
+            .map(|line| line.split('\t').map(str::to_string).collect())
+            .unwrap_or_default(),
+        op_head: fields.get("jj_op_head").map(PathBuf::from),
+    });
+
+    Some(Discovery {
+        work_dir: PathBuf::from(fields.get("work_dir")?),
+        git_dir: PathBuf::from(fields.get("git_dir")?),
+        common_dir: PathBuf::from(fields.get("common_dir")?),
+        head: Head {
+            name: fields.get("head")?.to_string(),
+            symref: fields.get("symref").map(|s| s.to_string()),
+        },
+        layout,
+        settings,
+        upstream: match fields.get("upstream") {
+            Some(value) => Some(Upstream::decode(value)?),
+            None => None,
+        },
+        last_fetch: match fields.get("last_fetch") {
+            Some(value) => Some(value.parse().ok()?),
+            None => None,
+        },
+        remotes,
+        submodule,
+        operation,
+        jj,
+        shape: Shape {
+            shallow: fields.contains_key("shallow"),
+            partial: fields.get("partial").map(|s| s.to_string()),
+            sparse: match fields.get("sparse") {
+                Some(value) => Some(Sparse::parse(value)?),
+                None => None,
+            },
+        },
+    })
+}
+
+/// Paths that can't be stored on a single line are not cached
+pub(crate) fn path_str(path: &Path) -> Option<&str> {
+    clean(path.to_str()?)
+}
+
+pub(crate) fn clean(value: &str) -> Option<&str> {
+    if value.contains(['\n', '\t']) {
+        None
+    } else {
+        Some(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_discovery() -> Discovery {
+        Discovery {
+            work_dir: PathBuf::from("/src/repo/feature"),
+            git_dir: PathBuf::from("/src/repo/.bare/worktrees/feature"),
+            common_dir: PathBuf::from("/src/repo/.bare"),
+            head: Head {
+                name: "feature".to_string(),
+                symref: Some("refs/heads/feature".to_string()),
+            },
+            layout: Layout::Worktree {
+                bare_parent: PathBuf::from("/src/repo"),
+                path: "feature".to_string(),
+            },
+            settings: RepoSettings {
+                entries: vec![
+                    ("status".to_string(), "off".to_string()),
+                    ("branchicon".to_string(), "b ".to_string()),
+                ],
+                sources: vec![PathBuf::from("/src/repo/.bare/config")],
+            },
+            upstream: Some(Upstream::Tracking(
+                "refs/remotes/origin/feature".to_string(),
+            )),
+            last_fetch: Some(1_700_000_000),
+            remotes: vec![(
+                "origin".to_string(),
+                "git@github.com:kyle/repo.git".to_string(),
+            )],
+            submodule: Some(Submodule {
+                superproject: PathBuf::from("/src/parent"),
+                super_git_dir: PathBuf::from("/src/parent/.git"),
+                path: "vendor/repo".to_string(),
+                head: Some("a".repeat(40)),
+                recorded: None,
+            }),
+            shape: Shape {
+                shallow: true,
+                partial: Some("blob:none".to_string()),
+                sparse: Some(Sparse::Cone),
+            },
+            operation: Some(Operation {
+                kind: Kind::Rebase,
+                progress: Some((2, 5)),
+                conflicts: 3,
+            }),
+            jj: Some(Jj {
+                change_id: Some("zyxwvuts".to_string()),
+                bookmarks: vec!["main".to_string(), "split??".to_string()],
+                op_head: Some(PathBuf::from("/src/repo/.jj/repo/op_heads/heads/0b0b")),
+            }),
+        }
+    }
+
+    #[test]
+    fn test_cache_entry_roundtrip() {
+        let start = Path::new("/src/repo/feature/src");
+        let discovery = sample_discovery();
+
+        let content = encode(start, &discovery).unwrap();
+        let (decoded, validators) = decode(start, &content).unwrap();
+
+        assert_eq!(decoded, discovery);
+        assert!(validators.contains(&(
+            PathBuf::from("/src/repo/feature/src/.git"),
+            Signature::Missing
+        )));
+        assert!(validators.contains(&(
+            PathBuf::from("/src/repo/.bare/refs/heads/feature"),
+            Signature::Missing
+        )));
+        assert!(validators.contains(&(
+            PathBuf::from("/src/repo/.bare/refs/remotes/origin/feature"),
+            Signature::Missing
+        )));
+    }
+
+    #[test]
+    fn test_cache_entry_rejects_other_start_dir() {
+        let discovery = sample_discovery();
+        let content = encode(Path::new("/src/repo/feature"), &discovery).unwrap();
+
+        assert!(decode(Path::new("/src/other"), &content).is_none());
+        assert!(decode(Path::new("/src/repo/feature"), "garbage\n").is_none());
+    }
+
+    #[test]
+    fn test_signature_encoding() {
+        for signature in [
+            Signature::Missing,
+            Signature::Dir { ino: 42 },
+            Signature::File {
+                ino: 7,
+                mtime_ns: 1_700_000_000_123_456_789,
+                len: 23,
+            },
+        ] {
+            assert_eq!(Signature::decode(&signature.encode()), Some(signature));
+        }
+    }
+}
@@ -0,0 +1,481 @@
+use crate::Error;
+use std::path::PathBuf;
+
+// ============================================================================
+// Command-Line Parsing
+// ============================================================================
+//
+// A small hand-written parser to keep the binary dependency-free. Each
+// subcommand declares the flags it accepts in COMMANDS; anything else is a
+// usage error rather than being silently ignored.
+
+/// What the invocation asks for
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Command {
+    /// Print the prompt segment (no subcommand)
+    Prompt,
+    Daemon,
+    DaemonStop,
+    Doctor,
+    Repair,
+    PruneMerged {
+        yes: bool,
+    },
+    Refresh,
+    Watch,
+    Fields,
+    Logs {
+        lines: Option<usize>,
+        clear: bool,
+    },
+    Bench {
+        iterations: Option<usize>,
+    },
+    /// Help for the top level or for one subcommand
+    Help(Option<&'static str>),
+    Version,
+}
+
+/// A parsed command line
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Cli {
+    pub(crate) command: Command,
+    /// Directory given with `--path`, where the command accepts one
+    pub(crate) path: Option<PathBuf>,
+    pub(crate) debug: bool,
+}
+
+/// A flag a subcommand accepts
+struct Flag {
+    name: &'static str,
+    /// Placeholder for the flag's value, or None for a switch
+    value: Option<&'static str>,
+    help: &'static str,
+}
+
+/// A subcommand and the flags it accepts
+struct Spec {
+    name: &'static str,
+    /// Positional arguments shown in the usage line
+    operands: &'static str,
+    summary: &'static str,
+    flags: &'static [Flag],
+}
+
+const PATH: Flag = Flag {
+    name: "--path",
+    value: Some("DIR"),
+    help: "Directory to inspect instead of the current one",
+};
+
+const DEBUG: Flag = Flag {
+    name: "--debug",
+    value: None,
+    help: "Print errors and phase timings to stderr",
+};
+
+const HELP: Flag = Flag {
+    name: "--help",
+    value: None,
+    help: "Show this help",
+};
+
+/// The prompt itself, used when no subcommand is given
+const PROMPT: Spec = Spec {
+    name: "",
+    operands: "",
+    summary: "Print the prompt segment for the current directory",
+    flags: &[
+        PATH,
+        DEBUG,
+        HELP,
+        Flag {
+            name: "--version",
+            value: None,
+            help: "Show the version",
+        },
+    ],
+};
+
+const COMMANDS: &[Spec] = &[
+    Spec {
+        name: "watch",
+        operands: "",
+        summary: "Print the prompt again whenever it changes (for status bars)",
+        flags: &[PATH, HELP],
+    },
+    Spec {
+        name: "daemon",
+        operands: "[stop]",
+        summary: "Serve prompts from a background daemon, or stop it",
+        flags: &[HELP],
+    },
+    Spec {
+        name: "fields",
+        operands: "",
+        summary: "Print what discovery found as key=value lines",
+        flags: &[PATH, HELP],
+    },
+    Spec {
+        name: "doctor",
+        operands: "",
+        summary: "Explain discovery step by step and suggest fixes",
+        flags: &[PATH, HELP],
+    },
+    Spec {
+        name: "repair",
+        operands: "",
+        summary: "Rewrite worktree pointers broken by moving directories",
+        flags: &[PATH, HELP],
+    },
+    Spec {
+        name: "prune-merged",
+        operands: "",
+        summary: "Remove worktrees whose branches are merged or gone upstream",
+        flags: &[
+            PATH,
+            Flag {
+                name: "--yes",
+                value: None,
+                help: "Remove without asking for confirmation",
+            },
+            HELP,
+        ],
+    },
+    Spec {
+        name: "logs",
+        operands: "",
+        summary: "Show or clear the error log",
+        flags: &[
+            Flag {
+                name: "-n",
+                value: Some("LINES"),
+                help: "Number of lines to show (default 20)",
+            },
+            Flag {
+                name: "--clear",
+                value: None,
+                help: "Remove the log instead of showing it",
+            },
+            HELP,
+        ],
+    },
+    Spec {
+        name: "bench",
+        operands: "",
+        summary: "Time the prompt in-process and report percentiles",
+        flags: &[
+            PATH,
+            Flag {
+                name: "-n",
+                value: Some("RUNS"),
+                help: "Number of runs (default 1000)",
+            },
+            HELP,
+        ],
+    },
+    Spec {
+        name: "refresh",
+        operands: "",
+        summary: "Recompute the cached status snapshot (run by async mode)",
+        flags: &[PATH, HELP],
+    },
+];
+
+/// Parses `args`, which include the program name
+pub(crate) fn parse(args: &[String]) -> Result<Cli, Error> {
+    let mut args = args.iter().skip(1).map(String::as_str).peekable();
+
+    let spec = match args.peek().copied() {
+        Some("help") => {
+            args.next();
+            let topic = match args.next() {
+                Some(name) => Some(find(name)?.name),
+                None => None,
+            };
+            if let Some(extra) = args.next() {
+                return Err(unexpected(extra, "help"));
+            }
+            return Ok(cli(Command::Help(topic)));
+        }
+        Some(name) if !name.starts_with('-') => {
+            args.next();
+            find(name)?
+        }
+        _ => &PROMPT,
+    };
+
+    let mut parsed = cli(match spec.name {
+        "watch" => Command::Watch,
+        "daemon" => Command::Daemon,
+        "fields" => Command::Fields,
+        "doctor" => Command::Doctor,
+        "repair" => Command::Repair,
+        "prune-merged" => Command::PruneMerged { yes: false },
+        "refresh" => Command::Refresh,
+        "logs" => Command::Logs {
+            lines: None,
+            clear: false,
+        },
+        "bench" => Command::Bench { iterations: None },
+        _ => Command::Prompt,
+    });
+
+    while let Some(arg) = args.next() {
+        // Accept both `--path DIR` and `--path=DIR`
+        let (name, inline) = match arg.split_once('=') {
+            Some((name, value)) if name.starts_with("--") => (name, Some(value)),
+            _ => (arg, None),
+        };
+        let name = match name {
+            "-h" => "--help",
+            "-y" => "--yes",
+            "-V" => "--version",
+            name => name,
+        };
+
+        if !name.starts_with('-') {
+            match (&parsed.command, name) {
+                (Command::Daemon, "stop") => parsed.command = Command::DaemonStop,
+                _ => return Err(unexpected(arg, spec.name)),
+            }
+            continue;
+        }
+
+        let Some(flag) = spec.flags.iter().find(|f| f.name == name) else {
+            return Err(Error::Usage(format!(
+                "unknown flag '{}'{}; see --help",
+                name,
+                context(spec.name)
+            )));
+        };
+        let value = match (flag.value, inline) {
+            (Some(_), Some(value)) => Some(value),
+            (Some(placeholder), None) => {
+                let missing = || Error::Usage(format!("{} needs a {} value", name, placeholder));
+                Some(args.next().ok_or_else(missing)?)
+            }
+            (None, Some(_)) => {
+                return Err(Error::Usage(format!("{} does not take a value", name)));
+            }
+            (None, None) => None,
+        };
+
+        match (name, value, &mut parsed.command) {
+            ("--help", _, _) => {
+                let topic = (!spec.name.is_empty()).then_some(spec.name);
+                return Ok(cli(Command::Help(topic)));
+            }
+            ("--version", _, _) => return Ok(cli(Command::Version)),
+            ("--debug", _, _) => parsed.debug = true,
+            ("--path", Some(value), _) => parsed.path = Some(PathBuf::from(value)),
+            ("-n", Some(value), Command::Logs { lines: count, .. })
+            | ("-n", Some(value), Command::Bench { iterations: count }) => {
+                let invalid = |_| Error::Usage(format!("-n needs a number, got '{}'", value));
+                *count = Some(value.parse().map_err(invalid)?);
+            }
+            ("--clear", _, Command::Logs { clear, .. }) => *clear = true,
+            ("--yes", _, Command::PruneMerged { yes }) => *yes = true,
+            _ => unreachable!("flag {} declared but not handled", name),
+        }
+    }
+
+    Ok(parsed)
+}
+
+fn cli(command: Command) -> Cli {
+    Cli {
+        command,
+        path: None,
+        debug: false,
+    }
+}
+
+fn find(name: &str) -> Result<&'static Spec, Error> {
+    COMMANDS
+        .iter()
+        .find(|spec| spec.name == name)
+        .ok_or_else(|| Error::Usage(format!("unknown command '{}'; see --help", name)))
+}
+
+fn unexpected(arg: &str, command: &str) -> Error {
+    Error::Usage(format!(
+        "unexpected argument '{}'{}; see --help",
+        arg,
+        context(command)
+    ))
+}
+
+fn context(command: &str) -> String {
+    if command.is_empty() {
+        String::new()
+    } else {
+        format!(" for '{}'", command)
+    }
+}
+
+/// Help text for the top level, or for one subcommand
+pub(crate) fn help(topic: Option<&str>) -> String {
+    let spec = topic
+        .and_then(|name| COMMANDS.iter().find(|spec| spec.name == name))
+        .unwrap_or(&PROMPT);
+
+    let mut out = String::new();
+    let name = env!("CARGO_PKG_NAME");
+    let usage = [name, spec.name, spec.operands, "[OPTIONS]"]
+        .iter()
+        .filter(|part| !part.is_empty())
+        .copied()
+        .collect::<Vec<_>>()
+        .join(" ");
+    out.push_str(&format!("{}\n\nUsage: {}\n", spec.summary, usage));
+
+    if spec.name.is_empty() {
+        out.push_str(&format!(
+            "       {} <COMMAND> [OPTIONS]\n\nCommands:\n",
+            name
+        ));
+        let width = COMMANDS
+            .iter()
+            .map(|command| command.name.len())
+            .chain(["help".len()])
+            .max()
+            .unwrap_or_default();
+        for command in COMMANDS {
+            out.push_str(&format!("  {:<width$} {}\n", command.name, command.summary));
+        }
+        out.push_str(&format!(
+            "  {:<width$} {}\n",
+            "help", "Show help for a command"
+        ));
+    }
+
+    out.push_str("\nOptions:\n");
+    for flag in spec.flags {
+        let label = match flag.value {
+            Some(value) => format!("{} {}", flag.name, value),
+            None => flag.name.to_string(),
+        };
+        out.push_str(&format!("  {:<14} {}\n", label, flag.help));
+    }
+    out
+}
+
+/// Text printed by `--version`
+pub(crate) fn version() -> String {
+    format!("{} {}\n", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_args(args: &[&str]) -> Result<Cli, Error> {
+        let args: Vec<String> = std::iter::once("git-worktree-prompt")
+            .chain(args.iter().copied())
+            .map(String::from)
+            .collect();
+        parse(&args)
+    }
+
+    fn command(args: &[&str]) -> Command {
+        parse_args(args).unwrap().command
+    }
+
+    #[test]
+    fn test_parse_subcommands_and_flags() {
+        assert_eq!(parse_args(&[]).unwrap(), cli(Command::Prompt));
+        assert!(parse_args(&["--debug"]).unwrap().debug);
+        assert_eq!(command(&["daemon", "stop"]), Command::DaemonStop);
+        assert_eq!(command(&["fields"]), Command::Fields);
+        assert_eq!(
+            command(&["bench", "-n", "50"]),
+            Command::Bench {
+                iterations: Some(50)
+            }
+        );
+        assert_eq!(
+            command(&["prune-merged", "-y"]),
+            Command::PruneMerged { yes: true }
+        );
+        assert_eq!(
+            command(&["logs", "-n", "5", "--clear"]),
+            Command::Logs {
+                lines: Some(5),
+                clear: true
+            }
+        );
+
+        for args in [&["watch", "--path", "/src"][..], &["watch", "--path=/src"]] {
+            let parsed = parse_args(args).unwrap();
+            assert_eq!(parsed.command, Command::Watch);
+            assert_eq!(parsed.path, Some(PathBuf::from("/src")));
+        }
+    }
+
+    #[test]
+    fn test_parse_help_and_version() {
+        assert_eq!(command(&["--help"]), Command::Help(None));
+        assert_eq!(command(&["-h"]), Command::Help(None));
+        assert_eq!(command(&["help"]), Command::Help(None));
+        assert_eq!(command(&["help", "doctor"]), Command::Help(Some("doctor")));
+        assert_eq!(
+            command(&["repair", "--help"]),
+            Command::Help(Some("repair"))
+        );
+        assert_eq!(command(&["--version"]), Command::Version);
+        assert_eq!(command(&["-V"]), Command::Version);
+
+        assert!(help(None).contains("Commands:"));
+        assert!(help(Some("logs")).contains("-n LINES"));
+        assert!(version().starts_with("git-worktree-prompt "));
+    }
+
+    #[test]
+    fn test_help_aligns_command_summaries() {
+        let help = help(None);
+        let commands: Vec<&str> = help
+            .lines()
+            .skip_while(|line| *line != "Commands:")
+            .skip(1)
+            .take_while(|line| !line.is_empty())
+            .collect();
+        assert_eq!(commands.len(), COMMANDS.len() + 1);
+        // Summaries start in one column, past the longest name (prune-merged)
+        let summary_column = |line: &str| {
+            let name_end = line[2..].find(' ').unwrap() + 2;
+            name_end + line[name_end..].find(|c| c != ' ').unwrap()
+        };
+        let column = summary_column(commands[0]);
+        assert!(commands.iter().all(|line| summary_column(line) == column));
+        assert!(column > "  prune-merged".len(), "{}", help);
+    }
+
+    #[test]
+    fn test_parse_rejects_typos() {
+        for (args, expected) in [
+            (&["--debgu"][..], "unknown flag '--debgu'"),
+            (&["wacth"], "unknown command 'wacth'"),
+            (&["watch", "--debug"], "unknown flag '--debug' for 'watch'"),
+            (
+                &["doctor", "extra"],
+                "unexpected argument 'extra' for 'doctor'",
+            ),
+            (&["--path"], "--path needs a DIR value"),
+            (&["logs", "-n", "many"], "-n needs a number, got 'many'"),
+            (&["--debug=yes"], "--debug does not take a value"),
+            (
+                &["daemon", "--version"],
+                "unknown flag '--version' for 'daemon'",
+            ),
+            (&["help", "nope"], "unknown command 'nope'"),
+        ] {
+            match parse_args(args) {
+                Err(Error::Usage(message)) => {
+                    assert!(message.contains(expected), "{:?}: {}", args, message)
+                }
+                other => panic!("{:?} should be a usage error, got {:?}", args, other),
+            }
+        }
+    }
+}
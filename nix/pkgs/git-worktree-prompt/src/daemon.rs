@@ -0,0 +1,690 @@
+use crate::budget::Segment;
+use crate::cache::{decode_discovery, encode_discovery, path_str};
+use crate::status::Status;
+use crate::{Discovery, Env, get_runtime_dir};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// ============================================================================
+// Daemon Client and Protocol
+// ============================================================================
+//
+// An optional `git-worktree-prompt daemon` keeps discovery results and status
+// warm per start directory and invalidates them through inotify. inotify is
+// not recursive, so status is only served as fresh for worktrees whose every
+// directory is watched (up to MAX_TREE_WATCHES); larger trees get their last
+// value marked stale. Prompts
+// connect to $XDG_RUNTIME_DIR/git-worktree-prompt/daemon.sock first; when no
+// daemon is listening the connect fails immediately and the normal
+// in-process path runs instead.
+//
+// Protocol (one request per connection, newline-terminated):
+//
+//   query\t<start dir>\t<0|1 want status>   ->  reply lines until EOF
+//   shutdown                                ->  daemon exits
+//
+// Replies start with DAEMON_PROTOCOL_VERSION, then `repo=0` outside a
+// repository or `repo=1` followed by discovery fields (same encoding as the
+// cache) and an optional `status=<ready|stale|skipped> ...` line. Errors are
+// reported as a single `error=<message>` line; clients treat any error or
+// malformed reply as "no daemon" and fall back.
+
+/// Environment variable that stops prompts from contacting the daemon when "0"
+pub(crate) const ENV_DAEMON: &str = "GIT_WORKTREE_PROMPT_DAEMON";
+
+/// Socket file name inside the runtime directory
+const SOCKET_FILE: &str = "daemon.sock";
+
+/// Version tag written as the first line of every reply
+const DAEMON_PROTOCOL_VERSION: &str = "git-worktree-prompt-daemon v1";
+
+/// A daemon answer for one start directory
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Reply {
+    /// Discovery results, or None outside a repository
+    pub(crate) discovery: Option<Discovery>,
+    /// Status segment when requested and the repo has a worktree
+    pub(crate) status: Option<Segment<Status>>,
+}
+
+/// Resolves the daemon socket path
+pub(crate) fn socket_path(env: &Env) -> Option<PathBuf> {
+    Some(get_runtime_dir(env)?.join(SOCKET_FILE))
+}
+
+/// Asks a running daemon about `start`; None when no daemon answers in time
+pub(crate) fn query(
+    start: &Path,
+    env: &Env,
+    want_status: bool,
+    timeout: Duration,
+) -> Option<Reply> {
+    if env.get(ENV_DAEMON) == Some("0") {
+        return None;
+    }
+
+    let mut stream = UnixStream::connect(socket_path(env)?).ok()?;
+    stream.set_read_timeout(Some(timeout)).ok()?;
+    stream.set_write_timeout(Some(timeout)).ok()?;
+
+    let request = format!(
+        "query\t{}\t{}\n",
+        path_str(start)?,
+        if want_status { 1 } else { 0 }
+    );
+    stream.write_all(request.as_bytes()).ok()?;
+
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply).ok()?;
+    decode_reply(&reply)
+}
+
+/// Asks a running daemon to exit
+pub(crate) fn shutdown(env: &Env) -> bool {
+    let Some(path) = socket_path(env) else {
+        return false;
+    };
+    match UnixStream::connect(path) {
+        Ok(mut stream) => stream.write_all(b"shutdown\n").is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// Serializes a reply
+fn encode_reply(reply: &Reply) -> Option<String> {
+    let mut lines = vec![DAEMON_PROTOCOL_VERSION.to_string()];
+    match &reply.discovery {
+        None => lines.push("repo=0".to_string()),
+        Some(discovery) => {
+            lines.push("repo=1".to_string());
+            lines.extend(encode_discovery(discovery)?);
+        }
+    }
+    if let Some(segment) = &reply.status {
+        lines.push(format!("status={}", encode_status(segment)));
+    }
+
+    let mut content = lines.join("\n");
+    content.push('\n');
+    Some(content)
+}
+
+/// Parses a reply; None for errors or anything unexpected
+fn decode_reply(content: &str) -> Option<Reply> {
+    let mut lines = content.lines();
+    if lines.next()? != DAEMON_PROTOCOL_VERSION {
+        return None;
+    }
+    let fields: HashMap<&str, &str> = lines.filter_map(|l| l.split_once('=')).collect();
+
+    let discovery = match *fields.get("repo")? {
+        "0" => None,
+        "1" => Some(decode_discovery(&fields)?),
+        _ => return None,
+    };
+    let status = match fields.get("status") {
+        Some(value) => Some(decode_status(value)?),
+        None => None,
+    };
+
+    Some(Reply { discovery, status })
+}
+
+/// Encodes a status segment as `<state> staged modified untracked ahead behind`
+fn encode_status(segment: &Segment<Status>) -> String {
+    let counts = |s: &Status| {
+        let optional = |n: Option<u32>| n.map_or("-".to_string(), |n| n.to_string());
+        format!(
+            "{} {} {} {} {}",
+            s.staged,
+            s.modified,
+            s.untracked,
+            optional(s.ahead),
+            optional(s.behind)
+        )
+    };
+    match segment {
+        Segment::Ready(status) => format!("ready {}", counts(status)),
+        Segment::Stale(status) => format!("stale {}", counts(status)),
+        Segment::Skipped => "skipped".to_string(),
+    }
+}
+
+fn decode_status(value: &str) -> Option<Segment<Status>> {
+    let fields: Vec<&str> = value.split(' ').collect();
+    let optional = |s: &str| -> Option<Option<u32>> {
+        match s {
+            "-" => Some(None),
+            n => Some(Some(n.parse().ok()?)),
+        }
+    };
+    let status = |f: &[&str]| -> Option<Status> {
+        match f {
+            [staged, modified, untracked, ahead, behind] => Some(Status {
+                staged: staged.parse().ok()?,
+                modified: modified.parse().ok()?,
+                untracked: untracked.parse().ok()?,
+                ahead: optional(ahead)?,
+                behind: optional(behind)?,
+            }),
+            _ => None,
+        }
+    };
+    match fields.split_first()? {
+        (&"ready", rest) => Some(Segment::Ready(status(rest)?)),
+        (&"stale", rest) => Some(Segment::Stale(status(rest)?)),
+        (&"skipped", []) => Some(Segment::Skipped),
+        _ => None,
+    }
+}
+
+/// Reads one request line from a client connection
+fn read_request(stream: &UnixStream) -> Option<String> {
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line).ok()?;
+    Some(line.trim_end_matches('\n').to_string())
+}
+
+// ============================================================================
+// Daemon Server (Linux)
+// ============================================================================
+
+#[cfg(target_os = "linux")]
+pub(crate) use server::serve;
+
+#[cfg(target_os = "linux")]
+mod server {
+    use super::*;
+    use crate::cache::validated_paths;
+    use crate::inotify::{self, Event, Inotify};
+    use crate::refresh::max_age;
+    use crate::status::run_git_status;
+    use crate::{Error, IoContext, discover};
+    use std::collections::HashSet;
+    use std::fs;
+    use std::io::ErrorKind;
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::net::UnixListener;
+    use std::sync::{Arc, Mutex, MutexGuard};
+    use std::thread;
+    use std::time::Instant;
+
+    /// Longest a daemon-side `git status` may run
+    const STATUS_TIMEOUT: Duration = Duration::from_secs(60);
+
+    /// How long a client may take to send its request
+    const REQUEST_TIMEOUT: Duration = Duration::from_secs(1);
+
+    /// Upper bound on directories watched per worktree for status invalidation
+    const MAX_TREE_WATCHES: usize = 4096;
+
+    /// Cached discovery result and the paths it depends on
+    struct Entry {
+        discovery: Discovery,
+        deps: HashSet<PathBuf>,
+    }
+
+    /// Last status for a work directory
+    #[derive(Default)]
+    struct StatusEntry {
+        status: Option<Status>,
+        fresh_until: Option<Instant>,
+        /// Bumped on every invalidation so in-flight results can tell they're outdated
+        generation: u64,
+        computing: bool,
+        git_dir: PathBuf,
+        /// The worktree's directories have been walked and watched once
+        walked: bool,
+        /// Every directory of the worktree is watched, so no edit goes unseen
+        covered: bool,
+        /// Directories watched on behalf of this worktree
+        tree_dirs: usize,
+    }
+
+    #[derive(Default)]
+    struct State {
+        entries: HashMap<PathBuf, Entry>,
+        statuses: HashMap<PathBuf, StatusEntry>,
+        /// Watched directory by watch descriptor
+        watches: HashMap<i32, PathBuf>,
+        watched: HashSet<PathBuf>,
+    }
+
+    impl State {
+        /// Watches `dir` unless already watched; false when inotify refuses
+        fn watch(&mut self, inotify: &Inotify, dir: &Path) -> bool {
+            if self.watched.contains(dir) {
+                return true;
+            }
+            match inotify.add_watch(dir, inotify::DIR_CHANGES) {
+                Ok(wd) => {
+                    self.watches.insert(wd, dir.to_path_buf());
+                    self.watched.insert(dir.to_path_buf());
+                    true
+                }
+                Err(_) => false,
+            }
+        }
+
+        /// Watches every directory in `dirs`; false when any is refused
+        fn watch_all(&mut self, inotify: &Inotify, dirs: &[PathBuf]) -> bool {
+            dirs.iter().all(|dir| self.watch(inotify, dir))
+        }
+
+        /// Extends a covered worktree's watches to a directory created or moved
+        /// into it, giving up coverage past MAX_TREE_WATCHES
+        fn extend_tree(&mut self, work_dir: &Path, root: &Path, inotify: &Inotify) {
+            let Some(used) = self.statuses.get(work_dir).map(|s| s.tree_dirs) else {
+                return;
+            };
+            let dirs = tree_dirs(root, MAX_TREE_WATCHES.saturating_sub(used));
+            let watched = dirs
+                .as_ref()
+                .is_some_and(|dirs| self.watch_all(inotify, dirs));
+            if let Some(status) = self.statuses.get_mut(work_dir) {
+                status.tree_dirs += dirs.map_or(0, |dirs| dirs.len());
+                status.covered &= watched;
+            }
+        }
+
+        /// Drops everything that depends on the path an event reports
+        fn invalidate(&mut self, event: &Event, inotify: &Inotify) {
+            if event.mask & inotify::IN_Q_OVERFLOW != 0 {
+                self.entries.clear();
+                for status in self.statuses.values_mut() {
+                    status.invalidate();
+                }
+                return;
+            }
+
+            let Some(dir) = self.watches.get(&event.wd).cloned() else {
+                return;
+            };
+
+            if event.mask & inotify::IN_IGNORED != 0 {
+                self.watches.remove(&event.wd);
+                self.watched.remove(&dir);
+            }
+
+            match &event.name {
+                // Lock files are always followed by a rename onto the real name
+                Some(name) if name.as_encoded_bytes().ends_with(b".lock") => {}
+                Some(name) => {
+                    let path = dir.join(name);
+                    self.entries.retain(|_, entry| !entry.deps.contains(&path));
+                    let new_dir = event.mask & inotify::IN_ISDIR != 0
+                        && event.mask & (inotify::IN_CREATE | inotify::IN_MOVED_TO) != 0;
+                    let mut grown = Vec::new();
+                    for (work_dir, status) in self.statuses.iter_mut() {
+                        if path.starts_with(work_dir) || path.starts_with(&status.git_dir) {
+                            status.invalidate();
+                        }
+                        if new_dir
+                            && status.covered
+                            && path.starts_with(work_dir)
+                            && !path.starts_with(&status.git_dir)
+                        {
+                            grown.push(work_dir.clone());
+                        }
+                    }
+                    for work_dir in grown {
+                        self.extend_tree(&work_dir, &path, inotify);
+                    }
+                }
+                // The directory itself went away or moved
+                None => {
+                    self.entries
+                        .retain(|_, entry| !entry.deps.iter().any(|dep| dep.starts_with(&dir)));
+                    for (work_dir, status) in self.statuses.iter_mut() {
+                        if work_dir.starts_with(&dir) || status.git_dir.starts_with(&dir) {
+                            status.invalidate();
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    impl StatusEntry {
+        fn invalidate(&mut self) {
+            self.fresh_until = None;
+            self.generation += 1;
+        }
+    }
+
+    type Shared = Arc<Mutex<State>>;
+
+    fn lock(state: &Shared) -> MutexGuard<'_, State> {
+        state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Runs the daemon until a `shutdown` request arrives
+    ///
+    /// Every prompt it answers sees the daemon's own environment, `env`.
+    pub(crate) fn serve(env: &Env) -> Result<(), Error> {
+        let path =
+            socket_path(env).ok_or_else(|| Error::command("daemon: XDG_RUNTIME_DIR is not set"))?;
+        let dir = path
+            .parent()
+            .ok_or_else(|| Error::command("daemon: invalid socket path"))?;
+        fs::create_dir_all(dir).with_path(dir)?;
+        fs::set_permissions(dir, fs::Permissions::from_mode(0o700)).with_path(dir)?;
+
+        if path.exists() {
+            if UnixStream::connect(&path).is_ok() {
+                return Err(Error::command("daemon: already running"));
+            }
+            // Left behind by a daemon that was killed
+            fs::remove_file(&path).with_path(&path)?;
+        }
+        let listener = UnixListener::bind(&path).with_path(&path)?;
+
+        let inotify = Arc::new(Inotify::new().with_path(Path::new("inotify"))?);
+        let state: Shared = Arc::default();
+        let env = Arc::new(env.clone());
+
+        {
+            let inotify = Arc::clone(&inotify);
+            let state = Arc::clone(&state);
+            thread::spawn(move || {
+                while let Ok(events) = inotify.read_events() {
+                    let mut state = lock(&state);
+                    for event in &events {
+                        state.invalidate(event, &inotify);
+                    }
+                }
+            });
+        }
+
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(Error::io(&path, e)),
+            };
+            let _ = stream.set_read_timeout(Some(REQUEST_TIMEOUT));
+
+            let Some(request) = read_request(&stream) else {
+                continue;
+            };
+            if request == "shutdown" {
+                break;
+            }
+            handle_query(&request, stream, &env, &state, &inotify);
+        }
+
+        let _ = fs::remove_file(&path);
+        Ok(())
+    }
+
+    /// Answers a `query` request
+    ///
+    /// Warm entries are answered right here. Misses are discovered on their
+    /// own thread, so one slow or network-mounted repository never holds up
+    /// the accept loop and every other shell's prompt with it.
+    fn handle_query(
+        request: &str,
+        mut stream: UnixStream,
+        env: &Arc<Env>,
+        state: &Shared,
+        inotify: &Arc<Inotify>,
+    ) {
+        let fields: Vec<&str> = request.split('\t').collect();
+        let ["query", start, want_status] = fields.as_slice() else {
+            let _ = stream.write_all(b"error=malformed request\n");
+            return;
+        };
+        let start = PathBuf::from(start);
+        let want_status = *want_status == "1";
+
+        let warm = lock(state)
+            .entries
+            .get(&start)
+            .map(|entry| entry.discovery.clone());
+        match warm {
+            Some(discovery) => respond(
+                &mut stream,
+                Ok(Some(discovery)),
+                want_status,
+                env,
+                state,
+                inotify,
+            ),
+            None => {
+                let env = Arc::clone(env);
+                let state = Arc::clone(state);
+                let inotify = Arc::clone(inotify);
+                thread::spawn(move || {
+                    let discovered = lookup(&start, &env, &state, &inotify);
+                    respond(&mut stream, discovered, want_status, &env, &state, &inotify);
+                });
+            }
+        }
+    }
+
+    /// Writes the reply for a discovery result, with status when requested
+    fn respond(
+        stream: &mut UnixStream,
+        discovered: Result<Option<Discovery>, Error>,
+        want_status: bool,
+        env: &Arc<Env>,
+        state: &Shared,
+        inotify: &Arc<Inotify>,
+    ) {
+        let response = match discovered {
+            Ok(discovery) => {
+                let status = match &discovery {
+                    Some(discovery) if want_status => {
+                        Some(status_for(discovery, env, state, inotify))
+                    }
+                    _ => None,
+                };
+                encode_reply(&Reply { discovery, status })
+            }
+            Err(e) => Some(format!("error={}\n", e)),
+        };
+
+        if let Some(response) = response {
+            let _ = stream.write_all(response.as_bytes());
+        }
+    }
+
+    /// Discovers `start` and watches its dependencies, keeping the result
+    /// only when every dependency directory is watched
+    fn lookup(
+        start: &Path,
+        env: &Env,
+        state: &Shared,
+        inotify: &Inotify,
+    ) -> Result<Option<Discovery>, Error> {
+        let Some(discovery) = discover(start, env)? else {
+            return Ok(None);
+        };
+
+        let deps: HashSet<PathBuf> = validated_paths(start, &discovery).into_iter().collect();
+        let mut state = lock(state);
+        let dirs: Vec<PathBuf> = deps
+            .iter()
+            .filter_map(|dep| dep.parent())
+            .map(Path::to_path_buf)
+            .collect();
+        if state.watch_all(inotify, &dirs) {
+            state.entries.insert(
+                start.to_path_buf(),
+                Entry {
+                    discovery: discovery.clone(),
+                    deps,
+                },
+            );
+        }
+        Ok(Some(discovery))
+    }
+
+    /// Returns the status segment, recomputing it in the background when stale
+    fn status_for(
+        discovery: &Discovery,
+        env: &Arc<Env>,
+        state: &Shared,
+        inotify: &Arc<Inotify>,
+    ) -> Segment<Status> {
+        let work_dir = discovery.work_dir.clone();
+        let mut guard = lock(state);
+        let entry = guard
+            .statuses
+            .entry(work_dir.clone())
+            .or_insert_with(|| StatusEntry {
+                git_dir: discovery.git_dir.clone(),
+                ..StatusEntry::default()
+            });
+
+        if entry
+            .fresh_until
+            .is_some_and(|until| Instant::now() < until)
+            && let Some(status) = &entry.status
+        {
+            // Without a watch on every directory an edit may have gone unseen
+            return if entry.covered {
+                Segment::Ready(status.clone())
+            } else {
+                Segment::Stale(status.clone())
+            };
+        }
+
+        if !entry.computing {
+            entry.computing = true;
+            let walk = !entry.walked;
+            entry.walked = true;
+            let generation = entry.generation;
+            let env = Arc::clone(env);
+            let state = Arc::clone(state);
+            let inotify = Arc::clone(inotify);
+            thread::spawn(move || {
+                if walk {
+                    // Watches go up before status runs, so later edits bump the generation
+                    watch_tree(&work_dir, &state, &inotify);
+                }
+                let output = run_git_status(&work_dir, Instant::now() + STATUS_TIMEOUT);
+                let mut guard = lock(&state);
+                if let Some(entry) = guard.statuses.get_mut(&work_dir) {
+                    entry.computing = false;
+                    if let Some(output) = output {
+                        entry.status = Some(crate::status::parse_porcelain_v2(&output));
+                        // A change during the run leaves the new value stale
+                        if entry.generation == generation {
+                            entry.fresh_until = Some(Instant::now() + max_age(&env));
+                        }
+                    }
+                }
+            });
+        }
+
+        match &entry.status {
+            Some(status) => Segment::Stale(status.clone()),
+            None => Segment::Skipped,
+        }
+    }
+
+    /// Watches every directory of a worktree, recording whether all are covered
+    fn watch_tree(work_dir: &Path, state: &Shared, inotify: &Inotify) {
+        // Walk without the lock; large trees take a while
+        let dirs = tree_dirs(work_dir, MAX_TREE_WATCHES);
+        let mut state = lock(state);
+        let covered = dirs
+            .as_ref()
+            .is_some_and(|dirs| state.watch_all(inotify, dirs));
+        if let Some(status) = state.statuses.get_mut(work_dir) {
+            status.tree_dirs = dirs.map_or(0, |dirs| dirs.len());
+            status.covered = covered;
+        }
+    }
+
+    /// `root` and the directories below it, skipping `.git`, or None when
+    /// there are more than `limit`
+    fn tree_dirs(root: &Path, limit: usize) -> Option<Vec<PathBuf>> {
+        let mut dirs = Vec::new();
+        let mut pending = vec![root.to_path_buf()];
+        while let Some(dir) = pending.pop() {
+            if dirs.len() >= limit {
+                return None;
+            }
+            if let Ok(entries) = fs::read_dir(&dir) {
+                pending.extend(
+                    entries
+                        .flatten()
+                        .filter(|e| e.file_name() != ".git")
+                        .filter(|e| e.file_type().is_ok_and(|t| t.is_dir()))
+                        .map(|e| e.path()),
+                );
+            }
+            dirs.push(dir);
+        }
+        Some(dirs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::RepoSettings;
+    use crate::shape::Shape;
+    use crate::{Head, Layout};
+
+    #[test]
+    fn test_reply_roundtrip() {
+        let replies = [
+            Reply {
+                discovery: None,
+                status: None,
+            },
+            Reply {
+                discovery: Some(Discovery {
+                    work_dir: PathBuf::from("/src/repo"),
+                    git_dir: PathBuf::from("/src/repo/.git"),
+                    common_dir: PathBuf::from("/src/repo/.git"),
+                    head: Head {
+                        name: "main".to_string(),
+                        symref: Some("refs/heads/main".to_string()),
+                    },
+                    layout: Layout::Regular,
+                    settings: RepoSettings::default(),
+                    upstream: None,
+                    last_fetch: None,
+                    remotes: Vec::new(),
+                    submodule: None,
+                    shape: Shape::default(),
+                    operation: None,
+                    jj: None,
+                }),
+                status: Some(Segment::Stale(Status {
+                    staged: 1,
+                    modified: 0,
+                    untracked: 2,
+                    ahead: Some(3),
+                    behind: None,
+                })),
+            },
+        ];
+
+        for reply in replies {
+            let encoded = encode_reply(&reply).unwrap();
+            assert_eq!(decode_reply(&encoded), Some(reply));
+        }
+    }
+
+    #[test]
+    fn test_decode_reply_rejects_errors() {
+        assert_eq!(decode_reply("error=daemon: boom\n"), None);
+        assert_eq!(decode_reply(""), None);
+        assert_eq!(
+            decode_status("skipped"),
+            Some(Segment::Skipped),
+            "Skipped status carries no counts"
+        );
+        assert_eq!(decode_status("ready 1 2"), None);
+    }
+}
@@ -0,0 +1,158 @@
+use crate::{Checkout, Error, IoContext, trace};
+use std::path::{Component, Path, PathBuf};
+
+// ============================================================================
+// Repository Discovery
+// ============================================================================
+//
+// The steps git itself takes to find a repository, without running git:
+// walk up to the nearest `.git`, follow a `.git` file to the worktree's own
+// git directory, read HEAD, and follow `commondir` to the shared directory.
+// A `.bare` directory next to the worktrees marks the bare-worktree layout.
+// The library's Repository API and the prompt's discovery both build on
+// these; every filesystem call goes through the trace wrappers.
+
+/// Maximum depth to search for .git directory when walking up the tree
+pub const MAX_GIT_SEARCH_DEPTH: usize = 32;
+
+/// Maximum depth to search for .bare directory
+pub const MAX_BARE_SEARCH_DEPTH: usize = 4;
+
+/// Finds the .git directory by walking up from the start directory
+/// Returns the path to .git (which might be a file or directory)
+pub fn find_git_dir(start: &Path) -> Result<Option<PathBuf>, Error> {
+    let mut current = start.to_path_buf();
+
+    for _ in 0..MAX_GIT_SEARCH_DEPTH {
+        let git_path = current.join(".git");
+        if trace::exists(&git_path) {
+            return Ok(Some(git_path));
+        }
+
+        // Move to parent directory
+        current = match current.parent() {
+            Some(parent) => parent.to_path_buf(),
+            None => return Ok(None), // Reached filesystem root
+        };
+    }
+
+    Ok(None) // Not found within max depth
+}
+
+/// Parses a .git file (used in worktrees) to get the real git directory
+/// File format: "gitdir: /path/to/real/.git\n"
+pub fn parse_gitdir_file(git_file: &Path) -> Result<PathBuf, Error> {
+    let content = trace::read_to_string(git_file).with_path(git_file)?;
+
+    // Parse "gitdir: /path" format
+    if let Some(path_str) = content.strip_prefix("gitdir: ") {
+        let path_str = path_str.trim();
+        let path = PathBuf::from(path_str);
+
+        // Handle relative paths (relative to .git file location)
+        if path.is_absolute() {
+            Ok(path)
+        } else {
+            let base = git_file.parent().ok_or_else(|| Error::InvalidGitdirFile {
+                path: git_file.to_path_buf(),
+            })?;
+            Ok(base.join(path))
+        }
+    } else {
+        Err(Error::InvalidGitdirFile {
+            path: git_file.to_path_buf(),
+        })
+    }
+}
+
+/// Reads and parses .git/HEAD into what the worktree has checked out
+pub fn read_checkout(git_dir: &Path) -> Result<Checkout, Error> {
+    let head_path = git_dir.join("HEAD");
+    let content = trace::read_to_string(&head_path).with_path(&head_path)?;
+    let content = content.trim();
+
+    // Case 1: Symbolic ref, a branch (ref: refs/heads/branch-name) or another ref
+    if let Some(ref_path) = content.strip_prefix("ref: ") {
+        return Ok(match ref_path.strip_prefix("refs/heads/") {
+            Some(branch) => Checkout::Branch(branch.to_string()),
+            None => Checkout::Ref(ref_path.to_string()),
+        });
+    }
+
+    // Case 2: Detached HEAD, a full SHA-1 or SHA-256 object id
+    if is_object_id(content) {
+        return Ok(Checkout::Detached(content.to_string()));
+    }
+
+    Err(Error::UnknownHeadFormat { path: head_path })
+}
+
+/// Resolves the shared git directory via the `commondir` file linked worktrees carry
+pub fn find_common_dir(git_dir: &Path) -> PathBuf {
+    match trace::read_to_string(&git_dir.join("commondir")) {
+        Ok(content) => {
+            let path = PathBuf::from(content.trim());
+            if path.is_absolute() {
+                path
+            } else {
+                git_dir.join(path)
+            }
+        }
+        Err(_) => git_dir.to_path_buf(),
+    }
+}
+
+/// Searches for .bare directory up to MAX_BARE_SEARCH_DEPTH levels
+/// Returns the directory containing .bare, or None for regular repos
+pub fn find_bare_parent(work_dir: &Path) -> Option<PathBuf> {
+    let mut current = work_dir;
+
+    for _ in 0..MAX_BARE_SEARCH_DEPTH {
+        let bare_path = current.join(".bare");
+        if trace::is_dir(&bare_path) {
+            return Some(current.to_path_buf());
+        }
+
+        current = current.parent()?;
+    }
+
+    None
+}
+
+/// Drops `.` and resolves `..` components without touching the filesystem
+///
+/// Linked worktrees name their common directory relative to the admin
+/// directory (`../..`), which would otherwise leak into the public paths.
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if normalized.file_name().is_some() => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// SHA-1 or SHA-256 object id in lowercase hex, as git writes them
+pub fn is_object_id(s: &str) -> bool {
+    matches!(s.len(), 40 | 64) && s.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(
+            normalize(Path::new("/src/repo/.git/worktrees/wt/../..")),
+            Path::new("/src/repo/.git")
+        );
+        assert_eq!(normalize(Path::new("./a/./b/../c")), Path::new("a/c"));
+        assert_eq!(normalize(Path::new("../a")), Path::new("../a"));
+    }
+}
@@ -0,0 +1,533 @@
+use crate::discovery::{MAX_GIT_SEARCH_DEPTH, normalize};
+use crate::gitconfig::Config;
+use crate::ignore;
+use crate::pointers::{self, Breakage, resolve};
+use crate::refs;
+use crate::settings;
+use crate::shape::{self, Shape, Sparse};
+use crate::{
+    Env, Error, find_bare_parent, find_common_dir, find_git_dir, get_relative_path,
+    parse_gitdir_file, read_git_head,
+};
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// ============================================================================
+// Doctor
+// ============================================================================
+//
+// `git-worktree-prompt doctor` replays discovery one step at a time, reporting
+// what each step found and why it stopped, then checks for the worktree
+// layout mistakes that make the prompt silently disappear. Every problem
+// comes with the command that fixes it, or with instructions where the fix
+// needs something only the user knows, such as where a worktree went.
+
+/// How a discovery step ended
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Outcome {
+    Ok,
+    /// Discovery ended here without anything being wrong
+    Stopped,
+    Failed,
+}
+
+/// One discovery step and what it found
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Step {
+    pub(crate) name: &'static str,
+    pub(crate) outcome: Outcome,
+    pub(crate) detail: String,
+}
+
+/// A misconfiguration and how to fix it
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Problem {
+    pub(crate) message: String,
+    pub(crate) fix: String,
+    /// False when `fix` describes steps rather than a command to run as printed
+    pub(crate) runnable: bool,
+}
+
+/// Findings for one start directory
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Report {
+    pub(crate) start: PathBuf,
+    pub(crate) steps: Vec<Step>,
+    pub(crate) problems: Vec<Problem>,
+}
+
+impl Report {
+    fn step(&mut self, name: &'static str, outcome: Outcome, detail: String) {
+        self.steps.push(Step {
+            name,
+            outcome,
+            detail,
+        });
+    }
+
+    fn problem(&mut self, message: String, fix: String) {
+        self.problems.push(Problem {
+            message,
+            fix,
+            runnable: true,
+        });
+    }
+
+    fn problem_with_steps(&mut self, message: String, steps: String) {
+        self.problems.push(Problem {
+            message,
+            fix: steps,
+            runnable: false,
+        });
+    }
+
+    /// Human-readable report
+    pub(crate) fn render(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "Discovery from {}", self.start.display());
+        for step in &self.steps {
+            let mark = match step.outcome {
+                Outcome::Ok => "✓",
+                Outcome::Stopped => "-",
+                Outcome::Failed => "✗",
+            };
+            let _ = writeln!(out, "  {} {:<18} {}", mark, step.name, step.detail);
+        }
+
+        out.push('\n');
+        if self.problems.is_empty() {
+            out.push_str("No problems found.\n");
+        } else {
+            out.push_str("Problems:\n");
+            for problem in &self.problems {
+                let _ = writeln!(out, "  ✗ {}", problem.message);
+                let label = if problem.runnable { "fix" } else { "to fix" };
+                let _ = writeln!(out, "    {}: {}", label, problem.fix);
+            }
+        }
+        out
+    }
+}
+
+/// Replays discovery from `start` and checks the repository layout
+pub(crate) fn diagnose(start: &Path, env: &Env) -> Report {
+    let mut report = Report {
+        start: start.to_path_buf(),
+        steps: Vec::new(),
+        problems: Vec::new(),
+    };
+
+    // 0. GIT_WORKTREE_PROMPT_IGNORE
+    if let Some(pattern) = ignore::matching_pattern(env, start) {
+        report.step(
+            "ignore",
+            Outcome::Stopped,
+            format!("matches {} pattern {}", ignore::ENV_IGNORE, pattern),
+        );
+        return report;
+    }
+
+    // 1. find_git_dir
+    let dot_git = match find_git_dir(start) {
+        Ok(Some(dot_git)) => {
+            let kind = if dot_git.is_file() {
+                "file"
+            } else {
+                "directory"
+            };
+            report.step(
+                "find_git_dir",
+                Outcome::Ok,
+                format!("{} ({})", dot_git.display(), kind),
+            );
+            dot_git
+        }
+        Ok(None) => {
+            report.step(
+                "find_git_dir",
+                Outcome::Stopped,
+                format!(
+                    "no .git within {} levels - not a git repository",
+                    MAX_GIT_SEARCH_DEPTH
+                ),
+            );
+            return report;
+        }
+        Err(e) => {
+            report.step("find_git_dir", Outcome::Failed, e.to_string());
+            return report;
+        }
+    };
+    let work_dir = dot_git.parent().unwrap_or(start).to_path_buf();
+    let bare_parent = find_bare_parent(&work_dir);
+
+    // 2. parse_gitdir_file
+    let git_dir = if dot_git.is_file() {
+        match parse_gitdir_file(&dot_git) {
+            Ok(git_dir) if git_dir.exists() => {
+                report.step(
+                    "parse_gitdir_file",
+                    Outcome::Ok,
+                    git_dir.display().to_string(),
+                );
+                git_dir
+            }
+            Ok(git_dir) => {
+                report.step(
+                    "parse_gitdir_file",
+                    Outcome::Failed,
+                    format!("points at missing {}", git_dir.display()),
+                );
+                report_broken_pointer(
+                    &mut report,
+                    format!(
+                        "{} points at {}, which does not exist",
+                        dot_git.display(),
+                        git_dir.display()
+                    ),
+                    &work_dir,
+                    bare_parent.as_deref(),
+                );
+                return report;
+            }
+            Err(e) => {
+                report.step("parse_gitdir_file", Outcome::Failed, e.to_string());
+                report_broken_pointer(
+                    &mut report,
+                    format!("{} is not a `gitdir: <path>` file", dot_git.display()),
+                    &work_dir,
+                    bare_parent.as_deref(),
+                );
+                return report;
+            }
+        }
+    } else {
+        report.step(
+            "parse_gitdir_file",
+            Outcome::Ok,
+            "skipped (.git is a directory)".to_string(),
+        );
+        dot_git.clone()
+    };
+    let common_dir = find_common_dir(&git_dir);
+
+    check_backlink(&mut report, &work_dir, &git_dir);
+    check_registered_worktrees(&mut report, &common_dir);
+    check_fetch_refspecs(&mut report, &common_dir);
+
+    // 3. Bare check
+    if !git_dir.join("HEAD").exists() {
+        report.step(
+            "bare check",
+            Outcome::Stopped,
+            format!("no HEAD in {} - bare repository", git_dir.display()),
+        );
+        return report;
+    }
+    report.step("bare check", Outcome::Ok, "HEAD present".to_string());
+
+    // 4. read_git_head
+    let head = match read_git_head(&git_dir) {
+        Ok(head) => {
+            let detail = match &head.symref {
+                Some(symref) => format!("{} ({})", head.name, symref),
+                None => format!("detached at {}", head.name),
+            };
+            report.step("read_git_head", Outcome::Ok, detail);
+            head
+        }
+        Err(e) => {
+            report.step("read_git_head", Outcome::Failed, e.to_string());
+            report_unreadable_head(&mut report, &git_dir.join("HEAD"), &common_dir, &e);
+            return report;
+        }
+    };
+
+    // 5. read_shape
+    match settings::load_config(&git_dir, &common_dir, &head, env) {
+        Ok(config) => {
+            let shape = shape::read(&config, &git_dir, &common_dir);
+            report.step("read_shape", Outcome::Ok, describe_shape(&shape, &git_dir));
+        }
+        Err(e) => report.step("read_shape", Outcome::Failed, e.to_string()),
+    }
+
+    // 6. find_bare_parent
+    let detail = match &bare_parent {
+        Some(bare_parent) => match get_relative_path(bare_parent, &work_dir) {
+            Ok(path) if path.is_empty() || path == "." => {
+                format!("{} (bare parent itself)", bare_parent.display())
+            }
+            Ok(path) => format!("{} (worktree {})", bare_parent.display(), path),
+            Err(e) => e.to_string(),
+        },
+        None => "none (regular repository)".to_string(),
+    };
+    report.step("find_bare_parent", Outcome::Ok, detail);
+
+    report
+}
+
+/// Lists what a reduced clone leaves out, with the active cones in cone mode
+fn describe_shape(shape: &Shape, git_dir: &Path) -> String {
+    let mut parts = Vec::new();
+    if shape.shallow {
+        parts.push("shallow".to_string());
+    }
+    match shape.partial.as_deref() {
+        Some("") => parts.push("partial".to_string()),
+        Some(filter) => parts.push(format!("partial ({})", filter)),
+        None => {}
+    }
+    match shape.sparse {
+        Some(Sparse::Cone) => {
+            let patterns =
+                fs::read_to_string(git_dir.join("info/sparse-checkout")).unwrap_or_default();
+            let dirs = shape::cone_dirs(&patterns);
+            if dirs.is_empty() {
+                parts.push("sparse cone: root files only".to_string());
+            } else {
+                parts.push(format!("sparse cone: {}", dirs.join(", ")));
+            }
+        }
+        Some(Sparse::Patterns) => parts.push("sparse (patterns)".to_string()),
+        None => {}
+    }
+    if parts.is_empty() {
+        "complete clone".to_string()
+    } else {
+        parts.join(", ")
+    }
+}
+
+/// A linked worktree's admin dir must point back at this worktree's .git file
+fn check_backlink(report: &mut Report, work_dir: &Path, git_dir: &Path) {
+    if let Some(Breakage::StaleBackPointer {
+        admin_dir,
+        points_at,
+    }) = pointers::check(&work_dir.join(".git"), git_dir)
+    {
+        report.problem(
+            format!(
+                "{} points at {}, not this worktree (was it moved?)",
+                admin_dir.join("gitdir").display(),
+                points_at.display()
+            ),
+            format!("git -C {} worktree repair", shell_quote(work_dir)),
+        );
+    }
+}
+
+/// Every registered worktree should still exist where the admin dir says it is
+fn check_registered_worktrees(report: &mut Report, common_dir: &Path) {
+    let Ok(entries) = fs::read_dir(common_dir.join("worktrees")) else {
+        return;
+    };
+
+    let mut admin_dirs: Vec<PathBuf> = entries.flatten().map(|e| e.path()).collect();
+    admin_dirs.sort();
+    for admin_dir in admin_dirs {
+        let Ok(content) = fs::read_to_string(admin_dir.join("gitdir")) else {
+            continue;
+        };
+        let target = resolve(&admin_dir, content.trim());
+        if target.exists() {
+            continue;
+        }
+
+        let name = admin_dir
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let work_dir = target.parent().unwrap_or(&target);
+        let main = main_worktree(common_dir);
+        report.problem_with_steps(
+            format!(
+                "worktree '{}' is registered at {}, which no longer exists",
+                name,
+                work_dir.display()
+            ),
+            format!(
+                "if it was moved, run `git -C {} worktree repair` with its new path; \
+                 if it was deleted, run `git -C {} worktree prune`",
+                shell_quote(&main),
+                shell_quote(&main)
+            ),
+        );
+    }
+}
+
+/// `git clone --bare` leaves remotes without a fetch refspec, so fetch never updates them
+fn check_fetch_refspecs(report: &mut Report, common_dir: &Path) {
+    let path = common_dir.join("config");
+    let config = match Config::load(&path) {
+        Ok(config) => config,
+        Err(e) => {
+            report.problem(
+                e.to_string(),
+                format!("${{EDITOR:-vi}} {}", shell_quote(&path)),
+            );
+            return;
+        }
+    };
+    if config.get_bool("core.bare") != Some(true) {
+        return;
+    }
+
+    for remote in config.subsections("remote") {
+        let url = config.get(&format!("remote.{}.url", remote));
+        let fetch = config.get_all(&format!("remote.{}.fetch", remote));
+        if url.is_none() || !fetch.is_empty() {
+            continue;
+        }
+        report.problem(
+            format!(
+                "bare repository {} has no fetch refspec for remote '{}'; `git fetch` won't update {}/* branches",
+                common_dir.display(),
+                remote,
+                remote
+            ),
+            format!(
+                "git -C {} config remote.{}.fetch '+refs/heads/*:refs/remotes/{}/*'",
+                shell_quote(common_dir),
+                remote,
+                remote
+            ),
+        );
+    }
+}
+
+/// Reports a broken `.git` pointer with the `git worktree repair` that fixes it
+///
+/// Outside a `.bare` layout a pointer that leads nowhere doesn't say where
+/// the main worktree is, so the user is told where to run it instead.
+fn report_broken_pointer(
+    report: &mut Report,
+    message: String,
+    work_dir: &Path,
+    bare_parent: Option<&Path>,
+) {
+    match bare_parent {
+        Some(bare_parent) => report.problem(
+            message,
+            format!(
+                "git -C {} worktree repair {}",
+                shell_quote(&bare_parent.join(".bare")),
+                shell_quote(work_dir)
+            ),
+        ),
+        None => report.problem_with_steps(
+            message,
+            format!(
+                "run `git worktree repair {}` in the main worktree",
+                shell_quote(work_dir)
+            ),
+        ),
+    }
+}
+
+/// Reports an unreadable HEAD with a rewrite naming a branch that exists
+///
+/// git refuses to run in a repository whose HEAD is invalid, so not even
+/// `git symbolic-ref` can repair it; the file has to be written directly.
+fn report_unreadable_head(report: &mut Report, head: &Path, common_dir: &Path, error: &Error) {
+    let branches: Vec<String> = refs::branches(common_dir)
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect();
+    let message = format!("{} is unreadable: {}", head.display(), error);
+    let preferred = ["main", "master"]
+        .into_iter()
+        .find(|name| branches.iter().any(|branch| branch == name))
+        .or(branches.first().map(String::as_str));
+    match preferred {
+        Some(branch) => {
+            let others: Vec<&str> = branches
+                .iter()
+                .map(String::as_str)
+                .filter(|name| *name != branch)
+                .collect();
+            let message = if others.is_empty() {
+                message
+            } else {
+                format!("{} (other branches: {})", message, others.join(", "))
+            };
+            report.problem(
+                message,
+                format!(
+                    "printf 'ref: refs/heads/%s\\n' {} > {}",
+                    shell_quote(Path::new(branch)),
+                    shell_quote(head)
+                ),
+            );
+        }
+        None => report.problem_with_steps(
+            message,
+            format!(
+                "the repository has no branches; write the full id of the commit to check out into {}",
+                head.display()
+            ),
+        ),
+    }
+}
+
+/// Directory worktree commands run in: the main worktree, or the bare repository
+fn main_worktree(common_dir: &Path) -> PathBuf {
+    let common_dir = normalize(common_dir);
+    match common_dir.file_name() {
+        Some(name) if name == ".git" => common_dir
+            .parent()
+            .map_or_else(|| common_dir.clone(), Path::to_path_buf),
+        _ => common_dir,
+    }
+}
+
+/// Quotes a path for copy-pasting into a POSIX shell
+pub(crate) fn shell_quote(path: &Path) -> String {
+    let s = path.to_string_lossy();
+    if s.chars()
+        .all(|c| c.is_ascii_alphanumeric() || "/._-+:@%".contains(c))
+    {
+        s.to_string()
+    } else {
+        format!("'{}'", s.replace('\'', r"'\''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(shell_quote(Path::new("/src/repo")), "/src/repo");
+        assert_eq!(shell_quote(Path::new("/src/my repo")), "'/src/my repo'");
+        assert_eq!(shell_quote(Path::new("/src/it's")), r"'/src/it'\''s'");
+    }
+
+    #[test]
+    fn test_render_lists_steps_and_fixes() {
+        let report = Report {
+            start: PathBuf::from("/src/repo"),
+            steps: vec![Step {
+                name: "find_git_dir",
+                outcome: Outcome::Failed,
+                detail: "boom".to_string(),
+            }],
+            problems: vec![Problem {
+                message: "broken".to_string(),
+                fix: "git fix-it".to_string(),
+                runnable: true,
+            }],
+        };
+
+        let rendered = report.render();
+        assert!(rendered.contains("✗ find_git_dir"));
+        assert!(rendered.contains("  ✗ broken\n    fix: git fix-it\n"));
+
+        let mut report = report;
+        report.problems[0].runnable = false;
+        assert!(report.render().contains("    to fix: git fix-it\n"));
+    }
+}
@@ -0,0 +1,152 @@
+use crate::settings::RepoSettings;
+use crate::{Env, normalize_path};
+
+// ============================================================================
+// Worktree/Branch Equivalence
+// ============================================================================
+//
+// A worktree's branch is hidden when the directory is "the same name" as the
+// branch. By default that means `normalize_path(dir) == branch`; these rules
+// widen it, each from a `[worktree-prompt]` key or its environment variable:
+//
+//   stripPrefixes  GIT_WORKTREE_PROMPT_STRIP_PREFIXES  `kyle/ feature/`
+//   ignoreCase     GIT_WORKTREE_PROMPT_IGNORE_CASE     `true`
+//   separators     GIT_WORKTREE_PROMPT_SEPARATORS      `_.` (also mapped to `-`)
+//   ticketMatch    GIT_WORKTREE_PROMPT_TICKET_MATCH    `true`
+//
+// Prefixes are stripped from both names, at most one each. A ticket match
+// treats the names as equal when both contain the same uppercase ticket ID
+// such as `DEV-123`.
+
+pub(crate) const ENV_STRIP_PREFIXES: &str = "GIT_WORKTREE_PROMPT_STRIP_PREFIXES";
+pub(crate) const ENV_IGNORE_CASE: &str = "GIT_WORKTREE_PROMPT_IGNORE_CASE";
+pub(crate) const ENV_SEPARATORS: &str = "GIT_WORKTREE_PROMPT_SEPARATORS";
+pub(crate) const ENV_TICKET_MATCH: &str = "GIT_WORKTREE_PROMPT_TICKET_MATCH";
+
+/// Rules deciding whether a worktree directory names its branch
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct Equivalence {
+    pub(crate) strip_prefixes: Vec<String>,
+    pub(crate) ignore_case: bool,
+    /// Characters mapped to `-` in both names, on top of `/` in directory names
+    pub(crate) separators: String,
+    pub(crate) ticket_match: bool,
+}
+
+impl Equivalence {
+    /// Reads each rule from the repository settings, then the environment
+    pub(crate) fn resolve(settings: &RepoSettings, env: &Env) -> Equivalence {
+        let setting = |key: &str, var: &str| settings.get_or_env(key, env, var).unwrap_or_default();
+        let flag = |key: &str, var: &str| {
+            matches!(
+                setting(key, var).to_lowercase().as_str(),
+                "1" | "true" | "yes" | "on"
+            )
+        };
+        Equivalence {
+            strip_prefixes: setting("stripPrefixes", ENV_STRIP_PREFIXES)
+                .split_whitespace()
+                .map(str::to_string)
+                .collect(),
+            ignore_case: flag("ignoreCase", ENV_IGNORE_CASE),
+            separators: setting("separators", ENV_SEPARATORS).to_string(),
+            ticket_match: flag("ticketMatch", ENV_TICKET_MATCH),
+        }
+    }
+
+    /// True when `worktree_path` names `branch` under these rules
+    pub(crate) fn matches(&self, worktree_path: &str, branch: &str) -> bool {
+        let dir = normalize_path(worktree_path);
+        if self.canonical(&dir) == self.canonical(branch) {
+            return true;
+        }
+        self.ticket_match && {
+            let dir_tickets = tickets(&dir);
+            tickets(branch)
+                .iter()
+                .any(|ticket| dir_tickets.contains(ticket))
+        }
+    }
+
+    fn canonical(&self, name: &str) -> String {
+        let mut name = name;
+        for prefix in &self.strip_prefixes {
+            // `kyle/` also strips the normalized directory form `kyle-`
+            let normalized = prefix.replace('/', "-");
+            if let Some(rest) = name
+                .strip_prefix(prefix.as_str())
+                .or_else(|| name.strip_prefix(normalized.as_str()))
+                && !rest.is_empty()
+            {
+                name = rest;
+                break;
+            }
+        }
+
+        let name: String = name
+            .chars()
+            .map(|c| if self.separators.contains(c) { '-' } else { c })
+            .collect();
+        if self.ignore_case {
+            name.to_lowercase()
+        } else {
+            name
+        }
+    }
+}
+
+/// Jira-style ticket IDs in `name`: an uppercase key of two or more letters
+/// or digits, `-`, then digits (`DEV-123`), standing apart from other words
+fn tickets(name: &str) -> Vec<&str> {
+    let bytes = name.as_bytes();
+    let mut found = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let boundary = i == 0 || !bytes[i - 1].is_ascii_alphanumeric();
+        let key_len = bytes[i..]
+            .iter()
+            .take_while(|b| b.is_ascii_uppercase() || b.is_ascii_digit())
+            .count();
+        let key_end = i + key_len;
+        if boundary
+            && bytes[i].is_ascii_uppercase()
+            && key_len >= 2
+            && bytes.get(key_end) == Some(&b'-')
+        {
+            let digits = bytes[key_end + 1..]
+                .iter()
+                .take_while(|b| b.is_ascii_digit())
+                .count();
+            let end = key_end + 1 + digits;
+            if digits > 0 && bytes.get(end).is_none_or(|b| !b.is_ascii_alphanumeric()) {
+                found.push(&name[i..end]);
+                i = end;
+                continue;
+            }
+        }
+        i += 1;
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tickets() {
+        for (name, expected) in [
+            ("DEV-123", &["DEV-123"][..]),
+            ("kyle/DEV-123-fix-thing", &["DEV-123"]),
+            ("AB2-7 and CD-8", &["AB2-7", "CD-8"]),
+            ("release-1.0", &[]),
+            ("dev-123", &[]),
+            ("DEV-12a", &[]),
+            ("xDEV-1", &[]),
+            ("V2-3", &["V2-3"]),
+            ("feature", &[]),
+        ] {
+            assert_eq!(tickets(name), expected, "{}", name);
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use crate::pointers::Breakage;
+use std::error::Error as StdError;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// ============================================================================
+// Error Handling
+// ============================================================================
+
+/// Everything that can go wrong, with the path it concerns
+///
+/// Discovery only fails with `Io`, `InvalidGitdirFile`, `UnknownHeadFormat`
+/// and `BrokenWorktree`; the other variants come from the prompt binary.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// A filesystem operation on `path` failed
+    Io { path: PathBuf, source: io::Error },
+    /// A `.git` file that is not `gitdir: <path>`
+    InvalidGitdirFile { path: PathBuf },
+    /// A HEAD that is neither a symbolic ref nor a commit hash
+    UnknownHeadFormat { path: PathBuf },
+    /// A work directory that is not below its `.bare` parent
+    RelativePath { base: PathBuf, path: PathBuf },
+    /// A git config file git itself would reject
+    ConfigParse {
+        path: PathBuf,
+        line: usize,
+        reason: String,
+    },
+    /// Worktree pointers left dangling by moving a worktree or its `.bare` parent
+    BrokenWorktree(Breakage),
+    /// Invalid command-line arguments
+    Usage(String),
+    /// A subcommand (daemon, refresh, repair, watch) could not do its job
+    Command(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            Error::InvalidGitdirFile { path } => write!(
+                f,
+                "{}: invalid .git file format (expected `gitdir: <path>`)",
+                path.display()
+            ),
+            Error::UnknownHeadFormat { path } => {
+                write!(f, "{}: unknown HEAD format", path.display())
+            }
+            Error::RelativePath { base, path } => write!(
+                f,
+                "{} is not below its .bare parent {}",
+                path.display(),
+                base.display()
+            ),
+            Error::ConfigParse { path, line, reason } => {
+                write!(f, "{}:{}: {}", path.display(), line, reason)
+            }
+            Error::BrokenWorktree(breakage) => write!(f, "{}", breakage),
+            Error::Usage(message) | Error::Command(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl Error {
+    #[doc(hidden)]
+    pub fn command(message: &str) -> Self {
+        Error::Command(message.to_string())
+    }
+
+    /// Short category written to error.log
+    #[doc(hidden)]
+    pub fn category(&self) -> &'static str {
+        match self {
+            Error::Io { .. } => "io",
+            Error::InvalidGitdirFile { .. } => "gitdir",
+            Error::UnknownHeadFormat { .. } => "head",
+            Error::RelativePath { .. } => "path",
+            Error::ConfigParse { .. } => "config",
+            Error::BrokenWorktree(_) => "worktree",
+            Error::Usage(_) => "usage",
+            Error::Command(_) => "command",
+        }
+    }
+
+    /// Process exit code, documented in the README's Troubleshooting section
+    #[doc(hidden)]
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Command(_) => 1,
+            Error::Usage(_) => 2,
+            Error::Io { .. } => 3,
+            Error::InvalidGitdirFile { .. } => 4,
+            Error::UnknownHeadFormat { .. } => 5,
+            Error::RelativePath { .. } => 6,
+            Error::ConfigParse { .. } => 7,
+            Error::BrokenWorktree(_) => 8,
+        }
+    }
+
+    #[doc(hidden)]
+    pub fn io(path: &Path, source: io::Error) -> Self {
+        Error::Io {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+}
+
+/// Attaches the path being operated on to `io::Error`s
+pub trait IoContext<T> {
+    fn with_path(self, path: &Path) -> Result<T, Error>;
+}
+
+impl<T> IoContext<T> for io::Result<T> {
+    fn with_path(self, path: &Path) -> Result<T, Error> {
+        self.map_err(|e| Error::io(path, e))
+    }
+}
@@ -0,0 +1,336 @@
+use crate::{ERROR_LOG_FILE, Error, IoContext};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{Duration, SystemTime};
+
+// ============================================================================
+// Error Log
+// ============================================================================
+//
+// error.log lines look like
+//
+//   2026-10-19T08:15:02Z [head] cwd=/src/project /src/project/.git/HEAD: unknown HEAD format
+//
+// A broken repo fails the same way on every prompt, so an error identical to
+// the previous one (same category, directory and message) within
+// DEDUP_WINDOW is only counted; the count is written as a single
+// "repeated N more times" line once a different error arrives, the window
+// passes, or the log is read. The file is rotated to error.log.1 when it
+// reaches MAX_LOG_BYTES.
+
+/// Size at which error.log is rotated to error.log.1
+const MAX_LOG_BYTES: u64 = 256 * 1024;
+
+/// Identical errors within this window are counted instead of written
+const DEDUP_WINDOW: Duration = Duration::from_secs(300);
+
+/// Suffix of the rotated log file
+const ROTATED_SUFFIX: &str = "1";
+
+/// Sidecar file remembering the last error and how often it repeated
+const DEDUP_STATE_FILE: &str = "error.log.last";
+
+/// One error occurrence as written to the log
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Entry {
+    pub(crate) category: String,
+    pub(crate) cwd: String,
+    pub(crate) message: String,
+}
+
+impl Entry {
+    pub(crate) fn new(error: &Error, cwd: &Path) -> Entry {
+        Entry {
+            category: error.category().to_string(),
+            cwd: cwd.display().to_string(),
+            message: error.to_string().replace('\n', " "),
+        }
+    }
+
+    fn line(&self, at: SystemTime) -> String {
+        format!(
+            "{} [{}] cwd={} {}\n",
+            rfc3339(at),
+            self.category,
+            self.cwd,
+            self.message
+        )
+    }
+}
+
+/// The last logged entry, when it was first written and how often it recurred since
+#[derive(Debug, Clone, PartialEq)]
+struct DedupState {
+    first_seen: u64,
+    repeats: u64,
+    entry: Entry,
+}
+
+impl DedupState {
+    fn encode(&self) -> String {
+        format!(
+            "{}\t{}\t{}\t{}\t{}\n",
+            self.first_seen, self.repeats, self.entry.category, self.entry.cwd, self.entry.message
+        )
+    }
+
+    fn decode(content: &str) -> Option<DedupState> {
+        let mut fields = content.trim_end_matches('\n').splitn(5, '\t');
+        Some(DedupState {
+            first_seen: fields.next()?.parse().ok()?,
+            repeats: fields.next()?.parse().ok()?,
+            entry: Entry {
+                category: fields.next()?.to_string(),
+                cwd: fields.next()?.to_string(),
+                message: fields.next()?.to_string(),
+            },
+        })
+    }
+
+    /// Line summarizing suppressed repeats, if there were any
+    fn summary(&self, at: SystemTime) -> Option<String> {
+        (self.repeats > 0).then(|| {
+            format!(
+                "{} [{}] cwd={} repeated {} more times: {}\n",
+                rfc3339(at),
+                self.entry.category,
+                self.entry.cwd,
+                self.repeats,
+                self.entry.message
+            )
+        })
+    }
+}
+
+/// Appends `entry` to the log in `dir`, deduplicating and rotating as needed
+pub(crate) fn append(dir: &Path, entry: &Entry, now: SystemTime) {
+    let _ = fs::create_dir_all(dir);
+    let state_path = dir.join(DEDUP_STATE_FILE);
+    let state = fs::read_to_string(&state_path)
+        .ok()
+        .and_then(|content| DedupState::decode(&content));
+    let now_secs = unix_secs(now);
+
+    let mut pending = String::new();
+    if let Some(mut state) = state {
+        let within_window = now_secs.saturating_sub(state.first_seen) < DEDUP_WINDOW.as_secs();
+        if state.entry == *entry && within_window {
+            state.repeats += 1;
+            write_state(&state_path, &state);
+            return;
+        }
+        if let Some(summary) = state.summary(now) {
+            pending.push_str(&summary);
+        }
+    }
+    pending.push_str(&entry.line(now));
+
+    write_lines(dir, &pending);
+    write_state(
+        &state_path,
+        &DedupState {
+            first_seen: now_secs,
+            repeats: 0,
+            entry: entry.clone(),
+        },
+    );
+}
+
+/// Writes out any pending repeat count so readers see an up-to-date log
+pub(crate) fn flush(dir: &Path, now: SystemTime) {
+    let state_path = dir.join(DEDUP_STATE_FILE);
+    let Some(mut state) = fs::read_to_string(&state_path)
+        .ok()
+        .and_then(|content| DedupState::decode(&content))
+    else {
+        return;
+    };
+    if let Some(summary) = state.summary(now) {
+        write_lines(dir, &summary);
+        state.repeats = 0;
+        state.first_seen = unix_secs(now);
+        write_state(&state_path, &state);
+    }
+}
+
+/// Last `count` lines across the rotated and current log, oldest first
+pub(crate) fn tail(dir: &Path, count: usize) -> Vec<String> {
+    let mut lines: Vec<String> = log_files(dir)
+        .iter()
+        .rev()
+        .filter_map(|path| fs::read_to_string(path).ok())
+        .flat_map(|content| content.lines().map(str::to_string).collect::<Vec<_>>())
+        .collect();
+    let skip = lines.len().saturating_sub(count);
+    lines.drain(..skip);
+    lines
+}
+
+/// Removes the log, its rotated copy and the dedup state
+pub(crate) fn clear(dir: &Path) -> Result<(), Error> {
+    let [log, rotated] = log_files(dir);
+    for path in [log, rotated, dir.join(DEDUP_STATE_FILE)] {
+        match fs::remove_file(&path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                return Err(e).with_path(&path);
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Current log followed by its rotated copy
+fn log_files(dir: &Path) -> [PathBuf; 2] {
+    let log = dir.join(ERROR_LOG_FILE);
+    let rotated = log.with_extension(format!("log.{}", ROTATED_SUFFIX));
+    [log, rotated]
+}
+
+fn write_lines(dir: &Path, lines: &str) {
+    let [log, rotated] = log_files(dir);
+    if fs::metadata(&log).is_ok_and(|meta| meta.len() >= MAX_LOG_BYTES) {
+        let _ = fs::rename(&log, &rotated);
+    }
+    if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(&log) {
+        let _ = file.write_all(lines.as_bytes());
+    }
+}
+
+fn write_state(path: &Path, state: &DedupState) {
+    let tmp = path.with_extension(format!("last.{}.tmp", process::id()));
+    if fs::write(&tmp, state.encode()).is_ok() {
+        let _ = fs::rename(&tmp, path);
+    }
+}
+
+fn unix_secs(at: SystemTime) -> u64 {
+    at.duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Formats a time as an RFC 3339 UTC timestamp, e.g. `2026-10-19T08:15:02Z`
+pub(crate) fn rfc3339(at: SystemTime) -> String {
+    let secs = unix_secs(at);
+    let (days, rem) = (secs / 86_400, secs % 86_400);
+
+    // Civil-from-days (Howard Hinnant's algorithm), valid for any date after 1970
+    let z = days as i64 + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3_600,
+        rem % 3_600 / 60,
+        rem % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn at(secs: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    fn entry(message: &str) -> Entry {
+        Entry {
+            category: "head".to_string(),
+            cwd: "/src/project".to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    fn log(dir: &Path) -> String {
+        fs::read_to_string(dir.join(ERROR_LOG_FILE)).unwrap_or_default()
+    }
+
+    #[test]
+    fn test_rfc3339() {
+        assert_eq!(rfc3339(at(0)), "1970-01-01T00:00:00Z");
+        assert_eq!(rfc3339(at(951_782_400)), "2000-02-29T00:00:00Z");
+        assert_eq!(rfc3339(at(1_792_397_702)), "2026-10-19T08:15:02Z");
+    }
+
+    #[test]
+    fn test_identical_errors_are_counted() {
+        let dir = TempDir::new().unwrap();
+        let start = 1_792_397_702;
+
+        for i in 0..5 {
+            append(dir.path(), &entry("bad HEAD"), at(start + i));
+        }
+        assert_eq!(
+            log(dir.path()),
+            "2026-10-19T08:15:02Z [head] cwd=/src/project bad HEAD\n",
+            "Repeats within the window should not be written"
+        );
+
+        append(dir.path(), &entry("other"), at(start + 10));
+        let lines: Vec<String> = log(dir.path()).lines().map(str::to_string).collect();
+        assert_eq!(lines.len(), 3);
+        assert!(
+            lines[1].ends_with("repeated 4 more times: bad HEAD"),
+            "{}",
+            lines[1]
+        );
+        assert!(lines[2].ends_with("cwd=/src/project other"));
+
+        // Once the window has passed the same error is written again
+        append(
+            dir.path(),
+            &entry("other"),
+            at(start + 10 + DEDUP_WINDOW.as_secs()),
+        );
+        assert_eq!(log(dir.path()).lines().count(), 4);
+    }
+
+    #[test]
+    fn test_flush_writes_pending_repeats() {
+        let dir = TempDir::new().unwrap();
+        append(dir.path(), &entry("bad HEAD"), at(100));
+        append(dir.path(), &entry("bad HEAD"), at(101));
+
+        flush(dir.path(), at(102));
+        assert!(log(dir.path()).contains("repeated 1 more times: bad HEAD"));
+
+        flush(dir.path(), at(103));
+        assert_eq!(log(dir.path()).lines().count(), 2, "Flush is idempotent");
+    }
+
+    #[test]
+    fn test_rotation_caps_size_and_tail_spans_files() {
+        let dir = TempDir::new().unwrap();
+        let long = "x".repeat(1024);
+
+        for i in 0..300 {
+            append(dir.path(), &entry(&format!("{} {}", i, long)), at(i));
+        }
+
+        let [log, rotated] = log_files(dir.path());
+        assert!(rotated.exists(), "Log should have been rotated");
+        assert!(fs::metadata(&log).unwrap().len() < MAX_LOG_BYTES);
+
+        let last = tail(dir.path(), 300);
+        assert!(last.len() > 250, "Tail should read across the rotated file");
+        assert!(last.last().unwrap().contains("cwd=/src/project 299 "));
+
+        clear(dir.path()).unwrap();
+        assert!(!log.exists() && !rotated.exists());
+        assert!(tail(dir.path(), 10).is_empty());
+    }
+}
@@ -0,0 +1,105 @@
+use crate::Env;
+use crate::settings::RepoSettings;
+use crate::trace;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+// ============================================================================
+// Last Fetch Age
+// ============================================================================
+//
+// Ahead/behind counts are only as fresh as the last fetch. Every fetch
+// rewrites `FETCH_HEAD` in the common directory, so its mtime says when that
+// was, for all worktrees of a `.bare` layout at once. Discovery records the
+// timestamp; the prompt compares it with the clock on every render and adds
+// a marker once it is older than GIT_WORKTREE_PROMPT_FETCH_STALE_SECS.
+
+/// Environment variable overriding the stale-fetch threshold in seconds; 0 disables it
+pub(crate) const ENV_FETCH_STALE_SECS: &str = "GIT_WORKTREE_PROMPT_FETCH_STALE_SECS";
+
+/// Default age after which the last fetch counts as stale: one day
+const DEFAULT_FETCH_STALE_SECS: u64 = 24 * 60 * 60;
+
+/// Seconds since the Unix epoch when `FETCH_HEAD` was last written, if ever
+pub(crate) fn last_fetch(common_dir: &Path) -> Option<u64> {
+    let modified = trace::metadata(&common_dir.join("FETCH_HEAD"))
+        .ok()?
+        .modified()
+        .ok()?;
+    Some(
+        modified
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .ok()?
+            .as_secs(),
+    )
+}
+
+/// Threshold from `worktree-prompt.fetchStaleSecs`, then the environment,
+/// then one day; None when set to 0
+pub(crate) fn stale_after(settings: &RepoSettings, env: &Env) -> Option<Duration> {
+    let secs = settings
+        .get_or_env("fetchStaleSecs", env, ENV_FETCH_STALE_SECS)
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(DEFAULT_FETCH_STALE_SECS);
+    (secs > 0).then(|| Duration::from_secs(secs))
+}
+
+/// True when the repository was fetched once, but longer ago than `stale_after`
+pub(crate) fn is_stale(
+    last_fetch: Option<u64>,
+    stale_after: Option<Duration>,
+    now: SystemTime,
+) -> bool {
+    let (Some(last_fetch), Some(stale_after)) = (last_fetch, stale_after) else {
+        return false;
+    };
+    let now = now
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    now.saturating_sub(last_fetch) > stale_after.as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_stale() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let day = Some(Duration::from_secs(86_400));
+        assert!(!is_stale(None, day, now), "Never fetched is not stale");
+        assert!(!is_stale(Some(1_000_000 - 86_400), day, now));
+        assert!(is_stale(Some(1_000_000 - 86_401), day, now));
+        assert!(
+            !is_stale(Some(0), None, now),
+            "A zero threshold disables it"
+        );
+        assert!(
+            !is_stale(Some(2_000_000), day, now),
+            "Clock skew is not stale"
+        );
+    }
+
+    #[test]
+    fn test_stale_after_prefers_repository_setting() {
+        let settings = RepoSettings {
+            entries: vec![("fetchstalesecs".to_string(), "3600".to_string())],
+            sources: Vec::new(),
+        };
+        let env = Env::new().with(ENV_FETCH_STALE_SECS, "60");
+        assert_eq!(
+            stale_after(&settings, &env),
+            Some(Duration::from_secs(3600))
+        );
+        assert_eq!(
+            stale_after(&RepoSettings::default(), &env),
+            Some(Duration::from_secs(60))
+        );
+
+        let disabled = RepoSettings {
+            entries: vec![("fetchstalesecs".to_string(), "0".to_string())],
+            sources: Vec::new(),
+        };
+        assert_eq!(stale_after(&disabled, &env), None);
+    }
+}
@@ -0,0 +1,224 @@
+use crate::errorlog::rfc3339;
+use crate::fetch;
+use crate::upstream::Upstream;
+use crate::{Discovery, Env};
+use std::time::{Duration, SystemTime};
+
+// ============================================================================
+// Structured Output
+// ============================================================================
+//
+// `git-worktree-prompt fields` prints what discovery found as `key=value`
+// lines, one field per line, for scripts and status bars that want the
+// values rather than the rendered glyphs. Keys are stable; a field that does
+// not apply (detached HEAD, never fetched, full checkout) is left out rather than printed
+// empty. Timestamps are RFC 3339 in UTC, like the error log's, and upstream
+// state is reported whether or not its marker is turned on.
+
+/// Discovery results as key/value pairs, in output order
+pub(crate) fn collect(
+    discovery: &Discovery,
+    env: &Env,
+    now: SystemTime,
+) -> Vec<(&'static str, String)> {
+    let mut fields = vec![
+        ("work_dir", discovery.work_dir.display().to_string()),
+        ("git_dir", discovery.git_dir.display().to_string()),
+        ("common_dir", discovery.common_dir.display().to_string()),
+        ("head", discovery.head.name.clone()),
+    ];
+    if let Some(symref) = &discovery.head.symref {
+        fields.push(("symref", symref.clone()));
+    }
+
+    match &discovery.upstream {
+        None => {}
+        Some(Upstream::Unset) => fields.push(("upstream", "unset".to_string())),
+        Some(Upstream::Gone(tracking_ref)) => {
+            fields.push(("upstream", "gone".to_string()));
+            fields.push(("upstream_ref", tracking_ref.clone()));
+        }
+        Some(Upstream::Tracking(tracking_ref)) => {
+            fields.push(("upstream", "tracking".to_string()));
+            fields.push(("upstream_ref", tracking_ref.clone()));
+        }
+    }
+
+    if let Some(secs) = discovery.last_fetch {
+        let stale_after = fetch::stale_after(&discovery.settings, env);
+        let stale = fetch::is_stale(discovery.last_fetch, stale_after, now);
+        fields.push((
+            "last_fetch",
+            rfc3339(SystemTime::UNIX_EPOCH + Duration::from_secs(secs)),
+        ));
+        fields.push(("fetch_stale", stale.to_string()));
+    }
+
+    let shape = &discovery.shape;
+    fields.push(("shallow", shape.shallow.to_string()));
+    fields.push(("partial", shape.partial.is_some().to_string()));
+    if let Some(filter) = shape.partial.as_ref().filter(|f| !f.is_empty()) {
+        fields.push(("partial_filter", filter.clone()));
+    }
+    if let Some(sparse) = shape.sparse {
+        fields.push(("sparse", sparse.name().to_string()));
+    }
+    fields
+}
+
+/// Formats the fields as `key=value` lines
+pub(crate) fn render(discovery: &Discovery, env: &Env, now: SystemTime) -> String {
+    collect(discovery, env, now)
+        .into_iter()
+        .map(|(key, value)| format!("{}={}\n", key, value))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::RepoSettings;
+    use crate::shape::{Shape, Sparse};
+    use crate::{Head, Layout};
+    use std::path::PathBuf;
+
+    fn sample_discovery() -> Discovery {
+        Discovery {
+            work_dir: PathBuf::from("/src/repo/feature"),
+            git_dir: PathBuf::from("/src/repo/.bare/worktrees/feature"),
+            common_dir: PathBuf::from("/src/repo/.bare"),
+            head: Head {
+                name: "feature".to_string(),
+                symref: Some("refs/heads/feature".to_string()),
+            },
+            layout: Layout::Worktree {
+                bare_parent: PathBuf::from("/src/repo"),
+                path: "feature".to_string(),
+            },
+            settings: RepoSettings::default(),
+            upstream: Some(Upstream::Tracking(
+                "refs/remotes/origin/feature".to_string(),
+            )),
+            last_fetch: Some(1_700_000_000),
+            remotes: Vec::new(),
+            submodule: None,
+            shape: Shape::default(),
+            operation: None,
+            jj: None,
+        }
+    }
+
+    #[test]
+    fn test_fields_report_last_fetch_as_rfc3339() {
+        let discovery = sample_discovery();
+        let env = Env::new();
+        let fetched = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+        assert_eq!(
+            render(&discovery, &env, fetched + Duration::from_secs(60)),
+            "work_dir=/src/repo/feature\n\
+             git_dir=/src/repo/.bare/worktrees/feature\n\
+             common_dir=/src/repo/.bare\n\
+             head=feature\n\
+             symref=refs/heads/feature\n\
+             upstream=tracking\n\
+             upstream_ref=refs/remotes/origin/feature\n\
+             last_fetch=2023-11-14T22:13:20Z\n\
+             fetch_stale=false\n\
+             shallow=false\n\
+             partial=false\n"
+        );
+
+        let two_days = Duration::from_secs(2 * 24 * 60 * 60);
+        let fields = collect(&discovery, &env, fetched + two_days);
+        assert!(fields.contains(&("fetch_stale", "true".to_string())));
+    }
+
+    #[test]
+    fn test_fields_leave_out_what_does_not_apply() {
+        let mut discovery = sample_discovery();
+        discovery.head = Head {
+            name: "abc1234".to_string(),
+            symref: None,
+        };
+        discovery.upstream = None;
+        discovery.last_fetch = None;
+
+        let keys: Vec<&str> = collect(&discovery, &Env::new(), SystemTime::now())
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect();
+        assert_eq!(
+            keys,
+            [
+                "work_dir",
+                "git_dir",
+                "common_dir",
+                "head",
+                "shallow",
+                "partial"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fields_report_upstream_state() {
+        let mut discovery = sample_discovery();
+        let upstream = |discovery: &Discovery| -> Vec<(&str, String)> {
+            collect(discovery, &Env::new(), SystemTime::now())
+                .into_iter()
+                .filter(|(key, _)| key.starts_with("upstream"))
+                .collect()
+        };
+
+        discovery.upstream = Some(Upstream::Unset);
+        assert_eq!(upstream(&discovery), [("upstream", "unset".to_string())]);
+
+        discovery.upstream = Some(Upstream::Gone("refs/remotes/origin/old".to_string()));
+        assert_eq!(
+            upstream(&discovery),
+            [
+                ("upstream", "gone".to_string()),
+                ("upstream_ref", "refs/remotes/origin/old".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fields_report_clone_shape() {
+        let mut discovery = sample_discovery();
+        let shape = |discovery: &Discovery| -> Vec<(&str, String)> {
+            collect(discovery, &Env::new(), SystemTime::now())
+                .into_iter()
+                .skip_while(|(key, _)| *key != "shallow")
+                .collect()
+        };
+
+        discovery.shape = Shape {
+            shallow: true,
+            partial: Some("blob:none".to_string()),
+            sparse: Some(Sparse::Cone),
+        };
+        assert_eq!(
+            shape(&discovery),
+            [
+                ("shallow", "true".to_string()),
+                ("partial", "true".to_string()),
+                ("partial_filter", "blob:none".to_string()),
+                ("sparse", "cone".to_string()),
+            ]
+        );
+
+        // A promisor remote without a recorded filter is still a partial clone
+        discovery.shape.partial = Some(String::new());
+        discovery.shape.sparse = Some(Sparse::Patterns);
+        assert_eq!(
+            shape(&discovery),
+            [
+                ("shallow", "true".to_string()),
+                ("partial", "true".to_string()),
+                ("sparse", "patterns".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,656 @@
+use crate::wildmatch::wildmatch;
+use crate::{Error, trace};
+use std::fs;
+use std::io::ErrorKind;
+use std::iter::Peekable;
+use std::path::{Path, PathBuf};
+use std::str::Chars;
+
+// ============================================================================
+// Git Config Reader
+// ============================================================================
+//
+// Reads `section.subsection.key = value` entries from a git config file.
+// Section and key names are case-insensitive and stored lowercased;
+// subsection names keep their case, as in git. Values follow git's rules for
+// double quotes, backslash escapes, line continuations and inline comments.
+// `include.path` and `includeIf "<condition>".path` pull in further files at
+// the point they appear, so later entries still override included ones.
+
+/// Nesting limit for include directives, the same as git's
+const MAX_INCLUDE_DEPTH: usize = 10;
+
+/// Facts include paths and `includeIf` conditions are evaluated against
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Conditions<'a> {
+    /// Home directory that a leading `~/` expands to
+    pub(crate) home: Option<&'a Path>,
+    /// Git directory for `gitdir:` and `gitdir/i:`
+    pub(crate) git_dir: Option<&'a Path>,
+    /// Checked-out branch for `onbranch:`
+    pub(crate) branch: Option<&'a str>,
+}
+
+/// Entries of a git config file and the files it includes, in file order
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct Config {
+    entries: Vec<(String, String)>,
+    /// Every file read or probed, includes last
+    sources: Vec<PathBuf>,
+}
+
+impl Config {
+    /// Reads a config file, treating a missing file as empty
+    pub(crate) fn load(path: &Path) -> Result<Config, Error> {
+        Config::load_with(path, Conditions::default())
+    }
+
+    /// Reads a config file, following includes whose conditions hold
+    pub(crate) fn load_with(path: &Path, conditions: Conditions) -> Result<Config, Error> {
+        let mut config = Config::default();
+        config.read(path, conditions, 0)?;
+        Ok(config)
+    }
+
+    /// Parses config text, rejecting input git itself would refuse
+    ///
+    /// Plain includes are followed relative to `path`; conditional ones are not.
+    #[cfg(test)]
+    pub(crate) fn parse(path: &Path, text: &str) -> Result<Config, Error> {
+        let mut config = Config::default();
+        config.parse_into(path, text, Conditions::default(), 0)?;
+        Ok(config)
+    }
+
+    /// Appends another file's entries, which then take precedence
+    pub(crate) fn extend(&mut self, other: Config) {
+        self.entries.extend(other.entries);
+        self.sources.extend(other.sources);
+    }
+
+    /// Files this config was read from, including missing include targets
+    pub(crate) fn sources(&self) -> &[PathBuf] {
+        &self.sources
+    }
+
+    fn read(&mut self, path: &Path, conditions: Conditions, depth: usize) -> Result<(), Error> {
+        self.sources.push(path.to_path_buf());
+        match trace::read_to_string(path) {
+            Ok(text) => self.parse_into(path, &text, conditions, depth),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(Error::io(path, e)),
+        }
+    }
+
+    fn parse_into(
+        &mut self,
+        path: &Path,
+        text: &str,
+        conditions: Conditions,
+        depth: usize,
+    ) -> Result<(), Error> {
+        let mut parser = Parser {
+            path,
+            chars: text.chars().peekable(),
+            line: 1,
+        };
+        let mut section: Option<String> = None;
+
+        while let Some(c) = parser.peek() {
+            match c {
+                c if c.is_whitespace() => {
+                    parser.next();
+                }
+                '#' | ';' => parser.skip_line(),
+                '[' => {
+                    parser.next();
+                    section = Some(parser.header()?);
+                }
+                c if c.is_ascii_alphabetic() => {
+                    let section = section
+                        .as_deref()
+                        .ok_or_else(|| parser.error("key outside of any section"))?;
+                    let line = parser.line;
+                    let (key, value) = parser.entry()?;
+
+                    let include = key == "path"
+                        && match section.strip_prefix("includeif.") {
+                            Some(condition) => condition_holds(condition, conditions, path),
+                            None => section == "include",
+                        };
+                    let target = include.then(|| include_path(path, &value, conditions.home));
+                    self.entries.push((format!("{}.{}", section, key), value));
+
+                    if let Some(target) = target {
+                        if depth + 1 >= MAX_INCLUDE_DEPTH {
+                            return Err(Error::ConfigParse {
+                                path: path.to_path_buf(),
+                                line,
+                                reason: format!(
+                                    "exceeded maximum include depth ({})",
+                                    MAX_INCLUDE_DEPTH
+                                ),
+                            });
+                        }
+                        self.read(&target, conditions, depth + 1)?;
+                    }
+                }
+                _ if section.is_none() => return Err(parser.error("key outside of any section")),
+                _ => return Err(parser.error("invalid key name")),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Last value for a key, as git does for single-valued lookups
+    pub(crate) fn get(&self, key: &str) -> Option<&str> {
+        self.get_all(key).last().copied()
+    }
+
+    /// Every value for a multi-valued key such as `remote.origin.fetch`
+    pub(crate) fn get_all(&self, key: &str) -> Vec<&str> {
+        let key = normalize_key(key);
+        self.entries
+            .iter()
+            .filter(|(k, _)| *k == key)
+            .map(|(_, v)| v.as_str())
+            .collect()
+    }
+
+    /// Distinct subsection names of a section, e.g. remote names for "remote"
+    pub(crate) fn subsections(&self, section: &str) -> Vec<&str> {
+        let prefix = format!("{}.", section.to_lowercase());
+        let mut names: Vec<&str> = Vec::new();
+        for (key, _) in &self.entries {
+            if let Some((sub, _)) = key
+                .strip_prefix(&prefix)
+                .and_then(|rest| rest.rsplit_once('.'))
+                && !names.contains(&sub)
+            {
+                names.push(sub);
+            }
+        }
+        names
+    }
+
+    /// Keys and values directly in `[section]`, without a subsection
+    pub(crate) fn section(&self, section: &str) -> Vec<(&str, &str)> {
+        let prefix = format!("{}.", section.to_lowercase());
+        self.entries
+            .iter()
+            .filter_map(|(key, value)| {
+                let name = key.strip_prefix(&prefix)?;
+                (!name.contains('.')).then_some((name, value.as_str()))
+            })
+            .collect()
+    }
+
+    /// Interprets a key as a git boolean
+    pub(crate) fn get_bool(&self, key: &str) -> Option<bool> {
+        match self.get(key)?.to_lowercase().as_str() {
+            "true" | "yes" | "on" | "1" => Some(true),
+            "false" | "no" | "off" | "0" | "" => Some(false),
+            _ => None,
+        }
+    }
+}
+
+/// Reads one file's text, tracking the line for error messages
+struct Parser<'a> {
+    path: &'a Path,
+    chars: Peekable<Chars<'a>>,
+    line: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, reason: &str) -> Error {
+        Error::ConfigParse {
+            path: self.path.to_path_buf(),
+            line: self.line,
+            reason: reason.to_string(),
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next();
+        if c == Some('\n') {
+            self.line += 1;
+        }
+        c
+    }
+
+    fn skip_line(&mut self) {
+        while let Some(c) = self.next() {
+            if c == '\n' {
+                break;
+            }
+        }
+    }
+
+    fn skip_blanks(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\t')) {
+            self.next();
+        }
+    }
+
+    /// Parses `[section]`, `[section "subsection"]` or the old `[section.subsection]`
+    fn header(&mut self) -> Result<String, Error> {
+        let mut name = String::new();
+        loop {
+            match self.peek() {
+                Some(']') => {
+                    self.next();
+                    break;
+                }
+                Some(c) if c.is_ascii_alphanumeric() || c == '-' || c == '.' => {
+                    name.push(c);
+                    self.next();
+                }
+                Some(' ' | '\t') if !name.is_empty() => {
+                    self.skip_blanks();
+                    if self.next() != Some('"') {
+                        return Err(self.error("invalid section name"));
+                    }
+                    let sub = self.subsection()?;
+                    if self.next() != Some(']') {
+                        return Err(self.error("unterminated section header"));
+                    }
+                    return Ok(format!("{}.{}", name.to_lowercase(), sub));
+                }
+                None | Some('\n') => return Err(self.error("unterminated section header")),
+                Some(_) => return Err(self.error("invalid section name")),
+            }
+        }
+
+        if name.is_empty() {
+            return Err(self.error("invalid section name"));
+        }
+        // The deprecated dotted form is case-insensitive throughout
+        Ok(name.to_lowercase())
+    }
+
+    /// Reads a quoted subsection name after its opening quote
+    fn subsection(&mut self) -> Result<String, Error> {
+        let mut sub = String::new();
+        loop {
+            match self.peek() {
+                None | Some('\n') => return Err(self.error("unterminated subsection name")),
+                Some('"') => {
+                    self.next();
+                    return Ok(sub);
+                }
+                Some('\\') => {
+                    self.next();
+                    match self.peek() {
+                        None | Some('\n') => {
+                            return Err(self.error("unterminated subsection name"));
+                        }
+                        Some(c) => sub.push(c),
+                    }
+                    self.next();
+                }
+                Some(c) => {
+                    sub.push(c);
+                    self.next();
+                }
+            }
+        }
+    }
+
+    /// Parses `key = value` or a bare key, which means boolean true
+    fn entry(&mut self) -> Result<(String, String), Error> {
+        let mut key = String::new();
+        while let Some(c) = self.peek()
+            && (c.is_ascii_alphanumeric() || c == '-')
+        {
+            key.push(c.to_ascii_lowercase());
+            self.next();
+        }
+        self.skip_blanks();
+
+        match self.peek() {
+            None | Some('\n' | '\r') => Ok((key, "true".to_string())),
+            Some('#' | ';') => {
+                self.skip_line();
+                Ok((key, "true".to_string()))
+            }
+            Some('=') => {
+                self.next();
+                Ok((key, self.value()?))
+            }
+            Some(_) => Err(self.error("invalid key name")),
+        }
+    }
+
+    /// Reads a value up to the end of its (possibly continued) line
+    ///
+    /// Quotes only protect whitespace and comment characters. As in git, each
+    /// unquoted whitespace character inside a value becomes a space and
+    /// trailing whitespace is dropped.
+    fn value(&mut self) -> Result<String, Error> {
+        let mut value = String::new();
+        let mut quoted = false;
+        let mut spaces = 0;
+        self.skip_blanks();
+
+        loop {
+            match self.peek() {
+                None | Some('\n') => {
+                    if quoted {
+                        return Err(self.error("unterminated quoted value"));
+                    }
+                    return Ok(value);
+                }
+                Some('\r') if !quoted => {
+                    self.next();
+                }
+                Some(' ' | '\t') if !quoted => {
+                    self.next();
+                    spaces += 1;
+                }
+                Some('#' | ';') if !quoted => {
+                    self.skip_line();
+                    return Ok(value);
+                }
+                Some(c) => {
+                    self.next();
+                    for _ in 0..std::mem::take(&mut spaces) {
+                        value.push(' ');
+                    }
+                    match c {
+                        '\\' => match self.next() {
+                            Some('\n') => {}
+                            Some('n') => value.push('\n'),
+                            Some('t') => value.push('\t'),
+                            Some('b') => value.push('\u{8}'),
+                            Some(c @ ('\\' | '"')) => value.push(c),
+                            _ => return Err(self.error("invalid escape sequence")),
+                        },
+                        '"' => quoted = !quoted,
+                        c => value.push(c),
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Resolves an include path: `~/` is the home directory, relative paths
+/// are relative to the including file
+fn include_path(including: &Path, target: &str, home: Option<&Path>) -> PathBuf {
+    if let Some(rest) = target.strip_prefix("~/")
+        && let Some(home) = home
+    {
+        return home.join(rest);
+    }
+    match including.parent() {
+        Some(dir) => dir.join(target),
+        None => PathBuf::from(target),
+    }
+}
+
+/// Evaluates an `includeIf` condition; unsupported kinds never match
+fn condition_holds(condition: &str, conditions: Conditions, including: &Path) -> bool {
+    if let Some(pattern) = condition.strip_prefix("gitdir:") {
+        gitdir_matches(pattern, conditions, including, false)
+    } else if let Some(pattern) = condition.strip_prefix("gitdir/i:") {
+        gitdir_matches(pattern, conditions, including, true)
+    } else if let Some(pattern) = condition.strip_prefix("onbranch:") {
+        let pattern = with_dir_suffix(pattern.to_string());
+        conditions
+            .branch
+            .is_some_and(|branch| wildmatch(pattern.as_bytes(), branch.as_bytes()))
+    } else {
+        false
+    }
+}
+
+/// Matches a `gitdir:` pattern against the git directory and its real path
+fn gitdir_matches(pattern: &str, conditions: Conditions, including: &Path, icase: bool) -> bool {
+    let Some(git_dir) = conditions.git_dir else {
+        return false;
+    };
+
+    let mut pattern = if let Some(rest) = pattern.strip_prefix("~/")
+        && let Some(home) = conditions.home
+    {
+        home.join(rest).to_string_lossy().into_owned()
+    } else if let Some(rest) = pattern.strip_prefix("./") {
+        let dir = including.parent().unwrap_or(Path::new(""));
+        dir.join(rest).to_string_lossy().into_owned()
+    } else {
+        pattern.to_string()
+    };
+    if !pattern.starts_with('/') {
+        pattern.insert_str(0, "**/");
+    }
+    pattern = with_dir_suffix(pattern);
+
+    let fold = |s: &str| {
+        if icase {
+            s.to_lowercase()
+        } else {
+            s.to_string()
+        }
+    };
+    let pattern = fold(&pattern);
+    let real = fs::canonicalize(git_dir).ok();
+    [Some(git_dir), real.as_deref()]
+        .into_iter()
+        .flatten()
+        .any(|dir| wildmatch(pattern.as_bytes(), fold(&dir.to_string_lossy()).as_bytes()))
+}
+
+/// A trailing slash matches everything below that directory
+fn with_dir_suffix(mut pattern: String) -> String {
+    if pattern.ends_with('/') {
+        pattern.push_str("**");
+    }
+    pattern
+}
+
+/// Lowercases the section and key of `section[.subsection].key`
+fn normalize_key(key: &str) -> String {
+    let (head, name) = key.rsplit_once('.').unwrap_or(("", key));
+    let (section, sub) = match head.split_once('.') {
+        Some((section, sub)) => (section, Some(sub)),
+        None => (head, None),
+    };
+    match sub {
+        Some(sub) => format!("{}.{}.{}", section.to_lowercase(), sub, name.to_lowercase()),
+        None => format!("{}.{}", section.to_lowercase(), name.to_lowercase()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sections_and_subsections() {
+        let config = Config::parse(
+            Path::new("config"),
+            r#"
+[core]
+    bare = true
+    repositoryformatversion = 0
+# comment
+[remote "origin"]
+    url = git@github.com:user/repo.git
+    fetch = +refs/heads/*:refs/remotes/origin/*
+    fetch = +refs/tags/*:refs/tags/*
+[Branch "Feature"]
+    Remote = origin
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.get_bool("core.bare"), Some(true));
+        assert_eq!(config.get("CORE.Bare"), Some("true"));
+        assert_eq!(
+            config.get("remote.origin.url"),
+            Some("git@github.com:user/repo.git")
+        );
+        assert_eq!(config.get_all("remote.origin.fetch").len(), 2);
+        assert_eq!(config.get("branch.Feature.remote"), Some("origin"));
+        assert_eq!(config.get("branch.feature.remote"), None);
+        assert_eq!(config.get("remote.upstream.url"), None);
+        assert_eq!(config.subsections("remote"), vec!["origin"]);
+    }
+
+    #[test]
+    fn test_bare_key_is_true() {
+        let config = Config::parse(Path::new("config"), "[core]\n\tsparseCheckout\n").unwrap();
+        assert_eq!(config.get_bool("core.sparsecheckout"), Some(true));
+    }
+
+    #[test]
+    fn test_parse_errors_carry_line_numbers() {
+        let path = Path::new("/repo/.git/config");
+        for (text, line, reason) in [
+            ("[core\n", 1, "unterminated section header"),
+            ("[core]\n\tbare = true\n[bad!]\n", 3, "invalid section name"),
+            ("bare = true\n", 1, "key outside of any section"),
+            ("[core]\n\t1bare = true\n", 2, "invalid key name"),
+            ("[core]\n\tname = \"open\n", 2, "unterminated quoted value"),
+            ("[core]\n\tname = a\\qb\n", 2, "invalid escape sequence"),
+            ("[remote \"origin]\n", 1, "unterminated subsection name"),
+        ] {
+            match Config::parse(path, text) {
+                Err(Error::ConfigParse {
+                    path: error_path,
+                    line: error_line,
+                    reason: error_reason,
+                }) => {
+                    assert_eq!(error_path, path);
+                    assert_eq!(
+                        (error_line, error_reason.as_str()),
+                        (line, reason),
+                        "{:?}",
+                        text
+                    );
+                }
+                other => panic!("expected ConfigParse for {:?}, got {:?}", text, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_quoting_escapes_and_comments() {
+        let config = Config::parse(
+            Path::new("config"),
+            concat!(
+                "[worktree-prompt] branchIcon = \" \" ; inline comment\n",
+                "\tlabel = a   b \"#1\" # trailing\n",
+                "\tescaped = tab\\there \\\"quoted\\\"\n",
+                "\tlong = first \\\n",
+                "\t\tsecond\n",
+                "[Remote \"We\\\"ird \\\\Name\"]\n\turl = x\n",
+                "[Section.Sub]\n\tkey = y\n",
+            ),
+        )
+        .unwrap();
+
+        assert_eq!(config.get("worktree-prompt.branchicon"), Some(" "));
+        assert_eq!(config.get("worktree-prompt.label"), Some("a   b #1"));
+        assert_eq!(
+            config.get("worktree-prompt.escaped"),
+            Some("tab\there \"quoted\"")
+        );
+        assert_eq!(config.get("worktree-prompt.long"), Some("first   second"));
+        assert_eq!(config.get("remote.We\"ird \\Name.url"), Some("x"));
+        assert_eq!(config.get("section.sub.key"), Some("y"));
+        assert_eq!(config.section("worktree-prompt").len(), 4);
+    }
+
+    #[test]
+    fn test_includes_and_conditions() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let root = dir.path();
+        let git_dir = root.join("work/project/.git");
+        let home = root.join("work");
+        fs::create_dir_all(&home).unwrap();
+        fs::write(root.join("shared"), "[worktree-prompt]\n\tstatus = off\n").unwrap();
+        fs::write(
+            home.join("home.inc"),
+            "[worktree-prompt]\n\ttheme = ascii\n",
+        )
+        .unwrap();
+        fs::write(
+            root.join("work.inc"),
+            "[worktree-prompt]\n\tbranchIcon = W\n",
+        )
+        .unwrap();
+        fs::write(
+            root.join("main.inc"),
+            "[worktree-prompt]\n\tworktreeIcon = M\n",
+        )
+        .unwrap();
+        fs::write(
+            root.join("other.inc"),
+            "[worktree-prompt]\n\tbranchIcon = X\n",
+        )
+        .unwrap();
+        let config_path = root.join("config");
+        fs::write(
+            &config_path,
+            concat!(
+                "[include]\n\tpath = shared\n",
+                "[includeIf \"gitdir:work/\"]\n\tpath = work.inc\n",
+                "[includeIf \"gitdir/i:**/WORK/PROJECT/.git\"]\n\tpath = main.inc\n",
+                "[includeIf \"gitdir:/elsewhere/\"]\n\tpath = other.inc\n",
+                "[includeIf \"gitdir:~/project/\"]\n\tpath = ~/home.inc\n",
+                "[includeIf \"onbranch:release/\"]\n\tpath = other.inc\n",
+                "[includeIf \"hasconfig:remote.*.url:x\"]\n\tpath = other.inc\n",
+                "[include]\n\tpath = missing\n",
+            ),
+        )
+        .unwrap();
+
+        let conditions = Conditions {
+            home: Some(&home),
+            git_dir: Some(&git_dir),
+            branch: Some("main"),
+        };
+        let config = Config::load_with(&config_path, conditions).unwrap();
+        assert_eq!(config.get("worktree-prompt.status"), Some("off"));
+        assert_eq!(config.get("worktree-prompt.branchicon"), Some("W"));
+        assert_eq!(config.get("worktree-prompt.worktreeicon"), Some("M"));
+        assert_eq!(config.get("worktree-prompt.theme"), Some("ascii"));
+        assert!(config.sources().contains(&root.join("missing")));
+        assert!(!config.sources().contains(&root.join("other.inc")));
+
+        let conditions = Conditions {
+            home: None,
+            git_dir: Some(&git_dir),
+            branch: Some("release/1.0"),
+        };
+        let config = Config::load_with(&config_path, conditions).unwrap();
+        assert_eq!(config.get("worktree-prompt.branchicon"), Some("X"));
+        // `~/` only expands with a home directory
+        assert_eq!(config.get("worktree-prompt.theme"), None);
+
+        // Without conditions only plain includes apply
+        let config = Config::load(&config_path).unwrap();
+        assert_eq!(config.get("worktree-prompt.status"), Some("off"));
+        assert_eq!(config.get("worktree-prompt.branchicon"), None);
+    }
+
+    #[test]
+    fn test_include_cycle_is_rejected() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("config");
+        fs::write(&path, "[include]\n\tpath = config\n").unwrap();
+        match Config::load(&path) {
+            Err(Error::ConfigParse { reason, .. }) => {
+                assert!(reason.contains("include depth"), "{}", reason)
+            }
+            other => panic!("expected ConfigParse, got {:?}", other),
+        }
+    }
+}
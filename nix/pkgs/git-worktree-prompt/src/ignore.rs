@@ -0,0 +1,78 @@
+use crate::Env;
+use crate::wildmatch::wildmatch;
+use std::path::Path;
+
+// ============================================================================
+// Ignored Directories
+// ============================================================================
+//
+// GIT_WORKTREE_PROMPT_IGNORE holds colon-separated glob patterns, e.g.
+//
+//   /nix/store/*:~/build/**/vendor:/mnt/nfs/monorepo
+//
+// A directory is ignored when it or any of its parents matches a pattern.
+// Ignored directories print nothing and log nothing. The check runs before
+// the daemon, cache or `.git` walk and is pure string matching, so it costs
+// no filesystem calls.
+
+/// Environment variable holding the colon-separated ignore patterns
+pub const ENV_IGNORE: &str = "GIT_WORKTREE_PROMPT_IGNORE";
+
+/// The first pattern in GIT_WORKTREE_PROMPT_IGNORE covering `start`, if any
+pub fn matching_pattern(env: &Env, start: &Path) -> Option<String> {
+    match_patterns(env.get(ENV_IGNORE)?, env.get("HOME"), start)
+}
+
+fn match_patterns(patterns: &str, home: Option<&str>, start: &Path) -> Option<String> {
+    let start = start.to_str()?;
+    patterns
+        .split(':')
+        .filter(|pattern| !pattern.is_empty())
+        .find(|pattern| {
+            let expanded = expand(pattern, home);
+            let expanded = expanded.trim_end_matches('/');
+            Path::new(start)
+                .ancestors()
+                .filter_map(Path::to_str)
+                .any(|dir| wildmatch(expanded.as_bytes(), dir.as_bytes()))
+        })
+        .map(str::to_string)
+}
+
+/// Expands a leading `~/` to the home directory
+fn expand(pattern: &str, home: Option<&str>) -> String {
+    match (pattern.strip_prefix("~/"), home) {
+        (Some(rest), Some(home)) => format!("{}/{}", home.trim_end_matches('/'), rest),
+        _ => pattern.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_patterns_cover_directory_and_descendants() {
+        let patterns = "/nix/store/*:~/build/**/vendor::/mnt/nfs/mono/";
+        let home = Some("/home/me");
+        for (start, expected) in [
+            ("/nix/store/abc-hello/src", Some("/nix/store/*")),
+            ("/nix/store", None),
+            ("/home/me/build/x/y/vendor/lib", Some("~/build/**/vendor")),
+            ("/home/me/build/vendor", Some("~/build/**/vendor")),
+            ("/home/me/src/vendor", None),
+            ("/mnt/nfs/mono", Some("/mnt/nfs/mono/")),
+            ("/mnt/nfs/mono/deep/dir", Some("/mnt/nfs/mono/")),
+            ("/mnt/nfs/monorepo", None),
+            ("/", None),
+        ] {
+            assert_eq!(
+                match_patterns(patterns, home, Path::new(start)).as_deref(),
+                expected,
+                "{}",
+                start
+            );
+        }
+        assert_eq!(match_patterns("", home, Path::new("/src")), None);
+    }
+}
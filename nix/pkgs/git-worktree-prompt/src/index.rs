@@ -0,0 +1,295 @@
+// ============================================================================
+// Index Reader
+// ============================================================================
+//
+// The index (`.git/index`) is a 12-byte header (`DIRC`, version, entry count)
+// followed by one entry per path and stage, sorted by path:
+//
+//   40 bytes  ctime, mtime, dev, ino, mode, uid, gid, size (32-bit each)
+//   n bytes   object id, 20 for SHA-1 and 32 for SHA-256
+//   2 bytes   flags: assume-valid, extended, 2-bit stage, 12-bit name length
+//   2 bytes   extended flags, only in version 3+ when the extended bit is set
+//   name      NUL-terminated; versions 2 and 3 pad the entry to a multiple of
+//             eight bytes, version 4 instead prefix-compresses the name
+//             against the previous entry and pads nothing
+//
+// A clean path has a single stage-0 entry. A conflicted one has no stage 0,
+// but up to three entries for the common ancestor (1), ours (2) and theirs (3).
+// Extensions and the trailing checksum after the entries are never read.
+
+/// Mode bits of the object type
+const TYPE_MASK: u32 = 0o170000;
+
+/// Mode bits git uses for gitlink (submodule) entries
+const GITLINK_MODE: u32 = 0o160000;
+
+/// Fixed-size part of an index entry before the object id: ten 32-bit fields
+const ENTRY_STAT_LEN: usize = 40;
+
+/// Flag marking an entry that carries a second, extended flags field (v3+)
+const EXTENDED_FLAG: u16 = 0x4000;
+
+/// Flag bits holding the merge stage
+const STAGE_MASK: u16 = 0x3000;
+const STAGE_SHIFT: u16 = 12;
+
+/// One index entry
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Entry<'a> {
+    pub(crate) mode: u32,
+    pub(crate) id: &'a [u8],
+    /// 0 for a merged path, 1 to 3 for base, ours and theirs during a conflict
+    pub(crate) stage: u8,
+    pub(crate) path: Vec<u8>,
+}
+
+impl Entry<'_> {
+    pub(crate) fn is_gitlink(&self) -> bool {
+        self.mode & TYPE_MASK == GITLINK_MODE
+    }
+
+    /// Object id as lowercase hex
+    pub(crate) fn hex_id(&self) -> String {
+        self.id.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
+
+/// Iterates over index entries, stopping early at the first malformed one
+pub(crate) struct Entries<'a> {
+    data: &'a [u8],
+    hash_len: usize,
+    version: u32,
+    remaining: u32,
+    offset: usize,
+    previous: Vec<u8>,
+}
+
+/// Entries of an index file, or None if the header is not a supported index
+///
+/// `hash_len` is 20 for SHA-1 repositories and 32 for SHA-256 ones.
+pub(crate) fn entries(data: &[u8], hash_len: usize) -> Option<Entries<'_>> {
+    if data.get(..4)? != b"DIRC" {
+        return None;
+    }
+    let version = be32(data, 4)?;
+    if !(2..=4).contains(&version) {
+        return None;
+    }
+    Some(Entries {
+        data,
+        hash_len,
+        version,
+        remaining: be32(data, 8)?,
+        offset: 12,
+        previous: Vec::new(),
+    })
+}
+
+impl<'a> Iterator for Entries<'a> {
+    type Item = Entry<'a>;
+
+    fn next(&mut self) -> Option<Entry<'a>> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let entry = self.read();
+        // A truncated entry leaves nothing sensible to read after it
+        self.remaining = if entry.is_some() {
+            self.remaining - 1
+        } else {
+            0
+        };
+        entry
+    }
+}
+
+impl<'a> Entries<'a> {
+    fn read(&mut self) -> Option<Entry<'a>> {
+        let data = self.data;
+        let start = self.offset;
+        let mode = be32(data, start + 24)?;
+        let id_start = start + ENTRY_STAT_LEN;
+        let id = data.get(id_start..id_start + self.hash_len)?;
+        let flags_at = id_start + self.hash_len;
+        let flags = u16::from_be_bytes(data.get(flags_at..flags_at + 2)?.try_into().ok()?);
+        let mut name_start = flags_at + 2;
+        if self.version >= 3 && flags & EXTENDED_FLAG != 0 {
+            name_start += 2;
+        }
+
+        let path = if self.version == 4 {
+            // Varint count of bytes to drop from the previous name, then the new suffix
+            let (strip, used) = varint(data.get(name_start..)?)?;
+            let suffix_start = name_start + used;
+            let suffix_len = data.get(suffix_start..)?.iter().position(|b| *b == 0)?;
+            let keep = self.previous.len().checked_sub(strip)?;
+            let mut path = self.previous[..keep].to_vec();
+            path.extend_from_slice(&data[suffix_start..suffix_start + suffix_len]);
+            self.offset = suffix_start + suffix_len + 1;
+            path
+        } else {
+            let name_len = data.get(name_start..)?.iter().position(|b| *b == 0)?;
+            let path = data[name_start..name_start + name_len].to_vec();
+            // Entries are NUL-padded to a multiple of eight bytes
+            let entry_len = name_start + name_len - start;
+            self.offset = start + (entry_len + 8) / 8 * 8;
+            path
+        };
+        self.previous.clone_from(&path);
+
+        Some(Entry {
+            mode,
+            id,
+            stage: ((flags & STAGE_MASK) >> STAGE_SHIFT) as u8,
+            path,
+        })
+    }
+}
+
+/// Number of distinct paths with unmerged (stage 1-3) entries
+pub(crate) fn conflicts(data: &[u8], hash_len: usize) -> Option<usize> {
+    let mut count = 0;
+    let mut last: Option<Vec<u8>> = None;
+    for entry in entries(data, hash_len)? {
+        // Stages of one path are adjacent, so counting changes of path suffices
+        if entry.stage != 0 && last.as_ref() != Some(&entry.path) {
+            count += 1;
+            last = Some(entry.path);
+        }
+    }
+    Some(count)
+}
+
+fn be32(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(bytes.get(at..at + 4)?.try_into().ok()?))
+}
+
+/// Git's offset varint: seven bits per byte, high bit continues, each
+/// continuation adding one so every value has a single encoding
+fn varint(bytes: &[u8]) -> Option<(usize, usize)> {
+    let mut value: usize = 0;
+    for (i, byte) in bytes.iter().enumerate() {
+        if i > 0 {
+            value = value.checked_add(1)?.checked_shl(7)?;
+        }
+        value |= (byte & 0x7f) as usize;
+        if byte & 0x80 == 0 {
+            return Some((value, i + 1));
+        }
+    }
+    None
+}
+
+/// Builds an index holding `entries` of (mode, id byte, stage, path)
+#[cfg(test)]
+pub(crate) fn build(version: u32, entries: &[(u32, u8, u8, &str)]) -> Vec<u8> {
+    let mut out = b"DIRC".to_vec();
+    out.extend(version.to_be_bytes());
+    out.extend((entries.len() as u32).to_be_bytes());
+    let mut previous = "";
+    for (i, (mode, id, stage, path)) in entries.iter().enumerate() {
+        let start = out.len();
+        out.extend([0u8; 24]);
+        out.extend(mode.to_be_bytes());
+        out.extend([0u8; 12]);
+        out.extend([*id; 20]);
+        // Mark every other entry as extended in v3 to exercise the extra field
+        let extended = version == 3 && i % 2 == 1;
+        let mut flags = (path.len().min(0xfff) as u16) | ((*stage as u16) << STAGE_SHIFT);
+        if extended {
+            flags |= EXTENDED_FLAG;
+        }
+        out.extend(flags.to_be_bytes());
+        if extended {
+            out.extend([0u8; 2]);
+        }
+        if version == 4 {
+            let common = previous
+                .bytes()
+                .zip(path.bytes())
+                .take_while(|(a, b)| a == b)
+                .count();
+            let strip = previous.len() - common;
+            assert!(strip < 0x80, "test helper only writes one-byte varints");
+            out.push(strip as u8);
+            out.extend(&path.as_bytes()[common..]);
+            out.push(0);
+        } else {
+            out.extend(path.as_bytes());
+            let len = out.len() - start;
+            out.resize(start + (len + 8) / 8 * 8, 0);
+        }
+        previous = path;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entries_in_each_index_version() {
+        let listed = [
+            (0o100644, 0x11, 0, "README.md"),
+            (0o100644, 0x21, 1, "src/lib.rs"),
+            (0o100644, 0x22, 2, "src/lib.rs"),
+            (0o100644, 0x23, 3, "src/lib.rs"),
+            (0o160000, 0xab, 0, "vendor/bar"),
+        ];
+        for version in [2, 3, 4] {
+            let index = build(version, &listed);
+            let read: Vec<(u8, String, bool)> = entries(&index, 20)
+                .unwrap()
+                .map(|e| {
+                    (
+                        e.stage,
+                        String::from_utf8(e.path.clone()).unwrap(),
+                        e.is_gitlink(),
+                    )
+                })
+                .collect();
+            assert_eq!(
+                read,
+                [
+                    (0, "README.md".to_string(), false),
+                    (1, "src/lib.rs".to_string(), false),
+                    (2, "src/lib.rs".to_string(), false),
+                    (3, "src/lib.rs".to_string(), false),
+                    (0, "vendor/bar".to_string(), true),
+                ],
+                "v{}",
+                version
+            );
+            assert_eq!(conflicts(&index, 20), Some(1), "v{}", version);
+        }
+        assert!(entries(b"DIRC\0\0\0\x09", 20).is_none());
+        assert!(entries(b"garbage", 20).is_none());
+        assert_eq!(entries(&build(2, &listed)[..100], 20).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_conflicts_counts_paths_not_stages() {
+        let index = build(
+            2,
+            &[
+                (0o100644, 1, 2, "a.txt"),
+                (0o100644, 2, 3, "a.txt"),
+                (0o100644, 3, 0, "b.txt"),
+                (0o100644, 4, 1, "c.txt"),
+                (0o100644, 5, 3, "c.txt"),
+                (0o100644, 6, 2, "d.txt"),
+            ],
+        );
+        assert_eq!(conflicts(&index, 20), Some(3));
+        assert_eq!(conflicts(&build(4, &[(0o100644, 1, 0, "a")]), 20), Some(0));
+    }
+
+    #[test]
+    fn test_varint() {
+        assert_eq!(varint(&[0x05]), Some((5, 1)));
+        assert_eq!(varint(&[0x80, 0x00]), Some((128, 2)));
+        assert_eq!(varint(&[0x81, 0x7f]), Some((383, 2)));
+        assert_eq!(varint(&[0x80]), None);
+    }
+}
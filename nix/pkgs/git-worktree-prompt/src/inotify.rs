@@ -0,0 +1,177 @@
+use std::ffi::{CString, OsString, c_char, c_int};
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::fd::FromRawFd;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::Path;
+
+// ============================================================================
+// Minimal inotify Bindings (Linux)
+// ============================================================================
+//
+// Just enough of the inotify(7) API to watch directories for changes without
+// pulling in a crate: init, add_watch and a blocking read that decodes the
+// variable-length event records.
+
+unsafe extern "C" {
+    fn inotify_init1(flags: c_int) -> c_int;
+    fn inotify_add_watch(fd: c_int, pathname: *const c_char, mask: u32) -> c_int;
+}
+
+const IN_CLOEXEC: c_int = 0o2000000;
+
+pub(crate) const IN_MODIFY: u32 = 0x0000_0002;
+pub(crate) const IN_ATTRIB: u32 = 0x0000_0004;
+pub(crate) const IN_CLOSE_WRITE: u32 = 0x0000_0008;
+pub(crate) const IN_MOVED_FROM: u32 = 0x0000_0040;
+pub(crate) const IN_MOVED_TO: u32 = 0x0000_0080;
+pub(crate) const IN_CREATE: u32 = 0x0000_0100;
+pub(crate) const IN_DELETE: u32 = 0x0000_0200;
+pub(crate) const IN_DELETE_SELF: u32 = 0x0000_0400;
+pub(crate) const IN_MOVE_SELF: u32 = 0x0000_0800;
+pub(crate) const IN_Q_OVERFLOW: u32 = 0x0000_4000;
+pub(crate) const IN_IGNORED: u32 = 0x0000_8000;
+pub(crate) const IN_ONLYDIR: u32 = 0x0100_0000;
+pub(crate) const IN_ISDIR: u32 = 0x4000_0000;
+
+/// Mask covering every change to a directory's entries or the directory itself
+pub(crate) const DIR_CHANGES: u32 = IN_MODIFY
+    | IN_ATTRIB
+    | IN_CLOSE_WRITE
+    | IN_MOVED_FROM
+    | IN_MOVED_TO
+    | IN_CREATE
+    | IN_DELETE
+    | IN_DELETE_SELF
+    | IN_MOVE_SELF
+    | IN_ONLYDIR;
+
+/// Size of the fixed part of `struct inotify_event`
+const EVENT_HEADER_LEN: usize = 16;
+
+/// A decoded inotify event
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Event {
+    pub(crate) wd: i32,
+    pub(crate) mask: u32,
+    /// Entry name within the watched directory, if any
+    pub(crate) name: Option<OsString>,
+}
+
+/// An inotify instance; reads block until at least one event is available
+#[derive(Debug)]
+pub(crate) struct Inotify {
+    file: File,
+}
+
+impl Inotify {
+    pub(crate) fn new() -> io::Result<Inotify> {
+        let fd = unsafe { inotify_init1(IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: fd is a freshly created descriptor we exclusively own
+        let file = unsafe { File::from_raw_fd(fd) };
+        Ok(Inotify { file })
+    }
+
+    /// Watches a directory, returning its watch descriptor
+    pub(crate) fn add_watch(&self, dir: &Path, mask: u32) -> io::Result<i32> {
+        use std::os::fd::AsRawFd;
+
+        let path = CString::new(dir.as_os_str().as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let wd = unsafe { inotify_add_watch(self.file.as_raw_fd(), path.as_ptr(), mask) };
+        if wd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(wd)
+    }
+
+    /// Blocks until events arrive and returns all of them
+    pub(crate) fn read_events(&self) -> io::Result<Vec<Event>> {
+        let mut buf = [0u8; 4096];
+        let len = (&self.file).read(&mut buf)?;
+        Ok(parse_events(&buf[..len]))
+    }
+}
+
+/// Decodes a buffer of packed `struct inotify_event` records
+fn parse_events(mut buf: &[u8]) -> Vec<Event> {
+    let mut events = Vec::new();
+
+    while buf.len() >= EVENT_HEADER_LEN {
+        let field = |i: usize| [buf[i], buf[i + 1], buf[i + 2], buf[i + 3]];
+        let wd = i32::from_ne_bytes(field(0));
+        let mask = u32::from_ne_bytes(field(4));
+        let name_len = u32::from_ne_bytes(field(12)) as usize;
+
+        let Some(name_bytes) = buf.get(EVENT_HEADER_LEN..EVENT_HEADER_LEN + name_len) else {
+            break;
+        };
+        // Names are NUL-padded to alignment
+        let name_bytes: Vec<u8> = name_bytes.iter().copied().take_while(|b| *b != 0).collect();
+        let name = (!name_bytes.is_empty()).then(|| OsString::from_vec(name_bytes));
+
+        events.push(Event { wd, mask, name });
+        buf = &buf[EVENT_HEADER_LEN + name_len..];
+    }
+
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn raw_event(wd: i32, mask: u32, name: &str) -> Vec<u8> {
+        let padded_len = if name.is_empty() {
+            0
+        } else {
+            (name.len() + 1).next_multiple_of(16)
+        };
+        let mut raw = Vec::new();
+        raw.extend(wd.to_ne_bytes());
+        raw.extend(mask.to_ne_bytes());
+        raw.extend(0u32.to_ne_bytes());
+        raw.extend((padded_len as u32).to_ne_bytes());
+        let mut name = name.as_bytes().to_vec();
+        name.resize(padded_len, 0);
+        raw.extend(name);
+        raw
+    }
+
+    #[test]
+    fn test_parse_events() {
+        let mut buf = raw_event(1, IN_CREATE, "HEAD.lock");
+        buf.extend(raw_event(1, IN_MOVED_TO, "HEAD"));
+        buf.extend(raw_event(2, IN_IGNORED, ""));
+
+        let events = parse_events(&buf);
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0].name.as_deref(), Some("HEAD.lock".as_ref()));
+        assert_eq!(events[1].mask, IN_MOVED_TO);
+        assert_eq!(events[2].wd, 2);
+        assert_eq!(events[2].name, None);
+    }
+
+    #[test]
+    fn test_watch_reports_created_file() {
+        let dir = TempDir::new().unwrap();
+        let inotify = Inotify::new().unwrap();
+        let wd = inotify.add_watch(dir.path(), DIR_CHANGES).unwrap();
+
+        fs::write(dir.path().join("HEAD"), "ref: refs/heads/main\n").unwrap();
+
+        let events = inotify.read_events().unwrap();
+        assert!(
+            events
+                .iter()
+                .any(|e| e.wd == wd && e.name.as_deref() == Some("HEAD".as_ref())),
+            "Expected an event for HEAD, got {:?}",
+            events
+        );
+    }
+}
@@ -1,8 +1,8 @@
 use crate::*;
-use serial_test::serial;
-use std::env;
+use git_worktree_prompt::Repository;
 use std::fs;
 use std::process::Command;
+use std::time::{Duration, SystemTime};
 use tempfile::TempDir;
 
 fn create_test_repo() -> TempDir {
@@ -45,14 +45,9 @@ fn create_test_repo() -> TempDir {
 }
 
 #[test]
-#[serial]
 fn test_regular_repo_on_main() {
     let repo_dir = create_test_repo();
-    let original_dir = env::current_dir().unwrap();
-
-    env::set_current_dir(repo_dir.path()).unwrap();
-    let result = run();
-    env::set_current_dir(original_dir).unwrap();
+    let result = try_render_in(repo_dir.path(), &Env::new());
 
     assert!(result.is_ok(), "run() should succeed in a git repository");
     let output = result.unwrap();
@@ -70,14 +65,9 @@ fn test_regular_repo_on_main() {
 }
 
 #[test]
-#[serial]
 fn test_non_git_directory() {
     let temp_dir = TempDir::new().unwrap();
-    let original_dir = env::current_dir().unwrap();
-
-    env::set_current_dir(temp_dir.path()).unwrap();
-    let result = run();
-    env::set_current_dir(original_dir).unwrap();
+    let result = try_render_in(temp_dir.path(), &Env::new());
 
     assert!(result.is_ok(), "run() should succeed in non-git directory");
     let output = result.unwrap();
@@ -88,7 +78,6 @@ fn test_non_git_directory() {
 }
 
 #[test]
-#[serial]
 fn test_detached_head_state() {
     let repo_dir = create_test_repo();
     let path = repo_dir.path();
@@ -108,10 +97,7 @@ fn test_detached_head_state() {
         .output()
         .unwrap();
 
-    let original_dir = env::current_dir().unwrap();
-    env::set_current_dir(path).unwrap();
-    let result = run();
-    env::set_current_dir(original_dir).unwrap();
+    let result = try_render_in(path, &Env::new());
 
     assert!(
         result.is_ok(),
@@ -140,17 +126,11 @@ fn test_detached_head_state() {
 }
 
 #[test]
-#[serial]
 fn test_log_error_creates_file_and_directory() {
     let temp_dir = TempDir::new().unwrap();
-    let original_xdg = env::var("XDG_STATE_HOME").ok();
-
-    unsafe {
-        env::set_var("XDG_STATE_HOME", temp_dir.path());
-    }
 
-    let error = Error::from_str("Test error message");
-    log_error(&error);
+    let error = Error::command("Test error message");
+    log_error(&error, temp_dir.path(), &state_env(&temp_dir));
 
     let expected_dir = temp_dir.path().join(APP_STATE_DIR);
     assert!(expected_dir.exists(), "Log directory should be created");
@@ -167,30 +147,18 @@ fn test_log_error_creates_file_and_directory() {
     );
     assert!(content.contains("["), "Log should contain timestamp prefix");
     assert!(content.contains("]"), "Log should contain timestamp suffix");
-
-    unsafe {
-        match original_xdg {
-            Some(val) => env::set_var("XDG_STATE_HOME", val),
-            None => env::remove_var("XDG_STATE_HOME"),
-        }
-    }
 }
 
 #[test]
-#[serial]
 fn test_log_error_appends_multiple_entries() {
     let temp_dir = TempDir::new().unwrap();
-    let original_xdg = env::var("XDG_STATE_HOME").ok();
-
-    unsafe {
-        env::set_var("XDG_STATE_HOME", temp_dir.path());
-    }
+    let env = state_env(&temp_dir);
 
-    let error1 = Error::from_str("First error");
-    log_error(&error1);
+    let error1 = Error::command("First error");
+    log_error(&error1, temp_dir.path(), &env);
 
-    let error2 = Error::from_str("Second error");
-    log_error(&error2);
+    let error2 = Error::command("Second error");
+    log_error(&error2, temp_dir.path(), &env);
 
     let expected_log = temp_dir.path().join(APP_STATE_DIR).join(ERROR_LOG_FILE);
     let content = fs::read_to_string(&expected_log).unwrap();
@@ -209,29 +177,15 @@ fn test_log_error_appends_multiple_entries() {
         open_brackets >= 2,
         "Should have at least 2 timestamp entries"
     );
-
-    unsafe {
-        match original_xdg {
-            Some(val) => env::set_var("XDG_STATE_HOME", val),
-            None => env::remove_var("XDG_STATE_HOME"),
-        }
-    }
 }
 
 #[test]
-#[serial]
 fn test_log_error_uses_home_fallback() {
     let temp_dir = TempDir::new().unwrap();
-    let original_home = env::var("HOME").ok();
-    let original_xdg = env::var("XDG_STATE_HOME").ok();
+    let env = Env::new().with("HOME", temp_dir.path().to_str().unwrap());
 
-    unsafe {
-        env::remove_var("XDG_STATE_HOME");
-        env::set_var("HOME", temp_dir.path());
-    }
-
-    let error = Error::from_str("Fallback test error");
-    log_error(&error);
+    let error = Error::command("Fallback test error");
+    log_error(&error, temp_dir.path(), &env);
 
     let expected_log = temp_dir
         .path()
@@ -250,21 +204,9 @@ fn test_log_error_uses_home_fallback() {
         content.contains("Fallback test error"),
         "Log should contain error message"
     );
-
-    unsafe {
-        match original_home {
-            Some(val) => env::set_var("HOME", val),
-            None => env::remove_var("HOME"),
-        }
-        match original_xdg {
-            Some(val) => env::set_var("XDG_STATE_HOME", val),
-            None => env::remove_var("XDG_STATE_HOME"),
-        }
-    }
 }
 
 #[test]
-#[serial]
 fn test_bare_adjacent_directory() {
     // Create a worktree-style setup with .bare directory
     let temp_dir = TempDir::new().unwrap();
@@ -319,10 +261,7 @@ fn test_bare_adjacent_directory() {
         .unwrap();
 
     // Now test from the bare parent directory (not in any worktree)
-    let original_dir = env::current_dir().unwrap();
-    env::set_current_dir(path).unwrap();
-    let result = run();
-    env::set_current_dir(original_dir).unwrap();
+    let result = try_render_in(path, &Env::new());
 
     assert!(
         result.is_ok(),
@@ -343,39 +282,35 @@ fn test_bare_adjacent_directory() {
 }
 
 #[test]
-#[serial]
 fn test_corrupted_git_head() {
     let repo_dir = create_test_repo();
     let path = repo_dir.path();
 
     // Setup temporary log directory to capture errors
     let temp_log_dir = TempDir::new().unwrap();
-    let original_xdg = env::var("XDG_STATE_HOME").ok();
-
-    unsafe {
-        env::set_var("XDG_STATE_HOME", temp_log_dir.path());
-    }
+    let env = state_env(&temp_log_dir);
 
-    // Corrupt the .git/HEAD file with invalid content (too short to be valid)
-    // Must be < 7 characters to trigger "Unknown HEAD format" error
+    // Corrupt the .git/HEAD file with content that is neither a ref nor an object id
     let head_path = path.join(".git").join("HEAD");
     fs::write(&head_path, "bad\n").unwrap();
 
-    // Change to repo directory and run
-    let original_dir = env::current_dir().unwrap();
-    env::set_current_dir(path).unwrap();
-    let result = run();
-    env::set_current_dir(original_dir).unwrap();
+    let result = try_render_in(path, &env);
 
     // Verify run() returns Err (graceful failure, not panic)
     assert!(
         result.is_err(),
         "run() should return Err for corrupted .git/HEAD"
     );
+    match &result {
+        Err(Error::UnknownHeadFormat { path }) => {
+            assert_eq!(path, &repo_dir.path().join(".git").join("HEAD"))
+        }
+        other => panic!("Expected UnknownHeadFormat, got {:?}", other),
+    }
 
     // Log the error (mimicking what main() does)
     if let Err(e) = result {
-        log_error(&e);
+        log_error(&e, path, &env);
     }
 
     // Verify error was logged
@@ -398,18 +333,17 @@ fn test_corrupted_git_head() {
         content.contains("["),
         "Error log should contain timestamp prefix"
     );
-
-    // Cleanup environment
-    unsafe {
-        match original_xdg {
-            Some(val) => env::set_var("XDG_STATE_HOME", val),
-            None => env::remove_var("XDG_STATE_HOME"),
-        }
-    }
+    assert!(
+        content.contains("Z [head] cwd="),
+        "Error log should contain an RFC 3339 timestamp and the error category"
+    );
+    assert!(
+        content.contains(&path.display().to_string()),
+        "Error log should contain the working directory"
+    );
 }
 
 #[test]
-#[serial]
 fn test_worktree_subdirectory_shows_only_worktree_name() {
     // Create a worktree-style setup with .bare directory
     let temp_dir = TempDir::new().unwrap();
@@ -468,10 +402,7 @@ fn test_worktree_subdirectory_shows_only_worktree_name() {
     fs::create_dir_all(&subdir).unwrap();
 
     // Test from deep subdirectory - should show only worktree name, not full path
-    let original_dir = env::current_dir().unwrap();
-    env::set_current_dir(&subdir).unwrap();
-    let result = run();
-    env::set_current_dir(original_dir).unwrap();
+    let result = try_render_in(&subdir, &Env::new());
 
     assert!(
         result.is_ok(),
@@ -493,3 +424,1860 @@ fn test_worktree_subdirectory_shows_only_worktree_name() {
         output_text
     );
 }
+
+/// Runs `git` with the given args in `dir`, panicking on failure
+fn git(dir: &std::path::Path, args: &[&str]) {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "git {:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+/// Renders the prompt for `dir` with `env` as its whole environment, so the
+/// test needs neither `#[serial]` nor a change of working directory
+fn try_render_in(dir: &std::path::Path, env: &Env) -> Result<Option<String>, Error> {
+    render(dir, env, &mut budget::Budget::from_env(env))
+}
+
+/// `try_render_in` for renders expected to succeed
+fn render_in(dir: &std::path::Path, env: &Env) -> Option<String> {
+    try_render_in(dir, env).unwrap()
+}
+
+/// Environment whose runtime directory, holding the discovery cache, is `dir`
+fn runtime_env(dir: &TempDir) -> Env {
+    Env::new().with("XDG_RUNTIME_DIR", dir.path().to_str().unwrap())
+}
+
+/// Environment whose state directory, holding the error log and status
+/// snapshots, is `dir`
+fn state_env(dir: &TempDir) -> Env {
+    Env::new().with("XDG_STATE_HOME", dir.path().to_str().unwrap())
+}
+
+/// Files the prompt keeps in the runtime directory `dir`
+fn runtime_entries(dir: &TempDir) -> Vec<std::path::PathBuf> {
+    match fs::read_dir(dir.path().join(APP_STATE_DIR)) {
+        Ok(entries) => entries.map(|e| e.unwrap().path()).collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+#[test]
+fn test_cache_hit_reuses_entry() {
+    let runtime = TempDir::new().unwrap();
+    let env = runtime_env(&runtime);
+    let repo_dir = create_test_repo();
+    git(repo_dir.path(), &["checkout", "-b", "cached"]);
+
+    assert_eq!(
+        render_in(repo_dir.path(), &env).as_deref(),
+        Some("⎇ cached")
+    );
+    let entries = runtime_entries(&runtime);
+    assert_eq!(entries.len(), 1, "First run should write one cache entry");
+
+    let written = fs::read_to_string(&entries[0]).unwrap();
+    assert_eq!(
+        render_in(repo_dir.path(), &env).as_deref(),
+        Some("⎇ cached")
+    );
+    assert_eq!(
+        fs::read_to_string(&entries[0]).unwrap(),
+        written,
+        "Cache hit should not rewrite the entry"
+    );
+}
+
+#[test]
+fn test_cache_invalidated_by_branch_switch() {
+    let runtime = TempDir::new().unwrap();
+    let env = runtime_env(&runtime);
+    let repo_dir = create_test_repo();
+    git(repo_dir.path(), &["checkout", "-b", "first"]);
+
+    assert_eq!(render_in(repo_dir.path(), &env).as_deref(), Some("⎇ first"));
+    git(repo_dir.path(), &["checkout", "-b", "second"]);
+    assert_eq!(
+        render_in(repo_dir.path(), &env).as_deref(),
+        Some("⎇ second")
+    );
+    git(repo_dir.path(), &["checkout", "first"]);
+    assert_eq!(render_in(repo_dir.path(), &env).as_deref(), Some("⎇ first"));
+}
+
+#[test]
+fn test_cache_invalidated_by_detached_head() {
+    let runtime = TempDir::new().unwrap();
+    let env = runtime_env(&runtime);
+    let repo_dir = create_test_repo();
+    git(repo_dir.path(), &["checkout", "-b", "attached"]);
+    assert_eq!(
+        render_in(repo_dir.path(), &env).as_deref(),
+        Some("⎇ attached")
+    );
+
+    git(repo_dir.path(), &["checkout", "--detach"]);
+    let output = render_in(repo_dir.path(), &env).unwrap();
+    assert_ne!(
+        output, "⎇ attached",
+        "Detaching HEAD should invalidate cache"
+    );
+    assert_eq!(output.len(), 11, "Expected '⎇ <hash>', got '{}'", output);
+}
+
+#[test]
+fn test_cache_invalidated_by_nested_repo() {
+    let runtime = TempDir::new().unwrap();
+    let env = runtime_env(&runtime);
+    let repo_dir = create_test_repo();
+    git(repo_dir.path(), &["checkout", "-b", "outer"]);
+    let nested = repo_dir.path().join("vendor").join("nested");
+    fs::create_dir_all(&nested).unwrap();
+
+    assert_eq!(render_in(&nested, &env).as_deref(), Some("⎇ outer"));
+    git(&nested, &["init", "-b", "inner"]);
+    assert_eq!(render_in(&nested, &env).as_deref(), Some("⎇ inner"));
+    fs::remove_dir_all(nested.join(".git")).unwrap();
+    assert_eq!(render_in(&nested, &env).as_deref(), Some("⎇ outer"));
+}
+
+#[test]
+fn test_cache_invalidated_by_gitdir_pointer_change() {
+    let runtime = TempDir::new().unwrap();
+    let env = runtime_env(&runtime);
+    let repo_dir = create_test_repo();
+    let base = repo_dir.path();
+    let linked = base.join("linked");
+    git(base, &["worktree", "add", "-b", "one", "linked"]);
+    git(base, &["worktree", "add", "-b", "two", "other"]);
+
+    assert_eq!(render_in(&linked, &env).as_deref(), Some("⎇ one"));
+
+    // Repoint the worktree at another admin directory without touching HEAD,
+    // keeping the back-pointer consistent so it isn't reported as broken
+    let admin = base.join(".git").join("worktrees").join("other");
+    fs::write(
+        linked.join(".git"),
+        format!("gitdir: {}\n", admin.display()),
+    )
+    .unwrap();
+    fs::write(
+        admin.join("gitdir"),
+        format!("{}\n", linked.join(".git").display()),
+    )
+    .unwrap();
+    assert_eq!(render_in(&linked, &env).as_deref(), Some("⎇ two"));
+}
+
+#[test]
+fn test_cache_ignores_corrupt_entry() {
+    let runtime = TempDir::new().unwrap();
+    let env = runtime_env(&runtime);
+    let repo_dir = create_test_repo();
+    git(repo_dir.path(), &["checkout", "-b", "sturdy"]);
+
+    assert_eq!(
+        render_in(repo_dir.path(), &env).as_deref(),
+        Some("⎇ sturdy")
+    );
+    let entries = runtime_entries(&runtime);
+    fs::write(&entries[0], "not a cache entry").unwrap();
+    assert_eq!(
+        render_in(repo_dir.path(), &env).as_deref(),
+        Some("⎇ sturdy")
+    );
+}
+
+#[test]
+fn test_cache_disabled_by_env() {
+    let runtime = TempDir::new().unwrap();
+    let env = runtime_env(&runtime).with(cache::ENV_CACHE, "0");
+    let repo_dir = create_test_repo();
+
+    let output = render_in(repo_dir.path(), &env);
+
+    assert!(output.is_some());
+    assert!(
+        runtime_entries(&runtime).is_empty(),
+        "Disabled cache should not write"
+    );
+}
+
+/// Renders the prompt for `dir` with `env` as its whole environment and the given budget
+fn render_budgeted(
+    dir: &std::path::Path,
+    env: &Env,
+    budget: &mut budget::Budget,
+) -> Option<String> {
+    render(dir, env, budget).unwrap()
+}
+
+#[test]
+fn test_status_segment_counts_changes() {
+    let env = Env::new().with(status::ENV_STATUS, "1");
+    let repo_dir = create_test_repo();
+    let path = repo_dir.path();
+    git(path, &["checkout", "-b", "work"]);
+
+    fs::write(path.join("staged.txt"), "staged").unwrap();
+    git(path, &["add", "staged.txt"]);
+    fs::write(path.join("test.txt"), "changed").unwrap();
+    fs::write(path.join("untracked.txt"), "new").unwrap();
+
+    let mut budget = budget::Budget::new(std::time::Duration::from_secs(30));
+    assert_eq!(
+        render_budgeted(path, &env, &mut budget).as_deref(),
+        Some("⎇ work +1 !1 %1")
+    );
+
+    let status_phase = budget.phases().iter().find(|p| p.name == "status");
+    assert!(
+        !status_phase.unwrap().cut,
+        "Status should finish within 30s"
+    );
+}
+
+#[test]
+fn test_status_segment_clean_tree_adds_nothing() {
+    let env = Env::new().with(status::ENV_STATUS, "1");
+    let repo_dir = create_test_repo();
+    git(repo_dir.path(), &["checkout", "-b", "tidy"]);
+
+    let mut budget = budget::Budget::new(std::time::Duration::from_secs(30));
+    assert_eq!(
+        render_budgeted(repo_dir.path(), &env, &mut budget).as_deref(),
+        Some("⎇ tidy")
+    );
+}
+
+#[test]
+fn test_status_segment_skipped_when_budget_exhausted() {
+    let env = Env::new().with(status::ENV_STATUS, "1");
+    let repo_dir = create_test_repo();
+    git(repo_dir.path(), &["checkout", "-b", "hurried"]);
+
+    let mut budget = budget::Budget::new(std::time::Duration::ZERO);
+    assert_eq!(
+        render_budgeted(repo_dir.path(), &env, &mut budget).as_deref(),
+        Some("⎇ hurried ?"),
+        "Discovery output should survive with a skip marker for status"
+    );
+
+    let phases = budget.phases();
+    assert!(phases.iter().any(|p| p.name == "status" && p.cut));
+    assert!(phases.iter().all(|p| p.name == "status" || !p.cut));
+}
+
+/// Creates a lock file so async rendering doesn't spawn a refresh child
+fn hold_refresh_lock(state_dir: &std::path::Path, work_dir: &std::path::Path) {
+    let lock = refresh::lock_path(&state_dir.join(APP_STATE_DIR), work_dir);
+    fs::create_dir_all(lock.parent().unwrap()).unwrap();
+    fs::write(lock, "").unwrap();
+}
+
+#[test]
+fn test_async_status_uses_fresh_snapshot() {
+    let state_dir = TempDir::new().unwrap();
+    let env = state_env(&state_dir).with(status::ENV_STATUS, "async");
+    let repo_dir = create_test_repo();
+    let path = repo_dir.path();
+    git(path, &["checkout", "-b", "work"]);
+    fs::write(path.join("test.txt"), "changed").unwrap();
+
+    refresh::refresh(path, &env).unwrap();
+    let state = state_dir.path().join(APP_STATE_DIR);
+    assert!(
+        refresh::snapshot_path(&state, path).exists(),
+        "refresh should write a snapshot"
+    );
+
+    assert_eq!(render_in(path, &env).as_deref(), Some("⎇ work !1"));
+}
+
+#[test]
+fn test_async_status_snapshot_reads_are_traced() {
+    let state_dir = TempDir::new().unwrap();
+    let env = state_env(&state_dir).with(cache::ENV_CACHE, "0");
+    let repo_dir = create_test_repo();
+    let path = repo_dir.path();
+
+    trace::enable();
+    render_in(path, &env);
+    let sync = trace::take().unwrap();
+
+    let env = env.with(status::ENV_STATUS, "async");
+    refresh::refresh(path, &env).unwrap();
+    trace::enable();
+    render_in(path, &env);
+    let trace = trace::take().unwrap();
+
+    // The snapshot read and the refresh lock check both show up in --debug
+    assert!(
+        trace.fs.read > sync.fs.read,
+        "{:?} vs {:?}",
+        trace.fs,
+        sync.fs
+    );
+    assert!(
+        trace.fs.stat > sync.fs.stat,
+        "{:?} vs {:?}",
+        trace.fs,
+        sync.fs
+    );
+}
+
+#[test]
+fn test_async_status_marks_stale_after_index_change() {
+    let state_dir = TempDir::new().unwrap();
+    let env = state_env(&state_dir).with(status::ENV_STATUS, "async");
+    let repo_dir = create_test_repo();
+    let path = repo_dir.path();
+    git(path, &["checkout", "-b", "work"]);
+    fs::write(path.join("test.txt"), "changed").unwrap();
+    refresh::refresh(path, &env).unwrap();
+
+    // Staging rewrites the index, so the snapshot is shown as last-known
+    git(path, &["add", "test.txt"]);
+    hold_refresh_lock(state_dir.path(), path);
+    assert_eq!(render_in(path, &env).as_deref(), Some("⎇ work ~!1"));
+}
+
+#[test]
+fn test_async_status_marks_stale_after_tracking_ref_moves() {
+    let state_dir = TempDir::new().unwrap();
+    let env = state_env(&state_dir).with(status::ENV_STATUS, "async");
+    let repo_dir = create_test_repo();
+    let path = repo_dir.path();
+    git(path, &["checkout", "-b", "work"]);
+    git(path, &["config", "remote.origin.url", "/nowhere"]);
+    git(
+        path,
+        &[
+            "config",
+            "remote.origin.fetch",
+            "+refs/heads/*:refs/remotes/origin/*",
+        ],
+    );
+    git(path, &["config", "branch.work.remote", "origin"]);
+    git(path, &["config", "branch.work.merge", "refs/heads/work"]);
+    git(path, &["commit", "-q", "--allow-empty", "-m", "second"]);
+    git(path, &["update-ref", "refs/remotes/origin/work", "HEAD~1"]);
+    refresh::refresh(path, &env).unwrap();
+    assert_eq!(render_in(path, &env).as_deref(), Some("⎇ work ⇡1"));
+
+    // A push or fetch moves only the tracking ref
+    git(path, &["update-ref", "refs/remotes/origin/work", "HEAD"]);
+    hold_refresh_lock(state_dir.path(), path);
+    assert_eq!(render_in(path, &env).as_deref(), Some("⎇ work ~⇡1"));
+}
+
+#[test]
+fn test_failed_refresh_keeps_lock_to_back_off() {
+    let state_dir = TempDir::new().unwrap();
+    let env = state_env(&state_dir);
+    let repo_dir = create_test_repo();
+    let path = repo_dir.path();
+    fs::write(path.join(".git/index"), "garbage").unwrap();
+
+    assert!(
+        refresh::refresh(path, &env).is_err(),
+        "git status rejects the index"
+    );
+    let lock = refresh::lock_path(&state_dir.path().join(APP_STATE_DIR), path);
+    assert!(
+        lock.exists(),
+        "The lock stays so prompts don't respawn at once"
+    );
+    assert!(
+        refresh::refresh(path, &env).is_ok(),
+        "Retries back off silently"
+    );
+}
+
+#[test]
+fn test_async_status_marks_stale_after_max_age() {
+    let state_dir = TempDir::new().unwrap();
+    let env = state_env(&state_dir)
+        .with(status::ENV_STATUS, "async")
+        .with(refresh::ENV_REFRESH_SECS, "0");
+    let repo_dir = create_test_repo();
+    let path = repo_dir.path();
+    git(path, &["checkout", "-b", "work"]);
+    refresh::refresh(path, &env).unwrap();
+
+    hold_refresh_lock(state_dir.path(), path);
+    assert_eq!(render_in(path, &env).as_deref(), Some("⎇ work ~"));
+}
+
+#[test]
+fn test_async_status_without_snapshot_shows_skip_marker() {
+    let state_dir = TempDir::new().unwrap();
+    let env = state_env(&state_dir).with(status::ENV_STATUS, "async");
+    let repo_dir = create_test_repo();
+    let path = repo_dir.path();
+    git(path, &["checkout", "-b", "work"]);
+
+    hold_refresh_lock(state_dir.path(), path);
+    assert_eq!(render_in(path, &env).as_deref(), Some("⎇ work ?"));
+}
+
+/// Polls `check` for up to two seconds
+fn eventually(mut check: impl FnMut() -> bool) -> bool {
+    for _ in 0..200 {
+        if check() {
+            return true;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    false
+}
+
+#[test]
+fn test_daemon_query_falls_back_without_daemon() {
+    let runtime = TempDir::new().unwrap();
+    let env = runtime_env(&runtime);
+    let repo_dir = create_test_repo();
+    let timeout = std::time::Duration::from_secs(1);
+
+    assert!(daemon::query(repo_dir.path(), &env, false, timeout).is_none());
+    assert!(
+        render_in(repo_dir.path(), &env).is_some(),
+        "render() should fall back"
+    );
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_daemon_serves_and_invalidates_on_branch_switch() {
+    let runtime = TempDir::new().unwrap();
+    let env = runtime_env(&runtime);
+    let repo_dir = create_test_repo();
+    let path = fs::canonicalize(repo_dir.path()).unwrap();
+    git(&path, &["checkout", "-b", "before"]);
+    let timeout = std::time::Duration::from_secs(1);
+
+    let server = {
+        let env = env.clone();
+        std::thread::spawn(move || daemon::serve(&env))
+    };
+    let head_name = || {
+        daemon::query(&path, &env, false, timeout)
+            .and_then(|reply| reply.discovery)
+            .map(|discovery| discovery.head.name)
+    };
+
+    assert!(
+        eventually(|| head_name().is_some()),
+        "daemon should start answering queries"
+    );
+    assert_eq!(head_name().as_deref(), Some("before"));
+
+    git(&path, &["checkout", "-b", "after"]);
+    assert!(
+        eventually(|| head_name().as_deref() == Some("after")),
+        "HEAD change should invalidate the daemon's entry"
+    );
+
+    // Outside a repository the daemon answers with an empty result
+    let outside = TempDir::new().unwrap();
+    let reply = daemon::query(outside.path(), &env, false, timeout).unwrap();
+    assert_eq!(reply.discovery, None);
+
+    assert!(daemon::shutdown(&env));
+    server.join().unwrap().unwrap();
+    assert!(
+        daemon::query(&path, &env, false, timeout).is_none(),
+        "Stopped daemon should remove its socket"
+    );
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_daemon_keeps_status_warm() {
+    let runtime = TempDir::new().unwrap();
+    let env = runtime_env(&runtime);
+    let repo_dir = create_test_repo();
+    let path = fs::canonicalize(repo_dir.path()).unwrap();
+    fs::write(path.join("untracked.txt"), "new").unwrap();
+    let timeout = std::time::Duration::from_secs(1);
+
+    let server = {
+        let env = env.clone();
+        std::thread::spawn(move || daemon::serve(&env))
+    };
+    let status = || daemon::query(&path, &env, true, timeout).and_then(|reply| reply.status);
+
+    assert!(
+        eventually(|| matches!(status(), Some(Segment::Ready(_)))),
+        "daemon should compute status in the background"
+    );
+    let Some(Segment::Ready(ready)) = status() else {
+        unreachable!()
+    };
+    assert_eq!(ready.untracked, 1);
+
+    // Staging rewrites the index: last-known value until recomputed
+    git(&path, &["add", "untracked.txt"]);
+    assert!(
+        eventually(|| matches!(status(), Some(Segment::Ready(s)) if s.staged == 1)),
+        "index change should trigger a recompute"
+    );
+
+    assert!(daemon::shutdown(&env));
+    server.join().unwrap().unwrap();
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_daemon_status_sees_edits_in_subdirectories() {
+    let runtime = TempDir::new().unwrap();
+    let env = runtime_env(&runtime);
+    let repo_dir = create_test_repo();
+    let path = fs::canonicalize(repo_dir.path()).unwrap();
+    fs::create_dir(path.join("sub")).unwrap();
+    fs::write(path.join("sub/g"), "tracked\n").unwrap();
+    git(&path, &["add", "sub/g"]);
+    git(&path, &["commit", "-q", "-m", "sub"]);
+    let timeout = std::time::Duration::from_secs(1);
+
+    let server = {
+        let env = env.clone();
+        std::thread::spawn(move || daemon::serve(&env))
+    };
+    let status = || daemon::query(&path, &env, true, timeout).and_then(|reply| reply.status);
+    assert!(
+        eventually(|| matches!(status(), Some(Segment::Ready(s)) if s.modified == 0)),
+        "daemon should compute a clean status"
+    );
+
+    // inotify is not recursive: the daemon must watch sub/ itself
+    fs::write(path.join("sub/g"), "tracked\nedited\n").unwrap();
+    assert!(
+        eventually(|| matches!(status(), Some(Segment::Ready(s)) if s.modified == 1)),
+        "an edit in a subdirectory should invalidate the status"
+    );
+
+    // Directories created after the walk are watched as they appear
+    fs::create_dir_all(path.join("sub/new/deeper")).unwrap();
+    fs::write(path.join("sub/new/deeper/h"), "new\n").unwrap();
+    assert!(
+        eventually(|| matches!(status(), Some(Segment::Ready(s)) if s.untracked == 1)),
+        "a new file in a new directory should invalidate the status"
+    );
+    fs::write(path.join("sub/new/deeper/h"), "changed\n").unwrap();
+    git(&path, &["add", "sub/new/deeper/h"]);
+    assert!(
+        eventually(|| matches!(status(), Some(Segment::Ready(s)) if s.staged == 1)),
+        "the new directory is watched"
+    );
+
+    assert!(daemon::shutdown(&env));
+    server.join().unwrap().unwrap();
+}
+
+/// Collects lines written by watch mode for inspection from the test thread
+#[derive(Clone, Default)]
+struct SharedLines(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+impl std::io::Write for SharedLines {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl SharedLines {
+    fn lines(&self) -> Vec<String> {
+        let buf = self.0.lock().unwrap();
+        String::from_utf8_lossy(&buf)
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_watch_streams_changes_and_exits_when_directory_disappears() {
+    let env = Env::new().with(daemon::ENV_DAEMON, "0");
+    let repo_dir = create_test_repo();
+    let path = repo_dir.path().to_path_buf();
+    git(&path, &["checkout", "-b", "first"]);
+
+    let output = SharedLines::default();
+    let watcher = {
+        let path = path.clone();
+        let mut output = output.clone();
+        std::thread::spawn(move || watch::watch(&path, &env, &mut output))
+    };
+
+    assert!(eventually(|| output.lines() == ["⎇ first"]));
+
+    git(&path, &["checkout", "-b", "second"]);
+    assert!(
+        eventually(|| output.lines() == ["⎇ first", "⎇ second"]),
+        "Branch switch should stream exactly one new line, got {:?}",
+        output.lines()
+    );
+
+    // Changes that don't alter the rendered output print nothing
+    fs::write(path.join("test.txt"), "edited").unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(50));
+    assert_eq!(output.lines().len(), 2);
+
+    fs::remove_dir_all(&path).unwrap();
+    assert!(
+        eventually(|| watcher.is_finished()),
+        "watch should exit when the directory disappears"
+    );
+    watcher.join().unwrap().unwrap();
+}
+
+#[test]
+fn test_doctor_healthy_repo_has_no_problems() {
+    let repo = create_test_repo();
+
+    let report = doctor::diagnose(repo.path(), &Env::new());
+    assert!(report.problems.is_empty(), "{}", report.render());
+    let names: Vec<&str> = report.steps.iter().map(|s| s.name).collect();
+    assert_eq!(
+        names,
+        [
+            "find_git_dir",
+            "parse_gitdir_file",
+            "bare check",
+            "read_git_head",
+            "read_shape",
+            "find_bare_parent"
+        ]
+    );
+    assert!(
+        report
+            .steps
+            .iter()
+            .all(|s| s.outcome == doctor::Outcome::Ok)
+    );
+}
+
+#[test]
+fn test_doctor_outside_repo_stops_without_problems() {
+    let dir = TempDir::new().unwrap();
+
+    let report = doctor::diagnose(dir.path(), &Env::new());
+    assert!(report.problems.is_empty());
+    assert_eq!(report.steps.len(), 1);
+    assert_eq!(report.steps[0].outcome, doctor::Outcome::Stopped);
+}
+
+#[test]
+fn test_doctor_reports_gitdir_file_pointing_nowhere() {
+    let root = TempDir::new().unwrap();
+    fs::create_dir(root.path().join(".bare")).unwrap();
+    let worktree = root.path().join("feature");
+    fs::create_dir(&worktree).unwrap();
+    fs::write(
+        worktree.join(".git"),
+        "gitdir: ../.bare/worktrees/feature\n",
+    )
+    .unwrap();
+
+    let report = doctor::diagnose(&worktree, &Env::new());
+    assert_eq!(report.problems.len(), 1, "{}", report.render());
+    assert!(report.problems[0].message.contains("does not exist"));
+    assert!(
+        report.problems[0].fix.contains("worktree repair"),
+        "{}",
+        report.problems[0].fix
+    );
+}
+
+#[test]
+fn test_doctor_detects_moved_worktree() {
+    let repo = create_test_repo();
+    let parent = TempDir::new().unwrap();
+    let original = parent.path().join("wt");
+    let moved = parent.path().join("wt-moved");
+    git(
+        repo.path(),
+        &["worktree", "add", "-b", "topic", original.to_str().unwrap()],
+    );
+    fs::rename(&original, &moved).unwrap();
+
+    let report = doctor::diagnose(&moved, &Env::new());
+    assert!(
+        report
+            .problems
+            .iter()
+            .any(|p| p.message.contains("was it moved?") && p.fix.ends_with("worktree repair")),
+        "{}",
+        report.render()
+    );
+
+    // The main worktree sees the registration pointing at the old location
+    let report = doctor::diagnose(repo.path(), &Env::new());
+    assert!(
+        report
+            .problems
+            .iter()
+            .any(|p| p.message.contains("no longer exists")
+                && !p.runnable
+                && p.fix
+                    .contains(&format!("git -C {} worktree prune", repo.path().display()))),
+        "{}",
+        report.render()
+    );
+
+    // Following the suggested fix clears both problems
+    git(&moved, &["worktree", "repair"]);
+    assert!(doctor::diagnose(&moved, &Env::new()).problems.is_empty());
+    assert!(
+        doctor::diagnose(repo.path(), &Env::new())
+            .problems
+            .is_empty()
+    );
+}
+
+#[test]
+fn test_doctor_suggests_existing_branch_for_unreadable_head() {
+    let repo = create_test_repo();
+    git(repo.path(), &["branch", "-m", "main"]);
+    git(repo.path(), &["branch", "dev"]);
+    git(repo.path(), &["pack-refs", "--all"]);
+    let head = repo.path().join(".git/HEAD");
+    fs::write(&head, "bad\n").unwrap();
+
+    let report = doctor::diagnose(repo.path(), &Env::new());
+    assert_eq!(report.problems.len(), 1, "{}", report.render());
+    let problem = &report.problems[0];
+    assert!(problem.runnable);
+    assert!(
+        problem.message.contains("other branches: dev"),
+        "{}",
+        problem.message
+    );
+    assert!(
+        problem
+            .fix
+            .starts_with("printf 'ref: refs/heads/%s\\n' main > "),
+        "{}",
+        problem.fix
+    );
+
+    // The suggested command runs as printed and restores the prompt
+    let status = Command::new("sh")
+        .args(["-c", &problem.fix])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    assert_eq!(fs::read_to_string(&head).unwrap(), "ref: refs/heads/main\n");
+    assert!(
+        doctor::diagnose(repo.path(), &Env::new())
+            .problems
+            .is_empty()
+    );
+}
+
+#[test]
+fn test_doctor_detects_bare_clone_without_fetch_refspec() {
+    let origin = create_test_repo();
+    let root = TempDir::new().unwrap();
+    git(
+        root.path(),
+        &["clone", "--bare", origin.path().to_str().unwrap(), ".bare"],
+    );
+    fs::write(root.path().join(".git"), "gitdir: ./.bare\n").unwrap();
+
+    let report = doctor::diagnose(root.path(), &Env::new());
+    assert_eq!(report.problems.len(), 1, "{}", report.render());
+    let fix = &report.problems[0].fix;
+    assert!(
+        fix.ends_with("config remote.origin.fetch '+refs/heads/*:refs/remotes/origin/*'"),
+        "{}",
+        fix
+    );
+
+    git(
+        &root.path().join(".bare"),
+        &[
+            "config",
+            "remote.origin.fetch",
+            "+refs/heads/*:refs/remotes/origin/*",
+        ],
+    );
+    assert!(
+        doctor::diagnose(root.path(), &Env::new())
+            .problems
+            .is_empty()
+    );
+}
+
+/// Builds `<root>/.bare` cloned from a test repo plus a linked worktree `<root>/feature`
+fn create_bare_layout() -> TempDir {
+    let origin = create_test_repo();
+    let root = TempDir::new().unwrap();
+    git(
+        root.path(),
+        &["clone", "--bare", origin.path().to_str().unwrap(), ".bare"],
+    );
+    fs::write(root.path().join(".git"), "gitdir: ./.bare\n").unwrap();
+    git(
+        root.path(),
+        &["worktree", "add", "-b", "feature", "feature"],
+    );
+    root
+}
+
+#[test]
+fn test_moved_worktree_shows_broken_and_repair_fixes_it() {
+    let runtime = TempDir::new().unwrap();
+    let env = runtime_env(&runtime);
+    let root = create_bare_layout();
+    let worktree = root.path().join("feature");
+    assert_eq!(render_in(&worktree, &env).as_deref(), Some("🌳 feature"));
+
+    let moved = root.path().join("renamed");
+    fs::rename(&worktree, &moved).unwrap();
+    assert_eq!(
+        render_in(&moved, &env).as_deref(),
+        Some("⚠ broken worktree")
+    );
+
+    let changes = repair::repair(&moved).unwrap();
+    assert_eq!(
+        changes.len(),
+        1,
+        "Only the back-pointer is stale: {:?}",
+        changes
+    );
+    assert_eq!(
+        render_in(&moved, &env).as_deref(),
+        Some("🌳 renamed → ⎇ feature")
+    );
+    assert!(repair::repair(&moved).unwrap().is_empty());
+}
+
+#[test]
+fn test_moved_bare_parent_shows_broken_and_repair_fixes_it() {
+    let runtime = TempDir::new().unwrap();
+    let env = runtime_env(&runtime);
+    let root = create_bare_layout();
+    let parent = TempDir::new().unwrap();
+    let moved = parent.path().join("project");
+    fs::rename(root.path(), &moved).unwrap();
+
+    // git writes absolute pointers, so both now name the old location
+    let worktree = moved.join("feature");
+    assert!(matches!(
+        repair::detect(&worktree),
+        Some(pointers::Breakage::MissingAdminDir { .. })
+    ));
+    assert_eq!(
+        render_in(&worktree, &env).as_deref(),
+        Some("⚠ broken worktree")
+    );
+
+    let changes = repair::repair(&worktree).unwrap();
+    assert_eq!(
+        changes.len(),
+        2,
+        "Both pointers should be rewritten: {:?}",
+        changes
+    );
+    assert_eq!(render_in(&worktree, &env).as_deref(), Some("🌳 feature"));
+    assert_eq!(repair::detect(&worktree), None);
+    git(&worktree, &["status"]);
+}
+
+#[test]
+fn test_repair_from_bare_parent_repoints_worktree_git_files() {
+    let root = create_bare_layout();
+    let worktree = root.path().join("feature");
+    fs::write(
+        worktree.join(".git"),
+        "gitdir: /nonexistent/worktrees/feature\n",
+    )
+    .unwrap();
+
+    let changes = repair::repair(root.path()).unwrap();
+    assert_eq!(changes.len(), 1, "{:?}", changes);
+    assert_eq!(repair::detect(&worktree), None);
+    git(&worktree, &["status"]);
+}
+
+#[test]
+fn test_invalid_gitdir_file_reports_variant() {
+    let dir = TempDir::new().unwrap();
+    fs::write(dir.path().join(".git"), "not a pointer\n").unwrap();
+
+    let result = try_render_in(dir.path(), &Env::new());
+
+    match result {
+        Err(e @ Error::InvalidGitdirFile { .. }) => {
+            assert_eq!(e.category(), "gitdir");
+            assert_eq!(e.exit_code(), 4);
+        }
+        other => panic!("Expected InvalidGitdirFile, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_unreadable_head_reports_io_error_with_path() {
+    let repo_dir = create_test_repo();
+    let head = repo_dir.path().join(".git").join("HEAD");
+    fs::remove_file(&head).unwrap();
+    fs::create_dir(&head).unwrap();
+
+    let result = try_render_in(repo_dir.path(), &Env::new());
+
+    match result {
+        Err(Error::Io { path, .. }) => assert_eq!(path, head),
+        other => panic!("Expected Io error for HEAD, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_repeated_prompt_errors_are_deduplicated() {
+    let repo_dir = create_test_repo();
+    let temp_log_dir = TempDir::new().unwrap();
+    let env = state_env(&temp_log_dir);
+    fs::write(repo_dir.path().join(".git").join("HEAD"), "bad\n").unwrap();
+
+    for _ in 0..10 {
+        if let Err(e) = try_render_in(repo_dir.path(), &env) {
+            log_error(&e, repo_dir.path(), &env);
+        }
+    }
+
+    let state_dir = temp_log_dir.path().join(APP_STATE_DIR);
+    let content = fs::read_to_string(state_dir.join(ERROR_LOG_FILE)).unwrap();
+    assert_eq!(content.lines().count(), 1, "{}", content);
+
+    errorlog::flush(&state_dir, std::time::SystemTime::now());
+    let lines = errorlog::tail(&state_dir, 20);
+    assert_eq!(lines.len(), 2);
+    assert!(lines[1].contains("repeated 9 more times"), "{}", lines[1]);
+}
+
+#[test]
+fn test_bench_reports_runs_and_fs_calls() {
+    let runtime = TempDir::new().unwrap();
+    let env = runtime_env(&runtime);
+    let repo_dir = create_test_repo();
+    git(repo_dir.path(), &["checkout", "-b", "bench"]);
+
+    let report = bench::bench(repo_dir.path(), 20, &env);
+
+    let rendered = report.unwrap().render();
+    assert!(
+        rendered.starts_with("output: ⎇ bench\nruns:   20\n"),
+        "{}",
+        rendered
+    );
+    assert!(!rendered.contains("fs calls per run: 0.0"), "{}", rendered);
+}
+
+#[test]
+fn test_debug_trace_records_discovery_steps() {
+    let repo_dir = create_test_repo();
+
+    trace::enable();
+    render_in(repo_dir.path(), &Env::new());
+    let trace = trace::take().unwrap();
+
+    let steps: Vec<&str> = trace.steps.iter().map(|(name, _)| *name).collect();
+    assert_eq!(
+        steps,
+        [
+            "find_git_dir",
+            "read_git_head",
+            "find_common_dir",
+            "find_bare_parent",
+            "load_settings",
+            "read_upstream",
+            "read_fetch_head",
+            "read_operation",
+            "read_shape"
+        ]
+    );
+    assert!(trace.fs.stat > 0 && trace.fs.read >= 1, "{:?}", trace.fs);
+}
+
+#[test]
+fn test_repo_settings_override_environment() {
+    let runtime = TempDir::new().unwrap();
+    let env = runtime_env(&runtime)
+        .with(ENV_BRANCH_ICON, "env")
+        .with(status::ENV_STATUS, "1");
+    let repo_dir = create_test_repo();
+    let path = repo_dir.path();
+    git(path, &["checkout", "-b", "mono"]);
+    fs::write(path.join("untracked.txt"), "new").unwrap();
+
+    assert_eq!(render_in(path, &env).as_deref(), Some("env mono %1"));
+
+    // Editing the config invalidates the cached discovery
+    git(path, &["config", "worktree-prompt.branchIcon", "repo:"]);
+    git(path, &["config", "worktree-prompt.status", "off"]);
+    assert_eq!(render_in(path, &env).as_deref(), Some("repo: mono"));
+}
+
+#[test]
+fn test_repo_settings_follow_includes() {
+    let env = Env::new();
+    let repo_dir = create_test_repo();
+    let path = repo_dir.path();
+    git(path, &["checkout", "-b", "release/1.0"]);
+    fs::write(
+        path.join(".git/release.inc"),
+        "[worktree-prompt]\n\tbranchIcon = \"rel \\\"1\\\"\"\n",
+    )
+    .unwrap();
+    git(
+        path,
+        &["config", "includeIf.onbranch:release/.path", "release.inc"],
+    );
+
+    assert_eq!(
+        render_in(path, &env).as_deref(),
+        Some("rel \"1\" release/1.0"),
+        "Included value should be unquoted and unescaped"
+    );
+
+    git(path, &["checkout", "-b", "topic"]);
+    assert_eq!(render_in(path, &env).as_deref(), Some("⎇ topic"));
+}
+
+#[test]
+fn test_worktree_config_overrides_one_worktree() {
+    let runtime = TempDir::new().unwrap();
+    let env = runtime_env(&runtime);
+    let root = create_bare_layout();
+    let bare = root.path().join(".bare");
+    git(&bare, &["config", "worktree-prompt.worktreeIcon", "shared"]);
+    git(&bare, &["config", "extensions.worktreeConfig", "true"]);
+    git(
+        &root.path().join("feature"),
+        &[
+            "config",
+            "--worktree",
+            "worktree-prompt.worktreeIcon",
+            "mine",
+        ],
+    );
+    git(root.path(), &["worktree", "add", "-b", "other", "other"]);
+
+    assert_eq!(
+        render_in(&root.path().join("feature"), &env).as_deref(),
+        Some("mine feature")
+    );
+    assert_eq!(
+        render_in(&root.path().join("other"), &env).as_deref(),
+        Some("shared other")
+    );
+}
+
+#[test]
+fn test_malformed_repo_config_reports_config_error() {
+    let repo_dir = create_test_repo();
+    let config = repo_dir.path().join(".git/config");
+    let mut text = fs::read_to_string(&config).unwrap();
+    text.push_str("[worktree-prompt]\n\tbranchIcon = \"open\n");
+    fs::write(&config, text).unwrap();
+
+    let result = try_render_in(repo_dir.path(), &Env::new());
+
+    match result {
+        Err(Error::ConfigParse { path, reason, .. }) => {
+            assert_eq!(path, config);
+            assert_eq!(reason, "unterminated quoted value");
+        }
+        other => panic!("expected ConfigParse, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_ignored_directory_prints_and_logs_nothing() {
+    let state = TempDir::new().unwrap();
+    let repo_dir = create_test_repo();
+    let path = repo_dir.path();
+    fs::create_dir(path.join("vendor")).unwrap();
+    fs::write(path.join(".git/HEAD"), "bad").unwrap();
+
+    let patterns = format!("/nonexistent/*:{}", path.display());
+    let env = state_env(&state).with(ignore::ENV_IGNORE, &patterns);
+    assert_eq!(render_in(&path.join("vendor"), &env), None);
+    assert_eq!(render_in(path, &env), None);
+    assert!(
+        !state
+            .path()
+            .join(APP_STATE_DIR)
+            .join(ERROR_LOG_FILE)
+            .exists(),
+        "Ignored directories should not log errors"
+    );
+
+    let report = doctor::diagnose(path, &env);
+    assert_eq!(report.steps.len(), 1);
+    assert_eq!(report.steps[0].name, "ignore");
+    assert_eq!(report.steps[0].outcome, doctor::Outcome::Stopped);
+
+    let result = try_render_in(path, &Env::new());
+    assert!(matches!(result, Err(Error::UnknownHeadFormat { .. })));
+}
+
+#[test]
+fn test_theme_covers_every_glyph_and_env_icons_override_it() {
+    let env = Env::new().with(theme::ENV_THEME, "ascii");
+    let root = create_bare_layout();
+    git(&root.path().join("feature"), &["checkout", "-b", "topic"]);
+
+    assert_eq!(render_in(root.path(), &env).as_deref(), Some("wt [bare]"));
+    assert_eq!(
+        render_in(&root.path().join("feature"), &env).as_deref(),
+        Some("wt feature -> @ topic")
+    );
+
+    let env = env.with(ENV_BRANCH_ICON, "B");
+    assert_eq!(
+        render_in(&root.path().join("feature"), &env).as_deref(),
+        Some("wt feature -> B topic")
+    );
+
+    fs::rename(root.path().join("feature"), root.path().join("moved")).unwrap();
+    assert_eq!(
+        render_in(&root.path().join("moved"), &env).as_deref(),
+        Some("!! broken worktree")
+    );
+}
+
+#[test]
+fn test_branch_styles_and_protected_warning_follow_shell_mode() {
+    let env = Env::new()
+        .with(style::ENV_BRANCH_STYLES, "release/*=red **=green")
+        .with(style::ENV_PROTECTED, "main master release/*")
+        .with(style::ENV_SHELL, "bash");
+    let root = create_bare_layout();
+    let feature = root.path().join("feature");
+
+    let green = |text: &str| format!("\u{1}\u{1b}[32m\u{2}{}\u{1}\u{1b}[0m\u{2}", text);
+    let red = |text: &str| format!("\u{1}\u{1b}[31m\u{2}{}\u{1}\u{1b}[0m\u{2}", text);
+    assert_eq!(
+        render_in(&feature, &env),
+        Some(format!("🌳 {}", green("feature"))),
+        "A worktree named after its branch is styled as the branch"
+    );
+
+    git(&feature, &["checkout", "-b", "release/1.0"]);
+    assert_eq!(
+        render_in(&feature, &env),
+        Some(format!(
+            "🌳 feature → {} {}",
+            red("⎇ release/1.0"),
+            red("⛔")
+        ))
+    );
+
+    // The repository can turn the warning off and zsh escapes `%`
+    git(&feature, &["config", "worktree-prompt.protected", ""]);
+    let env = env.with(style::ENV_SHELL, "zsh");
+    git(&feature, &["checkout", "-b", "100%"]);
+    assert_eq!(
+        render_in(&feature, &env),
+        Some("🌳 feature → %{\u{1b}[32m%}⎇ 100%%%{\u{1b}[0m%}".to_string())
+    );
+}
+
+#[test]
+fn test_equivalence_rules_hide_matching_branch() {
+    let env = Env::new().with(equivalence::ENV_STRIP_PREFIXES, "kyle/");
+    let root = create_bare_layout();
+    let feature = root.path().join("feature");
+
+    git(&feature, &["checkout", "-b", "kyle/feature"]);
+    assert_eq!(render_in(&feature, &env).as_deref(), Some("🌳 feature"));
+
+    git(&feature, &["checkout", "-b", "kyle/DEV-123-feature-work"]);
+    assert_eq!(
+        render_in(&feature, &env).as_deref(),
+        Some("🌳 feature → ⎇ kyle/DEV-123-feature-work")
+    );
+
+    // A ticket ID in both names is enough once the repository opts in
+    git(root.path(), &["worktree", "move", "feature", "DEV-123"]);
+    let worktree = root.path().join("DEV-123");
+    git(
+        &worktree,
+        &["config", "worktree-prompt.ticketMatch", "true"],
+    );
+    assert_eq!(render_in(&worktree, &env).as_deref(), Some("🌳 DEV-123"));
+}
+
+/// Commits an empty change in `dir` as a fixed test identity
+fn commit_empty(dir: &std::path::Path, message: &str) {
+    git(
+        dir,
+        &[
+            "-c",
+            "user.name=Test",
+            "-c",
+            "user.email=test@example.com",
+            "commit",
+            "--allow-empty",
+            "-m",
+            message,
+        ],
+    );
+}
+
+#[test]
+fn test_merged_marker_follows_default_branch_and_upstream() {
+    let runtime = TempDir::new().unwrap();
+    let env = runtime_env(&runtime)
+        .with(cache::ENV_CACHE, "0")
+        .with(merged::ENV_MERGED, "1");
+    let root = create_bare_layout();
+    let feature = root.path().join("feature");
+    git(
+        root.path(),
+        &["config", "worktree-prompt.defaultBranch", "trunk"],
+    );
+
+    // Fresh from the default branch, then with unmerged work
+    git(&feature, &["branch", "trunk"]);
+    assert_eq!(render_in(&feature, &env).as_deref(), Some("🌳 feature"));
+    commit_empty(&feature, "work");
+    assert_eq!(render_in(&feature, &env).as_deref(), Some("🌳 feature"));
+
+    // Merged once the default branch moves past the branch tip
+    git(&feature, &["checkout", "-B", "trunk"]);
+    commit_empty(&feature, "merge");
+    git(&feature, &["checkout", "feature"]);
+    assert_eq!(render_in(&feature, &env).as_deref(), Some("🌳 feature ✔"));
+    assert!(
+        runtime_entries(&runtime)
+            .iter()
+            .any(|path| path.ends_with("merged")),
+        "The ancestry answer should be remembered"
+    );
+    assert_eq!(render_in(&feature, &env).as_deref(), Some("🌳 feature ✔"));
+
+    // An upstream deleted on the remote marks unmerged work too
+    git(&feature, &["checkout", "-b", "pushed"]);
+    commit_empty(&feature, "pushed work");
+    git(&feature, &["config", "branch.pushed.remote", "origin"]);
+    git(
+        &feature,
+        &["config", "branch.pushed.merge", "refs/heads/pushed"],
+    );
+    assert_eq!(
+        render_in(&feature, &env).as_deref(),
+        Some("🌳 feature → ⎇ pushed ✔")
+    );
+
+    let env = env.with(merged::ENV_MERGED, "0");
+    assert_eq!(
+        render_in(&feature, &env).as_deref(),
+        Some("🌳 feature → ⎇ pushed")
+    );
+}
+
+#[test]
+fn test_merged_ancestry_failures_are_not_remembered() {
+    let runtime = TempDir::new().unwrap();
+    let env = runtime_env(&runtime);
+    let repo_dir = create_test_repo();
+    let path = repo_dir.path();
+    let output = Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .current_dir(path)
+        .output()
+        .unwrap();
+    let head = String::from_utf8(output.stdout).unwrap().trim().to_string();
+    let deadline = std::time::Instant::now() + Duration::from_secs(5);
+
+    // git exits 128 for an unknown object: no answer, and nothing memoized
+    let missing = "0".repeat(40);
+    assert_eq!(
+        merged::is_ancestor(path, &missing, &head, &env, deadline),
+        None
+    );
+    assert!(
+        runtime_entries(&runtime).is_empty(),
+        "{:?}",
+        runtime_entries(&runtime)
+    );
+
+    assert_eq!(
+        merged::is_ancestor(path, &head, &head, &env, deadline),
+        Some(true)
+    );
+    assert!(!runtime_entries(&runtime).is_empty());
+}
+
+#[test]
+fn test_prune_merged_skips_dirty_and_locked_worktrees() {
+    let runtime = TempDir::new().unwrap();
+    let env = runtime_env(&runtime);
+    let root = create_bare_layout();
+    let feature = root.path().join("feature");
+    git(
+        root.path(),
+        &["config", "worktree-prompt.defaultBranch", "trunk"],
+    );
+    for name in ["dirty", "locked", "active"] {
+        git(root.path(), &["worktree", "add", "-b", name, name]);
+    }
+
+    commit_empty(&feature, "work");
+    for name in ["dirty", "locked"] {
+        git(&root.path().join(name), &["merge", "--ff-only", "feature"]);
+    }
+    commit_empty(&root.path().join("active"), "wip");
+    git(&feature, &["branch", "trunk"]);
+    git(&feature, &["checkout", "trunk"]);
+    commit_empty(&feature, "merge");
+    git(&feature, &["checkout", "feature"]);
+
+    fs::write(root.path().join("dirty/notes.txt"), "keep me").unwrap();
+    git(root.path(), &["worktree", "lock", "locked"]);
+
+    let prune = |answer: &str| {
+        let mut out = Vec::new();
+        merged::prune(root.path(), &env, false, &mut answer.as_bytes(), &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    };
+
+    let output = prune("n\n");
+    let lines: Vec<&str> = output.lines().collect();
+    assert_eq!(lines.len(), 4, "{}", output);
+    assert!(lines[0].ends_with("dirty  dirty  merged into trunk (skipped: has local changes)"));
+    assert!(lines[1].ends_with("feature  feature  merged into trunk"));
+    assert!(lines[2].ends_with("locked  locked  merged into trunk (skipped: locked)"));
+    assert_eq!(lines[3], "Remove 1 worktree? [y/N] ");
+    assert!(feature.exists(), "Declining should keep every worktree");
+
+    let output = prune("y\n");
+    assert!(output.contains("Removed "), "{}", output);
+    assert!(output.contains("(branch feature kept)"), "{}", output);
+    assert!(!feature.exists());
+    for name in ["dirty", "locked", "active"] {
+        assert!(root.path().join(name).exists(), "{} should be kept", name);
+    }
+}
+
+#[test]
+fn test_upstream_markers_distinguish_unset_gone_and_tracking() {
+    let runtime = TempDir::new().unwrap();
+    let env = runtime_env(&runtime).with(upstream::ENV_UPSTREAM, "1");
+    let root = create_bare_layout();
+    let feature = root.path().join("feature");
+    let discovered = || discover(&feature, &env).unwrap().unwrap().upstream;
+
+    assert_eq!(discovered(), Some(Upstream::Unset));
+    assert_eq!(render_in(&feature, &env).as_deref(), Some("🌳 feature ◌"));
+
+    // Configured, but never fetched (or pruned after the remote branch was deleted)
+    git(
+        &feature,
+        &[
+            "config",
+            "remote.origin.fetch",
+            "+refs/heads/*:refs/remotes/origin/*",
+        ],
+    );
+    git(&feature, &["config", "branch.feature.remote", "origin"]);
+    git(
+        &feature,
+        &["config", "branch.feature.merge", "refs/heads/feature"],
+    );
+    assert_eq!(
+        discovered(),
+        Some(Upstream::Gone("refs/remotes/origin/feature".to_string()))
+    );
+    assert_eq!(render_in(&feature, &env).as_deref(), Some("🌳 feature ⊘"));
+
+    // A packed tracking ref counts as well as a loose one; the cache notices it
+    git(
+        &feature,
+        &["update-ref", "refs/remotes/origin/feature", "HEAD"],
+    );
+    git(&feature, &["pack-refs", "--all"]);
+    assert_eq!(
+        discovered(),
+        Some(Upstream::Tracking(
+            "refs/remotes/origin/feature".to_string()
+        ))
+    );
+    assert_eq!(render_in(&feature, &env).as_deref(), Some("🌳 feature"));
+
+    let env = env.with("GIT_WORKTREE_PROMPT_TRACKING_ICON", "⇅");
+    assert_eq!(render_in(&feature, &env).as_deref(), Some("🌳 feature ⇅"));
+
+    git(
+        &feature,
+        &["update-ref", "-d", "refs/remotes/origin/feature"],
+    );
+    assert_eq!(render_in(&feature, &env).as_deref(), Some("🌳 feature ⊘"));
+}
+
+#[test]
+fn test_stale_fetch_marker_follows_fetch_head_age() {
+    let runtime = TempDir::new().unwrap();
+    let env = runtime_env(&runtime);
+    let root = create_bare_layout();
+    let feature = root.path().join("feature");
+    let fetch_head = root.path().join(".bare/FETCH_HEAD");
+    assert_eq!(
+        render_in(&feature, &env).as_deref(),
+        Some("🌳 feature"),
+        "Never fetched"
+    );
+
+    let two_days_ago = SystemTime::now() - Duration::from_secs(2 * 24 * 60 * 60);
+    let file = fs::File::create(&fetch_head).unwrap();
+    file.set_modified(two_days_ago).unwrap();
+    drop(file);
+    let discovery = discover(&feature, &env).unwrap().unwrap();
+    let expected = two_days_ago.duration_since(SystemTime::UNIX_EPOCH).unwrap();
+    assert_eq!(discovery.last_fetch, Some(expected.as_secs()));
+    assert_eq!(render_in(&feature, &env).as_deref(), Some("🌳 feature ⌛"));
+
+    // The threshold is configurable per repository, and 0 turns it off
+    let env = env.with(fetch::ENV_FETCH_STALE_SECS, "0");
+    assert_eq!(render_in(&feature, &env).as_deref(), Some("🌳 feature"));
+    git(
+        root.path(),
+        &["config", "worktree-prompt.fetchStaleSecs", "3600"],
+    );
+    assert_eq!(render_in(&feature, &env).as_deref(), Some("🌳 feature ⌛"));
+
+    // A fresh fetch invalidates the cached timestamp
+    fs::write(&fetch_head, "").unwrap();
+    assert_eq!(render_in(&feature, &env).as_deref(), Some("🌳 feature"));
+}
+
+#[test]
+fn test_remote_segment_names_host_and_repository() {
+    let runtime = TempDir::new().unwrap();
+    let env = runtime_env(&runtime);
+    let root = create_bare_layout();
+    let feature = root.path().join("feature");
+    let origin_name = |discovery: Discovery| discovery.remotes[0].0.clone();
+    assert_eq!(
+        origin_name(discover(&feature, &env).unwrap().unwrap()),
+        "origin"
+    );
+
+    // The cloned-from path is a local remote
+    git(
+        root.path(),
+        &["config", "worktree-prompt.remoteFormat", "{remote_host}"],
+    );
+    assert_eq!(
+        render_in(&feature, &env).as_deref(),
+        Some("local 🌳 feature")
+    );
+
+    git(
+        root.path(),
+        &["config", "url.git@github.com:.insteadOf", "gh:"],
+    );
+    git(
+        root.path(),
+        &["remote", "set-url", "origin", "gh:kyle/dotfiles.git"],
+    );
+    let env = env
+        .with(remote::ENV_REMOTE_FORMAT, "{remote_icon}{repo}")
+        .with(remote::ENV_HOST_ICONS, "gitlab.com=GL: github.com=GH:");
+    git(
+        root.path(),
+        &["config", "--unset", "worktree-prompt.remoteFormat"],
+    );
+    assert_eq!(
+        render_in(&feature, &env).as_deref(),
+        Some("GH:dotfiles 🌳 feature")
+    );
+    assert_eq!(
+        render_in(root.path(), &env).as_deref(),
+        Some("GH:dotfiles 🌳 [bare]")
+    );
+
+    git(
+        root.path(),
+        &[
+            "remote",
+            "add",
+            "fork",
+            "https://gitlab.com/kyle/dotfiles-fork",
+        ],
+    );
+    let env = env.with(remote::ENV_REMOTE, "fork");
+    assert_eq!(
+        render_in(&feature, &env).as_deref(),
+        Some("GL:dotfiles-fork 🌳 feature")
+    );
+}
+
+#[test]
+fn test_submodule_shows_superproject_and_recorded_commit_drift() {
+    let runtime = TempDir::new().unwrap();
+    let env = runtime_env(&runtime);
+    let parent = create_test_repo();
+    let library = create_test_repo();
+    git(
+        parent.path(),
+        &[
+            "-c",
+            "protocol.file.allow=always",
+            "submodule",
+            "add",
+            library.path().to_str().unwrap(),
+            "libs/sub",
+        ],
+    );
+    commit_empty(parent.path(), "add submodule");
+
+    let checkout = parent.path().join("libs/sub");
+    let discovery = discover(&checkout, &env).unwrap().unwrap();
+    let submodule = discovery.submodule.clone().unwrap();
+    assert_eq!(submodule.path, "libs/sub");
+    assert_eq!(submodule.head, submodule.recorded);
+    assert!(
+        discover(parent.path(), &env)
+            .unwrap()
+            .unwrap()
+            .submodule
+            .is_none(),
+        "The superproject itself is no submodule"
+    );
+
+    let parent_name = parent.path().file_name().unwrap().to_str().unwrap();
+    let expected = format!("{} › libs/sub@{}", parent_name, discovery.head.name);
+    assert_eq!(
+        render_in(&checkout, &env).as_deref(),
+        Some(expected.as_str())
+    );
+
+    // A new commit in the submodule is not yet recorded by the parent
+    commit_empty(&checkout, "local work");
+    assert_eq!(
+        render_in(&checkout, &env).as_deref(),
+        Some(format!("{} ≠", expected).as_str())
+    );
+
+    git(parent.path(), &["add", "libs/sub"]);
+    assert_eq!(
+        render_in(&checkout, &env).as_deref(),
+        Some(expected.as_str())
+    );
+}
+
+#[test]
+fn test_shape_markers_for_shallow_partial_and_sparse_clones() {
+    let runtime = TempDir::new().unwrap();
+    let env = runtime_env(&runtime);
+    let origin = create_test_repo();
+    fs::create_dir_all(origin.path().join("docs")).unwrap();
+    fs::write(origin.path().join("docs/guide.md"), "guide").unwrap();
+    git(origin.path(), &["add", "docs"]);
+    git(origin.path(), &["commit", "-q", "-m", "docs"]);
+    git(origin.path(), &["config", "uploadpack.allowFilter", "true"]);
+
+    let clone = TempDir::new().unwrap();
+    let url = format!("file://{}", origin.path().display());
+    git(
+        clone.path(),
+        &["clone", "-q", "--depth", "1", &url, "shallow"],
+    );
+    git(
+        clone.path(),
+        &["clone", "-q", "--filter=blob:none", &url, "partial"],
+    );
+
+    let shallow = clone.path().join("shallow");
+    let branch = discover(&shallow, &env).unwrap().unwrap().head.name;
+    assert_eq!(
+        render_in(&shallow, &env),
+        Some(format!("⎇ {} ⇊", branch)),
+        "A shallow clone"
+    );
+
+    let partial = clone.path().join("partial");
+    assert_eq!(
+        discover(&partial, &env)
+            .unwrap()
+            .unwrap()
+            .shape
+            .partial
+            .as_deref(),
+        Some("blob:none")
+    );
+    git(&partial, &["sparse-checkout", "set", "--cone", "docs"]);
+    assert_eq!(render_in(&partial, &env), Some(format!("⎇ {} ◐⋯", branch)));
+
+    let report = doctor::diagnose(&partial, &env);
+    let step = report
+        .steps
+        .iter()
+        .find(|s| s.name == "read_shape")
+        .unwrap();
+    assert_eq!(step.detail, "partial (blob:none), sparse cone: docs");
+
+    let env = env.with(theme::ENV_THEME, "ascii");
+    assert_eq!(
+        render_in(&partial, &env),
+        Some(format!("@ {} [partial][sparse]", branch))
+    );
+}
+
+#[test]
+fn test_conflict_count_during_merge_and_rebase() {
+    let runtime = TempDir::new().unwrap();
+    let env = runtime_env(&runtime);
+    let repo = create_test_repo();
+    let dir = repo.path();
+    let commit_files = |content: &str| {
+        fs::write(dir.join("a.txt"), content).unwrap();
+        fs::write(dir.join("b.txt"), content).unwrap();
+        git(dir, &["add", "a.txt", "b.txt"]);
+        git(dir, &["commit", "-q", "-m", content]);
+    };
+    git(dir, &["checkout", "-q", "-b", "base"]);
+    commit_files("base");
+    git(dir, &["checkout", "-q", "-b", "topic"]);
+    commit_files("topic");
+    git(dir, &["checkout", "-q", "base"]);
+    commit_files("main");
+
+    // Conflicts make merge exit non-zero, so run it without the success check
+    let merge = Command::new("git")
+        .args(["merge", "-q", "topic"])
+        .current_dir(dir)
+        .output()
+        .unwrap();
+    assert!(!merge.status.success());
+    assert_eq!(render_in(dir, &env).as_deref(), Some("⎇ base MERGING ✖2"));
+
+    git(dir, &["add", "a.txt"]);
+    assert_eq!(render_in(dir, &env).as_deref(), Some("⎇ base MERGING ✖1"));
+    git(dir, &["add", "b.txt"]);
+    assert_eq!(render_in(dir, &env).as_deref(), Some("⎇ base MERGING"));
+    git(dir, &["merge", "--abort"]);
+    assert_eq!(render_in(dir, &env).as_deref(), Some("⎇ base"));
+
+    git(dir, &["checkout", "-q", "topic"]);
+    let rebase = Command::new("git")
+        .args(["rebase", "--merge", "base"])
+        .current_dir(dir)
+        .output()
+        .unwrap();
+    assert!(!rebase.status.success());
+    let output = render_in(dir, &env).unwrap();
+    assert!(output.ends_with(" REBASE 1/1 ✖2"), "{}", output);
+
+    let env = env.with(theme::ENV_THEME, "ascii");
+    let output = render_in(dir, &env).unwrap();
+    assert!(output.ends_with(" REBASE 1/1 x2"), "{}", output);
+}
+
+#[test]
+fn test_jj_colocated_shows_change_id_and_bookmarks() {
+    use jj::fixture::{bookmark, field};
+
+    let runtime = TempDir::new().unwrap();
+    let env = runtime_env(&runtime);
+    let repo = create_test_repo();
+    let dir = repo.path();
+    git(dir, &["branch", "-m", "trunk"]);
+    git(dir, &["checkout", "-q", "--detach"]);
+    let head = fs::read_to_string(dir.join(".git/HEAD")).unwrap();
+    let parent: Vec<u8> = (0..40)
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&head[i..i + 2], 16).unwrap())
+        .collect();
+    let short = &head[..7];
+
+    // Without a .jj directory a detached HEAD is just a hash
+    assert_eq!(render_in(dir, &env), Some(format!("⎇ {}", short)));
+
+    let wc = [0xc1; 20];
+    let view = [
+        field(8, &[field(1, b"default"), field(2, &wc)].concat()),
+        field(5, &bookmark("trunk", &[&parent])),
+    ]
+    .concat();
+    let extra = field(4, &[0x01, 0x23, 0x45, 0x67, 0x89, 0xab]);
+    jj::fixture::write(dir, "default", &view, &[(&wc, &extra)]);
+    assert_eq!(render_in(dir, &env).as_deref(), Some("◉ zyxwvuts trunk"));
+
+    // Removing the operation head invalidates the cache; git's view remains
+    let heads = dir.join(".jj/repo/op_heads/heads");
+    fs::remove_dir_all(&heads).unwrap();
+    fs::create_dir(&heads).unwrap();
+    assert_eq!(render_in(dir, &env), Some(format!("◉ {} trunk", short)));
+
+    let env = env.with(theme::ENV_THEME, "ascii");
+    assert_eq!(render_in(dir, &env), Some(format!("jj {} trunk", short)));
+}
+
+// The library API takes its start path and environment explicitly, so these
+// run in parallel with everything else and need no #[serial]
+
+#[test]
+fn test_repository_api_regular_repo_and_linked_worktree() {
+    let repo = create_test_repo();
+    let dir = repo.path();
+    let linked_root = TempDir::new().unwrap();
+    let linked = linked_root.path().join("topic");
+    git(
+        dir,
+        &[
+            "worktree",
+            "add",
+            "-q",
+            "-b",
+            "topic",
+            linked.to_str().unwrap(),
+        ],
+    );
+    fs::create_dir(dir.join("src")).unwrap();
+
+    let main = Repository::discover(&dir.join("src"), &Env::new())
+        .unwrap()
+        .unwrap();
+    assert_eq!(main.common_dir, dir.join(".git"));
+    assert_eq!(main.bare_parent, None);
+    let worktree = main.worktree.clone().unwrap();
+    assert_eq!(
+        (worktree.path.as_path(), worktree.name.as_deref()),
+        (dir, None)
+    );
+    let branch = worktree.checkout.branch().unwrap().to_string();
+
+    let topic = Repository::discover(&linked, &Env::new()).unwrap().unwrap();
+    assert_eq!(
+        topic.common_dir, main.common_dir,
+        "`commondir` is normalized"
+    );
+    let topic_worktree = topic.worktree.clone().unwrap();
+    assert_eq!(topic_worktree.name.as_deref(), Some("topic"));
+    assert_eq!(topic_worktree.git_dir, dir.join(".git/worktrees/topic"));
+    assert_eq!(
+        topic_worktree.checkout,
+        Checkout::Branch("topic".to_string())
+    );
+
+    let listed = main.worktrees();
+    assert_eq!(listed, topic.worktrees());
+    assert_eq!(listed, [worktree, topic_worktree]);
+    assert_eq!(listed[0].checkout, Checkout::Branch(branch));
+
+    git(&linked, &["checkout", "-q", "--detach"]);
+    let id = fs::read_to_string(dir.join(".git/worktrees/topic/HEAD")).unwrap();
+    assert_eq!(
+        main.worktrees()[1].checkout,
+        Checkout::Detached(id.trim().to_string())
+    );
+}
+
+#[test]
+fn test_repository_api_rejects_malformed_detached_head() {
+    let repo_dir = create_test_repo();
+    let head = repo_dir.path().join(".git/HEAD");
+
+    for content in [
+        "ééééé\n",
+        "garbage\n",
+        "a1b2c3d\n",
+        &format!("{}\n", "A".repeat(40)),
+        &format!("{}\n", "a".repeat(41)),
+    ] {
+        fs::write(&head, content).unwrap();
+        match Repository::discover(repo_dir.path(), &Env::new()) {
+            Err(Error::UnknownHeadFormat { path }) => assert_eq!(path, head),
+            other => panic!("{:?}: expected UnknownHeadFormat, got {:?}", content, other),
+        }
+    }
+
+    for id in ["a".repeat(40), "b".repeat(64)] {
+        fs::write(&head, format!("{}\n", id)).unwrap();
+        let repository = Repository::discover(repo_dir.path(), &Env::new())
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            repository.worktree.unwrap().checkout,
+            Checkout::Detached(id)
+        );
+    }
+}
+
+#[test]
+fn test_repository_api_bare_layout() {
+    let root = create_bare_layout();
+    let bare = root.path().join(".bare");
+    fs::create_dir(root.path().join("feature/docs")).unwrap();
+
+    let parent = Repository::discover(root.path(), &Env::new())
+        .unwrap()
+        .unwrap();
+    assert_eq!(parent.common_dir, bare);
+    assert_eq!(parent.bare_parent.as_deref(), Some(root.path()));
+    assert_eq!(parent.worktree, None, "The .bare parent is no worktree");
+
+    let feature = Repository::discover(&root.path().join("feature/docs"), &Env::new())
+        .unwrap()
+        .unwrap();
+    let worktree = feature.worktree.clone().unwrap();
+    assert_eq!(worktree.path, root.path().join("feature"));
+    assert_eq!(worktree.git_dir, bare.join("worktrees/feature"));
+    assert_eq!(worktree.checkout.branch(), Some("feature"));
+    assert_eq!(
+        feature.worktrees(),
+        [worktree],
+        "No main worktree when bare"
+    );
+
+    fs::rename(root.path().join("feature"), root.path().join("moved")).unwrap();
+    assert!(matches!(
+        Repository::discover(&root.path().join("moved"), &Env::new()),
+        Err(Error::BrokenWorktree(
+            pointers::Breakage::StaleBackPointer { .. }
+        ))
+    ));
+}
+
+#[test]
+fn test_repository_api_reads_ignore_patterns_from_snapshot() {
+    let repo = create_test_repo();
+    let dir = repo.path();
+    let home = dir.parent().unwrap().to_str().unwrap();
+    let name = dir.file_name().unwrap().to_str().unwrap();
+
+    let env = Env::new()
+        .with(ignore::ENV_IGNORE, &format!("~/{}", name))
+        .with("HOME", home);
+    assert_eq!(Repository::discover(dir, &env).unwrap(), None);
+    assert!(
+        Repository::discover(dir, &env.clone().with("HOME", "/elsewhere"))
+            .unwrap()
+            .is_some()
+    );
+
+    let outside = TempDir::new().unwrap();
+    assert_eq!(
+        Repository::discover(outside.path(), &Env::new()).unwrap(),
+        None
+    );
+}
@@ -0,0 +1,515 @@
+use crate::refs;
+use crate::trace;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// ============================================================================
+// Jujutsu Colocated Repositories
+// ============================================================================
+//
+// `jj git init --colocate` keeps a `.jj` directory next to `.git`. jj moves
+// git's HEAD to the parent of its working-copy commit and detaches it, so
+// the plain prompt only ever shows a hash. With a `.jj` directory next to a
+// detached HEAD the prompt shows jj's view instead: the working-copy change
+// ID and the bookmarks on it or its parent.
+//
+// That view is read from jj's store without running jj:
+//
+//   .jj/working_copy/checkout        workspace name (protobuf, field 3)
+//   .jj/repo/op_heads/heads/<op>     the current operation, as an empty file
+//   .jj/repo/op_store/operations/<op>  operation; field 1 is its view ID
+//   .jj/repo/op_store/views/<view>   working-copy commit per workspace
+//                                    (field 8) and bookmarks (field 5)
+//   .jj/repo/store/extra/            stacked tables mapping commit IDs to
+//                                    jj's extra metadata, incl. the change ID
+//
+// When any of that is missing or unreadable, e.g. while two operations are
+// still unmerged or after a store format change, the git-level view is shown:
+// HEAD's hash and the branches jj exported onto it.
+
+/// Length of a displayed change ID, like `jj log`'s shortest unique default
+const CHANGE_ID_LENGTH: usize = 8;
+
+/// Parent tables followed before giving up, in case a chain loops
+const MAX_TABLE_CHAIN: usize = 1000;
+
+/// Workspace `jj git init` creates
+const DEFAULT_WORKSPACE: &str = "default";
+
+/// What jj knows about the working copy
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Jj {
+    /// Short working-copy change ID, None when only the git view was readable
+    pub(crate) change_id: Option<String>,
+    /// Bookmarks on the working-copy commit or its parent; `??` marks a conflict
+    pub(crate) bookmarks: Vec<String>,
+    /// Current operation head; the next jj operation deletes this file
+    pub(crate) op_head: Option<PathBuf>,
+}
+
+/// jj's view for a work directory with a `.jj` directory, or None without one
+pub(crate) fn detect(work_dir: &Path, git_dir: &Path, common_dir: &Path) -> Option<Jj> {
+    let jj_dir = work_dir.join(".jj");
+    if !trace::is_dir(&jj_dir) {
+        return None;
+    }
+
+    // Colocated jj keeps git's HEAD detached at the working copy's parent
+    let git_head = trace::read_to_string(&git_dir.join("HEAD"))
+        .ok()
+        .map(|content| content.trim().to_string())
+        .filter(|content| !content.starts_with("ref: "));
+
+    read_view(&jj_dir, git_head.as_deref()).or_else(|| {
+        Some(Jj {
+            change_id: None,
+            bookmarks: git_head
+                .map(|id| refs::branches_at(common_dir, &id))
+                .unwrap_or_default(),
+            op_head: None,
+        })
+    })
+}
+
+/// Reads the working copy's change ID and bookmarks from the operation store
+fn read_view(jj_dir: &Path, git_head: Option<&str>) -> Option<Jj> {
+    let repo = repo_dir(jj_dir)?;
+    let workspace = trace::read(&jj_dir.join("working_copy/checkout"))
+        .ok()
+        .and_then(|checkout| string_field(&checkout, 3))
+        .unwrap_or_else(|| DEFAULT_WORKSPACE.to_string());
+
+    // Concurrent operations leave several heads until jj merges them
+    let mut heads = fs::read_dir(repo.join("op_heads/heads")).ok()?.flatten();
+    let op_head = heads.next()?.path();
+    if heads.next().is_some() {
+        return None;
+    }
+    let op_id = op_head.file_name()?.to_str()?;
+
+    let operation = trace::read(&repo.join("op_store/operations").join(op_id)).ok()?;
+    let view_id = bytes_field(&operation, 1)?;
+    let view = trace::read(&repo.join("op_store/views").join(hex(view_id))).ok()?;
+    let wc_commit = wc_commit(&view, &workspace)?;
+
+    let parent = git_head.and_then(unhex);
+    let mut bookmarks = Vec::new();
+    for (field, value) in fields(&view)? {
+        if let (5, Value::Bytes(bookmark)) = (field, value)
+            && let Some(name) = string_field(bookmark, 1)
+        {
+            let targets = bookmark_targets(bookmark);
+            let on_working_copy = targets
+                .iter()
+                .any(|id| *id == wc_commit || Some(*id) == parent.as_deref());
+            if on_working_copy {
+                let conflict = if targets.len() > 1 { "??" } else { "" };
+                bookmarks.push(format!("{}{}", name, conflict));
+            }
+        }
+    }
+
+    let change_id = extra(&repo.join("store/extra"), wc_commit)
+        .and_then(|extra| bytes_field(&extra, 4).map(reverse_hex))
+        .map(|id| id.chars().take(CHANGE_ID_LENGTH).collect());
+
+    Some(Jj {
+        change_id,
+        bookmarks,
+        op_head: Some(op_head),
+    })
+}
+
+/// `.jj/repo` is the repository, or for secondary workspaces a file naming it
+fn repo_dir(jj_dir: &Path) -> Option<PathBuf> {
+    let repo = jj_dir.join("repo");
+    if trace::is_dir(&repo) {
+        return Some(repo);
+    }
+    let target = trace::read_to_string(&repo).ok()?;
+    Some(jj_dir.join(target.trim()))
+}
+
+/// Working-copy commit of `workspace` from a view's `wc_commit_ids` map
+fn wc_commit<'a>(view: &'a [u8], workspace: &str) -> Option<&'a [u8]> {
+    let mut legacy = None;
+    for (field, value) in fields(view)? {
+        match (field, value) {
+            (8, Value::Bytes(entry)) if string_field(entry, 1).as_deref() == Some(workspace) => {
+                return bytes_field(entry, 2);
+            }
+            // Views written before workspaces existed hold a single commit
+            (2, Value::Bytes(id)) => legacy = Some(id),
+            _ => {}
+        }
+    }
+    legacy
+}
+
+/// Commits a bookmark's local target adds: one normally, several in a conflict
+fn bookmark_targets(bookmark: &[u8]) -> Vec<&[u8]> {
+    let Some(target) = bytes_field(bookmark, 2) else {
+        return Vec::new(); // Deleted locally, only remote bookmarks left
+    };
+    let mut ids = Vec::new();
+    for (field, value) in fields(target).unwrap_or_default() {
+        match (field, value) {
+            (1, Value::Bytes(id)) => ids.push(id),
+            (3, Value::Bytes(conflict)) => {
+                for (field, value) in fields(conflict).unwrap_or_default() {
+                    if let (2, Value::Bytes(term)) = (field, value)
+                        && let Some(id) = bytes_field(term, 1)
+                    {
+                        ids.push(id);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    ids
+}
+
+/// Looks `key` up in a stacked table directory such as `store/extra`
+///
+/// Each table file starts with its parent's name (u32 length, little-endian,
+/// then the name), the entry count, the sorted keys each followed by a u32
+/// offset into the values, and finally the values. Lookups start at every
+/// head table and follow the parents.
+fn extra(dir: &Path, key: &[u8]) -> Option<Vec<u8>> {
+    let heads = fs::read_dir(dir.join("heads")).ok()?;
+    for head in heads.flatten() {
+        let mut name = head.file_name().to_string_lossy().to_string();
+        for _ in 0..MAX_TABLE_CHAIN {
+            let Ok(table) = trace::read(&dir.join(&name)) else {
+                break;
+            };
+            let (parent, value) = table_lookup(&table, key)?;
+            if let Some(value) = value {
+                return Some(value.to_vec());
+            }
+            match parent {
+                Some(parent) => name = parent,
+                None => break,
+            }
+        }
+    }
+    None
+}
+
+/// Parent table name and the value for `key` in one table file
+fn table_lookup<'a>(table: &'a [u8], key: &[u8]) -> Option<(Option<String>, Option<&'a [u8]>)> {
+    let parent_len = le32(table, 0)? as usize;
+    let parent = table.get(4..4 + parent_len)?;
+    let parent = (!parent.is_empty()).then(|| String::from_utf8_lossy(parent).to_string());
+    let count = le32(table, 4 + parent_len)? as usize;
+    let entries = 8 + parent_len;
+    let entry_len = key.len() + 4;
+    let values = entries.checked_add(count.checked_mul(entry_len)?)?;
+    let offset = |i: usize| le32(table, entries + i * entry_len + key.len()).map(|o| o as usize);
+
+    let (mut low, mut high) = (0, count);
+    while low < high {
+        let mid = (low + high) / 2;
+        let mid_key =
+            table.get(entries + mid * entry_len..entries + mid * entry_len + key.len())?;
+        match mid_key.cmp(key) {
+            std::cmp::Ordering::Less => low = mid + 1,
+            std::cmp::Ordering::Greater => high = mid,
+            std::cmp::Ordering::Equal => {
+                let start = values + offset(mid)?;
+                let end = if mid + 1 < count {
+                    values + offset(mid + 1)?
+                } else {
+                    table.len()
+                };
+                return Some((parent, Some(table.get(start..end)?)));
+            }
+        }
+    }
+    Some((parent, None))
+}
+
+fn le32(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(bytes.get(at..at + 4)?.try_into().ok()?))
+}
+
+/// A protobuf field value; fixed-width scalars are skipped
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Value<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Fixed,
+}
+
+/// Top-level fields of a protobuf message, or None if it is malformed
+fn fields(mut buf: &[u8]) -> Option<Vec<(u64, Value<'_>)>> {
+    let mut out = Vec::new();
+    while !buf.is_empty() {
+        let key = varint(&mut buf)?;
+        let value = match key & 7 {
+            0 => Value::Varint(varint(&mut buf)?),
+            1 => {
+                buf = buf.get(8..)?;
+                Value::Fixed
+            }
+            2 => {
+                let len = usize::try_from(varint(&mut buf)?).ok()?;
+                let (bytes, rest) = (buf.get(..len)?, buf.get(len..)?);
+                buf = rest;
+                Value::Bytes(bytes)
+            }
+            5 => {
+                buf = buf.get(4..)?;
+                Value::Fixed
+            }
+            _ => return None,
+        };
+        out.push((key >> 3, value));
+    }
+    Some(out)
+}
+
+/// Last length-delimited value of `field`, as protobuf merges repeated scalars
+fn bytes_field(buf: &[u8], field: u64) -> Option<&[u8]> {
+    fields(buf)?
+        .into_iter()
+        .filter_map(|(f, value)| match value {
+            Value::Bytes(bytes) if f == field => Some(bytes),
+            _ => None,
+        })
+        .next_back()
+}
+
+fn string_field(buf: &[u8], field: u64) -> Option<String> {
+    String::from_utf8(bytes_field(buf, field)?.to_vec()).ok()
+}
+
+/// Protobuf base-128 varint, advancing `buf` past it
+fn varint(buf: &mut &[u8]) -> Option<u64> {
+    let mut value = 0u64;
+    for (i, byte) in buf.iter().enumerate().take(10) {
+        value |= u64::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            *buf = &buf[i + 1..];
+            return Some(value);
+        }
+    }
+    None
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// jj writes change IDs in hex with the digits mapped onto `z` down to `k`,
+/// so they never look like commit hashes
+fn reverse_hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .flat_map(|b| [b >> 4, b & 0xf])
+        .map(|nibble| (b'z' - nibble) as char)
+        .collect()
+}
+
+/// Builders for jj store fixtures, shared with the integration tests
+#[cfg(test)]
+pub(crate) mod fixture {
+    use super::hex;
+    use std::fs;
+    use std::path::Path;
+
+    /// Protobuf length-delimited field
+    pub(crate) fn field(number: u8, bytes: &[u8]) -> Vec<u8> {
+        let mut out = vec![number << 3 | 2];
+        let mut len = bytes.len();
+        loop {
+            let byte = (len & 0x7f) as u8;
+            len >>= 7;
+            if len == 0 {
+                out.push(byte);
+                break;
+            }
+            out.push(byte | 0x80);
+        }
+        out.extend_from_slice(bytes);
+        out
+    }
+
+    /// Bookmark message whose local target is `ids`, a conflict if several
+    pub(crate) fn bookmark(name: &str, ids: &[&[u8]]) -> Vec<u8> {
+        let target = match ids {
+            [id] => field(1, id),
+            _ => {
+                let adds: Vec<u8> = ids.iter().flat_map(|id| field(2, &field(1, id))).collect();
+                field(3, &adds)
+            }
+        };
+        [field(1, name.as_bytes()), field(2, &target)].concat()
+    }
+
+    /// Writes a `.jj` directory whose single operation points at `view`,
+    /// with `extras` as one stacked table of sorted (commit ID, value) pairs
+    pub(crate) fn write(work_dir: &Path, workspace: &str, view: &[u8], extras: &[(&[u8], &[u8])]) {
+        let jj = work_dir.join(".jj");
+        let repo = jj.join("repo");
+        for dir in [
+            "op_heads/heads",
+            "op_store/operations",
+            "op_store/views",
+            "store/extra/heads",
+        ] {
+            fs::create_dir_all(repo.join(dir)).unwrap();
+        }
+        fs::create_dir_all(jj.join("working_copy")).unwrap();
+        fs::write(
+            jj.join("working_copy/checkout"),
+            field(3, workspace.as_bytes()),
+        )
+        .unwrap();
+
+        let view_id = [0x5a; 64];
+        let op_id = hex(&[0x0b; 64]);
+        fs::write(repo.join("op_store/views").join(hex(&view_id)), view).unwrap();
+        fs::write(
+            repo.join("op_store/operations").join(&op_id),
+            field(1, &view_id),
+        )
+        .unwrap();
+        fs::write(repo.join("op_heads/heads").join(&op_id), "").unwrap();
+
+        let mut table = 0u32.to_le_bytes().to_vec();
+        table.extend((extras.len() as u32).to_le_bytes());
+        let mut offset = 0u32;
+        for (key, value) in extras {
+            table.extend_from_slice(key);
+            table.extend(offset.to_le_bytes());
+            offset += value.len() as u32;
+        }
+        for (_, value) in extras {
+            table.extend_from_slice(value);
+        }
+        fs::write(repo.join("store/extra/table"), table).unwrap();
+        fs::write(repo.join("store/extra/heads/table"), "").unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fixture::{bookmark, field};
+    use super::*;
+    use tempfile::TempDir;
+
+    const WC: [u8; 20] = [0xc1; 20];
+    const PARENT: [u8; 20] = [0xa7; 20];
+    const OTHER: [u8; 20] = [0x99; 20];
+
+    fn view(workspace: &str) -> Vec<u8> {
+        let wc_entry = [field(1, workspace.as_bytes()), field(2, &WC)].concat();
+        [
+            field(1, &WC),
+            field(8, &[field(1, b"other"), field(2, &OTHER)].concat()),
+            field(8, &wc_entry),
+            field(5, &bookmark("main", &[&PARENT])),
+            field(5, &bookmark("elsewhere", &[&OTHER])),
+            field(5, &bookmark("split", &[&WC, &OTHER])),
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn test_read_view_finds_change_id_and_bookmarks() {
+        let dir = TempDir::new().unwrap();
+        let change_id = [0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef];
+        let extra = [field(2, &OTHER), field(4, &change_id)].concat();
+        fixture::write(
+            dir.path(),
+            "ws",
+            &view("ws"),
+            &[(&PARENT, b"unrelated"), (&WC, &extra)],
+        );
+
+        let jj = read_view(&dir.path().join(".jj"), Some(&hex(&PARENT))).unwrap();
+        assert_eq!(jj.change_id.as_deref(), Some("zyxwvuts"));
+        assert_eq!(jj.bookmarks, ["main", "split??"]);
+        assert!(jj.op_head.unwrap().ends_with(hex(&[0x0b; 64])));
+    }
+
+    #[test]
+    fn test_detect_falls_back_to_git_branches() {
+        let dir = TempDir::new().unwrap();
+        let git_dir = dir.path().join(".git");
+        fs::create_dir_all(git_dir.join("refs/heads")).unwrap();
+        let head = hex(&PARENT);
+        fs::write(git_dir.join("HEAD"), format!("{}\n", head)).unwrap();
+        fs::write(git_dir.join("refs/heads/main"), format!("{}\n", head)).unwrap();
+
+        assert_eq!(detect(dir.path(), &git_dir, &git_dir), None, "No .jj");
+
+        // A .jj directory whose store can't be read, e.g. mid-initialization
+        fs::create_dir_all(dir.path().join(".jj/repo")).unwrap();
+        assert_eq!(
+            detect(dir.path(), &git_dir, &git_dir),
+            Some(Jj {
+                change_id: None,
+                bookmarks: vec!["main".to_string()],
+                op_head: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_table_lookup_follows_parents() {
+        let dir = TempDir::new().unwrap();
+        let store = dir.path();
+        fs::create_dir_all(store.join("heads")).unwrap();
+        let table = |parent: &str, key: &[u8], value: &[u8]| {
+            let mut out = (parent.len() as u32).to_le_bytes().to_vec();
+            out.extend(parent.as_bytes());
+            out.extend(1u32.to_le_bytes());
+            out.extend(key);
+            out.extend(0u32.to_le_bytes());
+            out.extend(value);
+            out
+        };
+        fs::write(store.join("old"), table("", &PARENT, b"parent")).unwrap();
+        fs::write(store.join("new"), table("old", &WC, b"wc")).unwrap();
+        fs::write(store.join("heads/new"), "").unwrap();
+
+        assert_eq!(extra(store, &WC).as_deref(), Some(&b"wc"[..]));
+        assert_eq!(extra(store, &PARENT).as_deref(), Some(&b"parent"[..]));
+        assert_eq!(extra(store, &OTHER), None);
+    }
+
+    #[test]
+    fn test_protobuf_and_hex_helpers() {
+        let message = [
+            vec![0x08, 0x96, 0x01],
+            field(2, b"hi"),
+            vec![0x1d, 1, 2, 3, 4],
+        ]
+        .concat();
+        assert_eq!(
+            fields(&message),
+            Some(vec![
+                (1, Value::Varint(150)),
+                (2, Value::Bytes(b"hi")),
+                (3, Value::Fixed)
+            ])
+        );
+        assert_eq!(fields(&[0x12, 0x05, b'a']), None, "Truncated");
+        assert_eq!(reverse_hex(&[0x0f, 0xa5]), "zkpu");
+        assert_eq!(unhex("0fa5"), Some(vec![0x0f, 0xa5]));
+        assert_eq!(unhex("0fa"), None);
+    }
+}
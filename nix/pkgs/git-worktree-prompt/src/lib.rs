@@ -0,0 +1,32 @@
+//! Git repository and worktree discovery without running git
+//!
+//! [`Repository::discover`] walks up from a start path the way git does and
+//! returns the repository's shared git directory, the worktree containing the
+//! path and what it has checked out. It reads only the start path and the
+//! [`Env`] snapshot it is given, never the process's current directory or
+//! environment, so callers can discover many paths concurrently.
+//!
+//! The `git-worktree-prompt` binary builds its prompt on [`Repository::discover`];
+//! its settings, rendering and subcommands are not part of this crate.
+
+mod repository;
+
+// Shared with the prompt binary, which builds on them; not part of the API
+#[doc(hidden)]
+pub mod discovery;
+#[doc(hidden)]
+pub mod error;
+#[doc(hidden)]
+pub mod ignore;
+#[doc(hidden)]
+pub mod pointers;
+#[doc(hidden)]
+pub mod trace;
+#[doc(hidden)]
+pub mod wildmatch;
+
+use error::IoContext;
+
+pub use error::Error;
+pub use pointers::Breakage;
+pub use repository::{Checkout, Env, Repository, Worktree};
@@ -1,68 +1,77 @@
 use std::env;
-use std::error::Error as StdError;
-use std::fmt;
-use std::fs;
-use std::fs::OpenOptions;
-use std::io::Write;
 use std::path::{Path, PathBuf};
-use std::time::SystemTime;
-
-// ============================================================================
-// Error Handling
-// ============================================================================
-
-/// Custom error type for git operations
-#[derive(Debug)]
-struct Error(String);
-
-impl fmt::Display for Error {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.0)
-    }
-}
-
-impl StdError for Error {}
-
-impl Error {
-    pub(crate) fn from_str(s: &str) -> Self {
-        Error(s.to_string())
-    }
-}
-
-impl From<std::io::Error> for Error {
-    fn from(err: std::io::Error) -> Self {
-        Error(err.to_string())
-    }
-}
+use std::time::{Instant, SystemTime};
+
+mod bench;
+mod budget;
+mod cache;
+mod cli;
+mod daemon;
+mod doctor;
+mod equivalence;
+mod errorlog;
+mod fetch;
+mod fields;
+mod gitconfig;
+mod index;
+#[cfg(target_os = "linux")]
+mod inotify;
+mod jj;
+mod merged;
+mod operation;
+mod refresh;
+mod refs;
+mod remote;
+mod repair;
+mod settings;
+mod shape;
+mod status;
+mod style;
+mod submodule;
+mod theme;
+mod upstream;
+#[cfg(target_os = "linux")]
+mod watch;
+
+use budget::{Budget, Segment};
+use cli::{Cli, Command};
+use discovery::{
+    MAX_BARE_SEARCH_DEPTH, find_bare_parent, find_common_dir, find_git_dir, parse_gitdir_file,
+    read_checkout,
+};
+use equivalence::Equivalence;
+use git_worktree_prompt::error::IoContext;
+use git_worktree_prompt::{Checkout, Env, Error, Repository};
+use git_worktree_prompt::{discovery, ignore, pointers, trace, wildmatch};
+use jj::Jj;
+use operation::Operation;
+use settings::RepoSettings;
+use shape::Shape;
+use status::StatusMode;
+use style::Styles;
+use submodule::Submodule;
+use theme::{Glyph, Icons};
+use upstream::Upstream;
 
 // ============================================================================
 // Configuration Constants
 // ============================================================================
 
-/// Maximum depth to search for .git directory when walking up the tree
-const MAX_GIT_SEARCH_DEPTH: usize = 32;
-
-/// Maximum depth to search for .bare directory
-const MAX_BARE_SEARCH_DEPTH: usize = 4;
-
 /// Length of commit hash to display for detached HEAD state
 const DETACHED_HEAD_HASH_LENGTH: usize = 7;
 
-/// Default icon displayed before worktree paths
-const DEFAULT_WORKTREE_ICON: &str = "🌳";
-
-/// Default icon displayed before branch names (Unicode, works without Nerd Fonts)
-const DEFAULT_BRANCH_ICON: &str = "⎇";
-
 /// Environment variable name for customizing the branch icon
-const ENV_BRANCH_ICON: &str = "GIT_WORKTREE_PROMPT_BRANCH_ICON";
+pub(crate) const ENV_BRANCH_ICON: &str = "GIT_WORKTREE_PROMPT_BRANCH_ICON";
 
 /// Environment variable name for customizing the worktree icon
-const ENV_WORKTREE_ICON: &str = "GIT_WORKTREE_PROMPT_WORKTREE_ICON";
+pub(crate) const ENV_WORKTREE_ICON: &str = "GIT_WORKTREE_PROMPT_WORKTREE_ICON";
 
 /// Log file name for error messages
 pub(crate) const ERROR_LOG_FILE: &str = "error.log";
 
+/// Lines shown by `logs` when no count is given
+const DEFAULT_LOG_LINES: usize = 20;
+
 /// Application directory name for state storage
 pub(crate) const APP_STATE_DIR: &str = "git-worktree-prompt";
 
@@ -70,12 +79,160 @@ pub(crate) const APP_STATE_DIR: &str = "git-worktree-prompt";
 // Main Entry Point
 // ============================================================================
 
+/// Parses arguments, then prints the prompt or runs a subcommand
 fn main() {
-    // Parse args for optional --debug flag
+    // Start the clock before anything else so the budget covers the whole run
+    let env = Env::from_process();
+    let mut budget = Budget::from_env(&env);
+
     let args: Vec<String> = env::args().collect();
-    let debug = args.contains(&"--debug".to_string());
+    let cli = match cli::parse(&args) {
+        Ok(cli) => cli,
+        Err(e) => {
+            eprintln!("git-worktree-prompt: {}", e);
+            std::process::exit(e.exit_code());
+        }
+    };
+    // Subcommands fall back to `.` in a deleted directory; the prompt reports it
+    let cwd = start_dir(&cli);
+    let start = cwd
+        .as_ref()
+        .map_or_else(|_| PathBuf::from("."), PathBuf::clone);
+
+    match cli.command {
+        Command::Prompt => {}
+        Command::Help(topic) => {
+            print!("{}", cli::help(topic));
+            return;
+        }
+        Command::Version => {
+            print!("{}", cli::version());
+            return;
+        }
+
+        // Persistent daemon mode, answering prompts over a Unix socket
+        Command::Daemon => {
+            #[cfg(target_os = "linux")]
+            let result = daemon::serve(&env);
+            #[cfg(not(target_os = "linux"))]
+            let result = Err(Error::command("daemon: requires Linux (inotify)"));
+
+            if let Err(e) = result {
+                exit_with(&e, &start, &env);
+            }
+            return;
+        }
+        Command::DaemonStop => {
+            if !daemon::shutdown(&env) {
+                eprintln!("git-worktree-prompt: no daemon running");
+                std::process::exit(1);
+            }
+            return;
+        }
+
+        // Step-by-step discovery diagnosis with suggested fixes
+        Command::Doctor => {
+            let report = doctor::diagnose(&start, &env);
+            print!("{}", report.render());
+            if !report.problems.is_empty() {
+                std::process::exit(1);
+            }
+            return;
+        }
+
+        // Discovery results as key=value lines for scripts
+        Command::Fields => {
+            match discover(&start, &env) {
+                Ok(Some(discovery)) => {
+                    print!("{}", fields::render(&discovery, &env, SystemTime::now()))
+                }
+                Ok(None) => {}
+                Err(e) => exit_with(&e, &start, &env),
+            }
+            return;
+        }
+
+        // Rewrite worktree pointers broken by moving a worktree or its .bare parent
+        Command::Repair => {
+            match repair::repair(&start) {
+                Ok(changes) if changes.is_empty() => println!("Nothing to repair."),
+                Ok(changes) => {
+                    for change in changes {
+                        println!("{}", change);
+                    }
+                }
+                Err(e) => exit_with(&e, &start, &env),
+            }
+            return;
+        }
+
+        // List worktrees whose branches are done with and remove them
+        Command::PruneMerged { yes } => {
+            let stdin = std::io::stdin();
+            let result =
+                merged::prune(&start, &env, yes, &mut stdin.lock(), &mut std::io::stdout());
+            if let Err(e) = result {
+                exit_with(&e, &start, &env);
+            }
+            return;
+        }
+
+        // Show or clear the error log
+        Command::Logs { lines, clear } => {
+            let Some(dir) = get_state_dir(&env) else {
+                exit_with(&Error::command("logs: no state directory"), &start, &env);
+            };
+            if clear {
+                if let Err(e) = errorlog::clear(&dir) {
+                    exit_with(&e, &start, &env);
+                }
+                return;
+            }
+            errorlog::flush(&dir, SystemTime::now());
+            for line in errorlog::tail(&dir, lines.unwrap_or(DEFAULT_LOG_LINES)) {
+                println!("{}", line);
+            }
+            return;
+        }
+
+        // In-process latency percentiles, no hyperfine needed
+        Command::Bench { iterations } => {
+            let iterations = iterations.unwrap_or(bench::DEFAULT_ITERATIONS);
+            match bench::bench(&start, iterations, &env) {
+                Ok(report) => print!("{}", report.render()),
+                Err(e) => exit_with(&e, &start, &env),
+            }
+            return;
+        }
+
+        // Background refresh mode, spawned by async status rendering
+        Command::Refresh => {
+            if let Err(e) = refresh::refresh(&start, &env) {
+                log_error(&e, &start, &env);
+            }
+            return;
+        }
 
-    match run() {
+        // Streaming mode for status bars: one line per change in rendered output
+        Command::Watch => {
+            #[cfg(target_os = "linux")]
+            let result = watch::watch(&start, &env, &mut std::io::stdout());
+            #[cfg(not(target_os = "linux"))]
+            let result = Err(Error::command("watch: requires Linux (inotify)"));
+
+            if let Err(e) = result {
+                exit_with(&e, &start, &env);
+            }
+            return;
+        }
+    }
+
+    let debug = cli.debug;
+    if debug {
+        trace::enable();
+    }
+    let result = cwd.and_then(|cwd| render(&cwd, &env, &mut budget));
+    match &result {
         Ok(Some(output)) => print!("{}", output),
         Ok(None) => {
             if debug {
@@ -83,208 +240,482 @@ fn main() {
             }
         }
         Err(e) => {
-            log_error(&e);
+            log_error(e, &start, &env);
             if debug {
-                eprintln!("[DEBUG] Error: {}", e);
+                eprintln!("[DEBUG] Error ({}): {}", e.category(), e);
             }
         }
     }
+
+    if debug {
+        let trace = trace::take().unwrap_or_default();
+        for line in budget
+            .report()
+            .into_iter()
+            .chain(budget::trace_report(&trace))
+        {
+            eprintln!("[DEBUG] {}", line);
+        }
+    }
+
+    // Prompts print nothing on failure; the exit code says what went wrong
+    if let Err(e) = result {
+        std::process::exit(e.exit_code());
+    }
+}
+
+/// Logs a subcommand failure, reports it on stderr and exits with its code
+fn exit_with(error: &Error, start: &Path, env: &Env) -> ! {
+    log_error(error, start, env);
+    eprintln!("git-worktree-prompt: {}", error);
+    std::process::exit(error.exit_code());
+}
+
+/// Directory given with `--path`, or the current directory
+fn start_dir(cli: &Cli) -> Result<PathBuf, Error> {
+    match &cli.path {
+        Some(path) => Ok(path.clone()),
+        None => env::current_dir().with_path(Path::new(".")),
+    }
 }
 
 // ============================================================================
 // Pure Rust Git Operations
 // ============================================================================
 
-/// Main logic function - discovers git repo and formats output
-pub(crate) fn run() -> Result<Option<String>, Error> {
-    // 1. Find .git directory (walk up from current directory)
-    let git_dir = match find_git_dir()? {
-        Some(dir) => dir,
-        None => return Ok(None), // Not in a git repo
-    };
-
-    // 2. Get the work directory (parent of .git if it's a directory)
-    let work_dir = if git_dir.is_file() {
-        // Worktree: .git is a file, work_dir is its parent
-        git_dir
-            .parent()
-            .ok_or_else(|| Error::from_str("Invalid .git file path"))?
-            .to_path_buf()
-    } else {
-        // Regular repo: work_dir is parent of .git directory
-        git_dir
-            .parent()
-            .ok_or_else(|| Error::from_str("Invalid .git directory path"))?
-            .to_path_buf()
-    };
+/// Renders the prompt for `start`, the shared core of every output mode,
+/// recording phase timings in `budget`
+///
+/// Neither the working directory nor the process environment is read:
+/// settings the repository leaves unset come from `env`.
+pub(crate) fn render(
+    start: &Path,
+    env: &Env,
+    budget: &mut Budget,
+) -> Result<Option<String>, Error> {
+    // Ignored directories are never walked, so they can't produce errors either
+    if ignore::matching_pattern(env, start).is_some() {
+        return Ok(None);
+    }
 
-    // 3. Get the actual git directory (handle worktrees)
-    let real_git_dir = if git_dir.is_file() {
-        parse_gitdir_file(&git_dir)?
-    } else {
-        git_dir
+    let env_mode = status::mode(env);
+
+    // A running daemon answers from warm state; otherwise fall back in-process
+    let phase_start = Instant::now();
+    let reply = daemon::query(start, env, env_mode != StatusMode::Off, budget.remaining());
+    budget.record("daemon", phase_start, false);
+
+    let (discovery, daemon_status) = match reply {
+        Some(reply) => match reply.discovery {
+            Some(discovery) => (discovery, reply.status),
+            None => return Ok(None),
+        },
+        None => match discover_cached(start, env, budget) {
+            Ok(Some(discovery)) => (discovery, None),
+            Ok(None) => return Ok(None),
+            // Say so instead of disappearing when the worktree was moved
+            Err(e) => {
+                return match repair::detect(start) {
+                    Some(_) => {
+                        let settings = RepoSettings::default();
+                        let output = repair::broken_output(&Icons::resolve(&settings, env));
+                        Ok(Some(Styles::resolve(&settings, env).escape(&output)))
+                    }
+                    None => Err(e),
+                };
+            }
+        },
     };
 
-    // 4. Check if this is a bare repository
-    if !real_git_dir.join("HEAD").exists() {
-        return Ok(None); // Bare repo
+    let icons = Icons::resolve(&discovery.settings, env);
+    let styles = Styles::resolve(&discovery.settings, env);
+    let equivalence = Equivalence::resolve(&discovery.settings, env);
+    let mut output = format_discovery(&discovery, &icons, &styles, &equivalence);
+    if let Some(remote) = remote::segment(&discovery.remotes, &discovery.settings, &icons, env) {
+        output = format!("{} {}", styles.escape(&remote), output);
     }
 
-    // 5. Read and parse HEAD to get branch name
-    let branch = read_git_head(&real_git_dir)?;
-
-    // 6. Check if we're in a worktree setup (look for .bare parent)
-    if let Some(bare_parent) = find_bare_parent(&work_dir) {
-        let worktree_path = get_relative_path(&bare_parent, &work_dir)?;
+    // What a stopped merge or rebase still needs, e.g. `REBASE 2/5 ✖3`
+    if let Some(operation) = &discovery.operation {
+        output.push(' ');
+        output.push_str(&format_operation(operation, &icons, &styles));
+    }
 
-        // If in bare parent directory (not in any worktree), show [bare]
-        if worktree_path.is_empty() || worktree_path == "." {
-            return Ok(Some(format!("{} [bare]", get_worktree_icon())));
+    if upstream::enabled(&discovery.settings, env)
+        && !matches!(discovery.layout, Layout::BareParent(_))
+        && let Some(upstream) = &discovery.upstream
+    {
+        let glyph = match upstream {
+            Upstream::Unset => Glyph::NoUpstream,
+            Upstream::Gone(_) => Glyph::UpstreamGone,
+            Upstream::Tracking(_) => Glyph::Tracking,
+        };
+        // The tracking glyph is empty by default so the usual case stays quiet
+        let marker = icons.get(glyph);
+        if !marker.is_empty() {
+            output.push(' ');
+            output.push_str(&styles.escape(marker));
         }
+    }
+    if merged::enabled(&discovery.settings, env)
+        && merged::collect(&discovery, env, budget).is_some()
+    {
+        output.push(' ');
+        output.push_str(&styles.escape(icons.get(Glyph::Merged)));
+    }
 
-        // IN ACTUAL WORKTREE
-        let output = format_output_worktree(&worktree_path, &branch);
-        Ok(Some(output))
-    } else {
-        // REGULAR GIT REPO
-        let output = format_output_regular(&branch);
-        Ok(Some(output))
+    // Ahead/behind is only as fresh as the last fetch
+    let stale_after = fetch::stale_after(&discovery.settings, env);
+    if fetch::is_stale(discovery.last_fetch, stale_after, SystemTime::now()) {
+        output.push(' ');
+        output.push_str(&styles.escape(icons.get(Glyph::StaleFetch)));
     }
-}
 
-/// Finds the .git directory by walking up from the current directory
-/// Returns the path to .git (which might be a file or directory)
-fn find_git_dir() -> Result<Option<PathBuf>, Error> {
-    let mut current = env::current_dir()?;
+    // Commands like `log` and `blame` see less in reduced clones
+    let shape = &discovery.shape;
+    let markers: String = [
+        (shape.shallow, Glyph::Shallow),
+        (shape.partial.is_some(), Glyph::Partial),
+        (shape.sparse.is_some(), Glyph::Sparse),
+    ]
+    .into_iter()
+    .filter(|(present, _)| *present)
+    .map(|(_, glyph)| icons.get(glyph))
+    .collect();
+    if !markers.is_empty() {
+        output.push(' ');
+        output.push_str(&styles.escape(&markers));
+    }
 
-    for _ in 0..MAX_GIT_SEARCH_DEPTH {
-        let git_path = current.join(".git");
-        if git_path.exists() {
-            return Ok(Some(git_path));
+    // The repository's `[worktree-prompt]` section wins over the environment
+    let mode = discovery.settings.status_mode().unwrap_or(env_mode);
+
+    // Expensive segments only apply inside a worktree and stop at the deadline
+    let segment = match (mode, daemon_status) {
+        (StatusMode::Off, _) => None,
+        _ if matches!(discovery.layout, Layout::BareParent(_)) => None,
+        (StatusMode::Inline, Some(Segment::Ready(status))) => Some(Segment::Ready(status)),
+        (StatusMode::Inline, _) => Some(status::collect(&discovery.work_dir, budget)),
+        (StatusMode::Async, Some(segment)) => Some(segment),
+        (StatusMode::Async, None) => Some(refresh::collect(&discovery, env, budget)),
+    };
+    if let Some(segment) = segment {
+        let segment = styles.escape(&status::format_segment(&segment, &icons));
+        if !segment.is_empty() {
+            output.push(' ');
+            output.push_str(&segment);
         }
-
-        // Move to parent directory
-        current = match current.parent() {
-            Some(parent) => parent.to_path_buf(),
-            None => return Ok(None), // Reached filesystem root
-        };
     }
 
-    Ok(None) // Not found within max depth
+    Ok(Some(output))
 }
 
-/// Parses a .git file (used in worktrees) to get the real git directory
-/// File format: "gitdir: /path/to/real/.git\n"
-fn parse_gitdir_file(git_file: &Path) -> Result<PathBuf, Error> {
-    let content = fs::read_to_string(git_file)?;
-
-    // Parse "gitdir: /path" format
-    if let Some(path_str) = content.strip_prefix("gitdir: ") {
-        let path_str = path_str.trim();
-        let path = PathBuf::from(path_str);
-
-        // Handle relative paths (relative to .git file location)
-        if path.is_absolute() {
-            Ok(path)
-        } else {
-            let base = git_file
-                .parent()
-                .ok_or_else(|| Error::from_str("Invalid .git file path"))?;
-            Ok(base.join(path))
-        }
-    } else {
-        Err(Error::from_str("Invalid .git file format"))
+/// Discovers the repository for `start`, reusing the runtime cache when valid
+fn discover_cached(
+    start: &Path,
+    env: &Env,
+    budget: &mut Budget,
+) -> Result<Option<Discovery>, Error> {
+    // Reuse cached discovery results when HEAD, index and refs are unchanged
+    let phase_start = Instant::now();
+    let cached = cache::load(start, env);
+    budget.record("cache", phase_start, false);
+    if cached.is_some() {
+        return Ok(cached);
     }
-}
 
-/// Reads and parses .git/HEAD to get the current branch name or commit hash
-fn read_git_head(git_dir: &Path) -> Result<String, Error> {
-    let head_path = git_dir.join("HEAD");
-    let content = fs::read_to_string(head_path)?;
-    let content = content.trim();
-
-    // Case 1: Regular branch (ref: refs/heads/branch-name)
-    if let Some(ref_path) = content.strip_prefix("ref: ") {
-        if let Some(branch) = ref_path.strip_prefix("refs/heads/") {
-            return Ok(branch.to_string());
-        }
-        // Other ref types (tags, remotes) - just return the ref name
-        return Ok(ref_path
-            .rsplit('/')
-            .next()
-            .unwrap_or(ref_path)
-            .to_string());
+    let phase_start = Instant::now();
+    let discovered = discover(start, env)?;
+    budget.record("discovery", phase_start, false);
+    if let Some(discovery) = &discovered {
+        cache::store(start, env, discovery);
     }
+    Ok(discovered)
+}
 
-    // Case 2: Detached HEAD (40-character SHA-1 hash)
-    if content.len() == 40 && content.chars().all(|c| c.is_ascii_hexdigit()) {
-        return Ok(content[..DETACHED_HEAD_HASH_LENGTH].to_string());
-    }
+/// Where the work directory sits relative to a `.bare` worktree layout
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Layout {
+    /// Regular repository without a `.bare` parent
+    Regular,
+    /// The `.bare` parent directory itself, outside any worktree
+    BareParent(PathBuf),
+    /// A worktree below a `.bare` parent, with its path relative to that parent
+    Worktree { bare_parent: PathBuf, path: String },
+}
 
-    // Case 3: Short hash or unknown format
-    if content.len() >= DETACHED_HEAD_HASH_LENGTH {
-        return Ok(content[..DETACHED_HEAD_HASH_LENGTH].to_string());
-    }
+/// Everything `render()` learns about the repository containing the start directory
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Discovery {
+    /// Directory containing the `.git` file or directory
+    pub(crate) work_dir: PathBuf,
+    /// Per-worktree git directory holding HEAD and the index
+    pub(crate) git_dir: PathBuf,
+    /// Shared git directory holding refs, config and objects
+    pub(crate) common_dir: PathBuf,
+    /// Parsed contents of HEAD
+    pub(crate) head: Head,
+    pub(crate) layout: Layout,
+    /// Overrides from the repository's `[worktree-prompt]` config section
+    pub(crate) settings: RepoSettings,
+    /// Upstream state of the checked-out branch, None when HEAD is detached
+    pub(crate) upstream: Option<Upstream>,
+    /// Seconds since the Unix epoch of the last fetch, None if never fetched
+    pub(crate) last_fetch: Option<u64>,
+    /// Remote names and URLs in config order, `insteadOf` already applied
+    pub(crate) remotes: Vec<(String, String)>,
+    /// Superproject context when the work directory is a submodule checkout
+    pub(crate) submodule: Option<Submodule>,
+    /// Shallow, partial and sparse state of the clone and worktree
+    pub(crate) shape: Shape,
+    /// Merge, rebase or similar operation stopped in this worktree
+    pub(crate) operation: Option<Operation>,
+    /// jj's view of the working copy in a colocated repository
+    pub(crate) jj: Option<Jj>,
+}
 
-    Err(Error::from_str("Unknown HEAD format"))
+/// Parsed contents of a HEAD file
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Head {
+    /// Branch name, other ref name, or short commit hash
+    pub(crate) name: String,
+    /// Full ref name when HEAD is symbolic (e.g. `refs/heads/main`)
+    pub(crate) symref: Option<String>,
 }
 
-/// Searches for .bare directory up to MAX_BARE_SEARCH_DEPTH levels
-/// Returns the directory containing .bare, or None for regular repos
-fn find_bare_parent(work_dir: &Path) -> Option<PathBuf> {
-    let mut current = work_dir;
+impl Head {
+    /// The name the prompt shows for a checkout and its full ref when symbolic
+    fn of(checkout: &Checkout) -> Head {
+        match checkout {
+            Checkout::Branch(name) => Head {
+                name: name.clone(),
+                symref: Some(format!("refs/heads/{}", name)),
+            },
+            // Other ref types (tags, remotes) show their last component
+            Checkout::Ref(symref) => Head {
+                name: symref.rsplit('/').next().unwrap_or(symref).to_string(),
+                symref: Some(symref.clone()),
+            },
+            Checkout::Detached(id) => Head {
+                name: id
+                    .get(..DETACHED_HEAD_HASH_LENGTH)
+                    .unwrap_or(id)
+                    .to_string(),
+                symref: None,
+            },
+        }
+    }
+}
 
-    for _ in 0..MAX_BARE_SEARCH_DEPTH {
-        let bare_path = current.join(".bare");
-        if bare_path.is_dir() {
-            return Some(current.to_path_buf());
+/// Discovers the repository containing `start` and collects what the prompt
+/// shows on top of the library's model, or None outside a repo
+pub(crate) fn discover(start: &Path, env: &Env) -> Result<Option<Discovery>, Error> {
+    let Some(repository) = Repository::discover(start, env)? else {
+        return Ok(None);
+    };
+    let common_dir = repository.common_dir;
+
+    // A `.bare` parent outside any worktree shows the bare repository's own HEAD
+    let (work_dir, git_dir, checkout) = match (repository.worktree, &repository.bare_parent) {
+        (Some(worktree), _) => (worktree.path, worktree.git_dir, worktree.checkout),
+        (None, Some(bare_parent)) => {
+            let checkout = trace::step("read_git_head", || read_checkout(&common_dir))?;
+            (bare_parent.clone(), common_dir.clone(), checkout)
         }
+        (None, None) => return Ok(None),
+    };
+    let layout = match repository.bare_parent {
+        Some(bare_parent) if bare_parent == work_dir => Layout::BareParent(bare_parent),
+        Some(bare_parent) => {
+            let path = get_relative_path(&bare_parent, &work_dir)?;
+            Layout::Worktree { bare_parent, path }
+        }
+        None => Layout::Regular,
+    };
 
-        current = current.parent()?;
+    let head = Head::of(&checkout);
+    let config = trace::step("load_settings", || {
+        settings::load_config(&git_dir, &common_dir, &head, env)
+    })?;
+    let settings = RepoSettings::from_config(&config);
+    let upstream = trace::step("read_upstream", || {
+        upstream::resolve(&config, &common_dir, &head)
+    });
+    let last_fetch = trace::step("read_fetch_head", || fetch::last_fetch(&common_dir));
+    // jj keeps HEAD detached, so attached HEADs skip the extra stat
+    let jj = if head.symref.is_none() {
+        trace::step("read_jj", || jj::detect(&work_dir, &git_dir, &common_dir))
+    } else {
+        None
+    };
+    let operation = trace::step("read_operation", || operation::detect(&config, &git_dir));
+    let shape = trace::step("read_shape", || shape::read(&config, &git_dir, &common_dir));
+    let remotes = remote::remotes(&config);
+
+    // Submodule checkouts always use a `.git` file pointing into the parent's `modules/`
+    let submodule = if git_dir != work_dir.join(".git") {
+        trace::step("find_submodule", || {
+            submodule::detect(&work_dir, &git_dir, &common_dir)
+        })
+    } else {
+        None
+    };
+
+    Ok(Some(Discovery {
+        work_dir,
+        git_dir,
+        common_dir,
+        head,
+        layout,
+        settings,
+        upstream,
+        last_fetch,
+        remotes,
+        submodule,
+        shape,
+        operation,
+        jj,
+    }))
+}
+
+/// Renders discovery results into the prompt string
+fn format_discovery(
+    discovery: &Discovery,
+    icons: &Icons,
+    styles: &Styles,
+    equivalence: &Equivalence,
+) -> String {
+    let branch = &discovery.head.name;
+    if let Some(submodule) = &discovery.submodule {
+        return format_output_submodule(icons, styles, submodule, branch);
+    }
+    if let Some(jj) = &discovery.jj {
+        return format_output_jj(icons, styles, jj, branch);
+    }
+    match &discovery.layout {
+        // If in bare parent directory (not in any worktree), show [bare]
+        Layout::BareParent(_) => styles.escape(&format!(
+            "{} {}",
+            icons.get(Glyph::Worktree),
+            icons.get(Glyph::Bare)
+        )),
+        // IN ACTUAL WORKTREE
+        Layout::Worktree { path, .. } => {
+            format_output_worktree(icons, styles, equivalence, path, branch)
+        }
+        // REGULAR GIT REPO
+        Layout::Regular => format_output_regular(icons, styles, branch),
     }
+}
 
-    None
+/// Reads and parses .git/HEAD to get the current branch name or commit hash
+fn read_git_head(git_dir: &Path) -> Result<Head, Error> {
+    read_checkout(git_dir).map(|checkout| Head::of(&checkout))
 }
 
 /// Calculates worktree directory path relative to bare parent directory
 fn get_relative_path(bare_parent: &Path, work_dir: &Path) -> Result<String, Error> {
     let relative = work_dir
         .strip_prefix(bare_parent)
-        .map_err(|e| Error::from_str(&format!("Failed to calculate relative path: {}", e)))?;
+        .map_err(|_| Error::RelativePath {
+            base: bare_parent.to_path_buf(),
+            path: work_dir.to_path_buf(),
+        })?;
 
     Ok(relative.to_string_lossy().to_string())
 }
 
-/// Gets the branch icon from environment variable or returns default
-fn get_branch_icon() -> String {
-    env::var(ENV_BRANCH_ICON).unwrap_or_else(|_| DEFAULT_BRANCH_ICON.to_string())
+/// Formats output for regular (non-worktree) repositories
+fn format_output_regular(icons: &Icons, styles: &Styles, branch: &str) -> String {
+    styles.branch(branch, &format!("{} {}", icons.get(Glyph::Branch), branch))
 }
 
-/// Gets the worktree icon from environment variable or returns default
-fn get_worktree_icon() -> String {
-    env::var(ENV_WORKTREE_ICON).unwrap_or_else(|_| DEFAULT_WORKTREE_ICON.to_string())
+/// Formats an operation label with its progress and conflict count
+fn format_operation(operation: &Operation, icons: &Icons, styles: &Styles) -> String {
+    let mut text = operation.kind.label().to_string();
+    if let Some((step, total)) = operation.progress {
+        text.push_str(&format!(" {}/{}", step, total));
+    }
+    if operation.conflicts > 0 {
+        text.push_str(&format!(
+            " {}{}",
+            icons.get(Glyph::Conflicts),
+            operation.conflicts
+        ));
+    }
+    styles.escape(&text)
 }
 
-/// Formats output for regular (non-worktree) repositories
-fn format_output_regular(branch: &str) -> String {
-    let icon = get_branch_icon();
-    format!("{} {}", icon, branch)
+/// Formats output for a jj working copy as `◉ change-id bookmarks`
+fn format_output_jj(icons: &Icons, styles: &Styles, jj: &Jj, git_head: &str) -> String {
+    let id = jj.change_id.as_deref().unwrap_or(git_head);
+    let mut text = format!("{} {}", icons.get(Glyph::Jj), id);
+    for bookmark in &jj.bookmarks {
+        text.push(' ');
+        text.push_str(bookmark);
+    }
+    // Bookmarks are jj's branches, so branch styles match against them
+    let style_name = jj.bookmarks.first().map_or(id, String::as_str);
+    styles.branch(style_name, &text)
+}
+
+/// Formats output for a submodule checkout as `parent › path@ref`
+fn format_output_submodule(
+    icons: &Icons,
+    styles: &Styles,
+    submodule: &Submodule,
+    branch: &str,
+) -> String {
+    let mut output = styles.branch(
+        branch,
+        &format!(
+            "{} {} {}@{}",
+            submodule.parent_name(),
+            icons.get(Glyph::Submodule),
+            submodule.path,
+            branch
+        ),
+    );
+    if submodule.out_of_sync() {
+        output.push(' ');
+        output.push_str(&styles.escape(icons.get(Glyph::SubmoduleMoved)));
+    }
+    output
 }
 
 /// Formats output for worktree repositories
-fn format_output_worktree(worktree_path: &str, branch: &str) -> String {
-    let worktree_icon = get_worktree_icon();
-    let branch_icon = get_branch_icon();
-    let normalized = normalize_path(worktree_path);
-    if normalized == branch {
-        format!("{} {}", worktree_icon, worktree_path)
+fn format_output_worktree(
+    icons: &Icons,
+    styles: &Styles,
+    equivalence: &Equivalence,
+    worktree_path: &str,
+    branch: &str,
+) -> String {
+    let worktree_icon = styles.escape(icons.get(Glyph::Worktree));
+    let branch_icon = icons.get(Glyph::Branch);
+    let arrow = styles.escape(icons.get(Glyph::Arrow));
+    if equivalence.matches(worktree_path, branch) {
+        // The directory is named after the branch, so it is styled as one
+        format!("{} {}", worktree_icon, styles.branch(branch, worktree_path))
     } else {
-        format!("{} {} → {} {}", worktree_icon, worktree_path, branch_icon, branch)
+        let mut output = format!(
+            "{} {} {} {}",
+            worktree_icon,
+            styles.escape(worktree_path),
+            arrow,
+            styles.branch(branch, &format!("{} {}", branch_icon, branch))
+        );
+        // A protected branch checked out outside its own directory is easy to commit to by mistake
+        if styles.is_protected(branch) {
+            output.push(' ');
+            output.push_str(&styles.branch(branch, icons.get(Glyph::Protected)));
+        }
+        output
     }
 }
 
 /// Converts forward slashes to hyphens for path comparison
-fn normalize_path(path: &str) -> String {
+pub(crate) fn normalize_path(path: &str) -> String {
     path.trim_end_matches('/').replace('/', "-")
 }
 
@@ -292,48 +723,40 @@ fn normalize_path(path: &str) -> String {
 // Error Logging
 // ============================================================================
 
-/// Resolves the error log file path using XDG Base Directory specification
-fn get_log_path() -> Option<PathBuf> {
-    if let Ok(xdg_state) = env::var("XDG_STATE_HOME") {
+/// Resolves the application state directory using XDG Base Directory specification
+pub(crate) fn get_state_dir(env: &Env) -> Option<PathBuf> {
+    if let Some(xdg_state) = env.get("XDG_STATE_HOME") {
         let mut path = PathBuf::from(xdg_state);
         path.push(APP_STATE_DIR);
-        path.push(ERROR_LOG_FILE);
         return Some(path);
     }
 
-    if let Ok(home) = env::var("HOME") {
+    if let Some(home) = env.get("HOME") {
         let mut path = PathBuf::from(home);
         path.push(".local");
         path.push("state");
         path.push(APP_STATE_DIR);
-        path.push(ERROR_LOG_FILE);
         return Some(path);
     }
 
     None
 }
 
-/// Logs errors to XDG_STATE_HOME error log file
-pub(crate) fn log_error(error: &Error) {
-    let log_path = match get_log_path() {
-        Some(path) => path,
-        None => return,
-    };
-
-    if let Some(parent) = log_path.parent() {
-        let _ = fs::create_dir_all(parent);
-    }
+/// Resolves the per-session runtime directory, or None when XDG_RUNTIME_DIR is unset
+pub(crate) fn get_runtime_dir(env: &Env) -> Option<PathBuf> {
+    let mut path = PathBuf::from(env.get("XDG_RUNTIME_DIR")?);
+    path.push(APP_STATE_DIR);
+    Some(path)
+}
 
-    let timestamp = match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
-        Ok(duration) => format!("{}", duration.as_secs()),
-        Err(_) => String::from("unknown"),
+/// Logs errors to XDG_STATE_HOME error log file, see errorlog.rs for the format
+///
+/// `start` is the directory the failed command ran for, logged as its `cwd`.
+pub(crate) fn log_error(error: &Error, start: &Path, env: &Env) {
+    let Some(dir) = get_state_dir(env) else {
+        return;
     };
-
-    let message = format!("[{}] {}\n", timestamp, error);
-
-    if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(&log_path) {
-        let _ = file.write_all(message.as_bytes());
-    }
+    errorlog::append(&dir, &errorlog::Entry::new(error, start), SystemTime::now());
 }
 
 // ============================================================================
@@ -343,7 +766,23 @@ pub(crate) fn log_error(error: &Error) {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use serial_test::serial;
+    use pointers::Breakage;
+    use std::io;
+
+    /// Default icons, for a repository without overrides
+    fn icons() -> Icons {
+        icons_from(&Env::new())
+    }
+
+    /// Icons with the environment overrides in `env`
+    fn icons_from(env: &Env) -> Icons {
+        Icons::resolve(&RepoSettings::default(), env)
+    }
+
+    /// Default styles, for a repository without overrides
+    fn styles() -> Styles {
+        Styles::resolve(&RepoSettings::default(), &Env::new())
+    }
 
     #[test]
     fn test_normalize_path() {
@@ -354,143 +793,281 @@ mod tests {
 
     #[test]
     fn test_format_output_regular() {
-        assert_eq!(format_output_regular("main"), "⎇ main");
+        assert_eq!(format_output_regular(&icons(), &styles(), "main"), "⎇ main");
         assert_eq!(
-            format_output_regular("feature/add-tests"),
+            format_output_regular(&icons(), &styles(), "feature/add-tests"),
             "⎇ feature/add-tests"
         );
-        assert_eq!(format_output_regular("a1b2c3d"), "⎇ a1b2c3d");
+        assert_eq!(
+            format_output_regular(&icons(), &styles(), "a1b2c3d"),
+            "⎇ a1b2c3d"
+        );
     }
 
     #[test]
     fn test_format_output_worktree_match() {
         assert_eq!(
-            format_output_worktree("DEV-123/fix-thing", "DEV-123-fix-thing"),
+            format_output_worktree(
+                &icons(),
+                &styles(),
+                &Equivalence::default(),
+                "DEV-123/fix-thing",
+                "DEV-123-fix-thing"
+            ),
             "🌳 DEV-123/fix-thing"
         );
         assert_eq!(
-            format_output_worktree("feature/add-tests", "feature-add-tests"),
+            format_output_worktree(
+                &icons(),
+                &styles(),
+                &Equivalence::default(),
+                "feature/add-tests",
+                "feature-add-tests"
+            ),
             "🌳 feature/add-tests"
         );
-        assert_eq!(format_output_worktree("simple", "simple"), "🌳 simple");
+        assert_eq!(
+            format_output_worktree(
+                &icons(),
+                &styles(),
+                &Equivalence::default(),
+                "simple",
+                "simple"
+            ),
+            "🌳 simple"
+        );
+    }
+
+    #[test]
+    fn test_format_output_worktree_equivalence_rules() {
+        let strip = Equivalence {
+            strip_prefixes: vec!["kyle/".to_string(), "feature/".to_string()],
+            ..Equivalence::default()
+        };
+        let ignore_case = Equivalence {
+            ignore_case: true,
+            ..Equivalence::default()
+        };
+        let separators = Equivalence {
+            separators: "_.".to_string(),
+            ..Equivalence::default()
+        };
+        let ticket = Equivalence {
+            ticket_match: true,
+            ..Equivalence::default()
+        };
+
+        for (rule, equivalence, path, branch, hidden) in [
+            (
+                "default",
+                &Equivalence::default(),
+                "DEV-123",
+                "kyle/DEV-123",
+                false,
+            ),
+            (
+                "default",
+                &Equivalence::default(),
+                "dev-123",
+                "DEV-123",
+                false,
+            ),
+            ("strip", &strip, "DEV-123", "kyle/DEV-123", true),
+            ("strip", &strip, "kyle/DEV-123", "DEV-123", true),
+            ("strip", &strip, "add-tests", "feature/add-tests", true),
+            ("strip", &strip, "DEV-123", "bob/DEV-123", false),
+            ("strip", &strip, "kyle", "kyle/", false),
+            ("ignore_case", &ignore_case, "dev-123", "DEV-123", true),
+            ("ignore_case", &ignore_case, "Feature/X", "feature-x", true),
+            ("ignore_case", &ignore_case, "dev-124", "DEV-123", false),
+            ("separators", &separators, "fix_thing", "fix-thing", true),
+            ("separators", &separators, "v1.2", "v1_2", true),
+            ("separators", &separators, "fix+thing", "fix-thing", false),
+            ("ticket", &ticket, "DEV-123", "kyle/DEV-123-fix-thing", true),
+            ("ticket", &ticket, "DEV-123-wip", "DEV-123", true),
+            ("ticket", &ticket, "DEV-123", "DEV-1234", false),
+            ("ticket", &ticket, "release-1", "release-1.5", false),
+        ] {
+            let output = format_output_worktree(&icons(), &styles(), equivalence, path, branch);
+            assert_eq!(
+                !output.contains('→'),
+                hidden,
+                "{}: {} on {} gave {}",
+                rule,
+                path,
+                branch,
+                output
+            );
+        }
     }
 
     #[test]
     fn test_format_output_worktree_mismatch() {
         assert_eq!(
-            format_output_worktree("DEV-123/fix-thing", "main"),
+            format_output_worktree(
+                &icons(),
+                &styles(),
+                &Equivalence::default(),
+                "DEV-123/fix-thing",
+                "main"
+            ),
             "🌳 DEV-123/fix-thing → ⎇ main"
         );
         assert_eq!(
-            format_output_worktree("feature/add-tests", "main"),
+            format_output_worktree(
+                &icons(),
+                &styles(),
+                &Equivalence::default(),
+                "feature/add-tests",
+                "main"
+            ),
             "🌳 feature/add-tests → ⎇ main"
         );
         assert_eq!(
-            format_output_worktree("DEV-123/fix-thing", "a1b2c3d"),
+            format_output_worktree(
+                &icons(),
+                &styles(),
+                &Equivalence::default(),
+                "DEV-123/fix-thing",
+                "a1b2c3d"
+            ),
             "🌳 DEV-123/fix-thing → ⎇ a1b2c3d"
         );
     }
 
     #[test]
-    #[serial]
-    fn test_log_error_no_panic_without_env() {
-        let home_backup = env::var("HOME").ok();
-        let xdg_backup = env::var("XDG_STATE_HOME").ok();
-
-        unsafe {
-            env::remove_var("HOME");
-            env::remove_var("XDG_STATE_HOME");
-        }
+    fn test_error_exit_codes_are_distinct() {
+        let errors = [
+            Error::command("x"),
+            Error::Usage("x".to_string()),
+            Error::io(Path::new("/x"), io::Error::other("x")),
+            Error::InvalidGitdirFile {
+                path: PathBuf::from("/x/.git"),
+            },
+            Error::UnknownHeadFormat {
+                path: PathBuf::from("/x/.git/HEAD"),
+            },
+            Error::RelativePath {
+                base: PathBuf::from("/a"),
+                path: PathBuf::from("/b"),
+            },
+            Error::ConfigParse {
+                path: PathBuf::from("/x/config"),
+                line: 1,
+                reason: "x".to_string(),
+            },
+            Error::BrokenWorktree(Breakage::MissingAdminDir {
+                dot_git: PathBuf::from("/x/.git"),
+                admin_dir: PathBuf::from("/y"),
+            }),
+        ];
+
+        let mut codes: Vec<i32> = errors.iter().map(Error::exit_code).collect();
+        let mut categories: Vec<&str> = errors.iter().map(Error::category).collect();
+        codes.sort();
+        codes.dedup();
+        categories.sort();
+        categories.dedup();
+        assert_eq!(codes.len(), errors.len());
+        assert_eq!(categories.len(), errors.len());
+        assert!(!codes.contains(&0));
+    }
 
-        let error = Error::from_str("Test error");
-        log_error(&error);
+    #[test]
+    fn test_head_of() {
+        let head = |checkout: Checkout| {
+            let head = Head::of(&checkout);
+            (head.name, head.symref)
+        };
+        assert_eq!(
+            head(Checkout::Branch("feat/x".to_string())),
+            ("feat/x".to_string(), Some("refs/heads/feat/x".to_string()))
+        );
+        assert_eq!(
+            head(Checkout::Ref("refs/remotes/origin/main".to_string())),
+            (
+                "main".to_string(),
+                Some("refs/remotes/origin/main".to_string())
+            )
+        );
+        assert_eq!(
+            head(Checkout::Detached("a".repeat(40))),
+            ("aaaaaaa".to_string(), None)
+        );
+        // Callers can build any id; odd ones are shown whole rather than cut mid-character
+        assert_eq!(
+            head(Checkout::Detached("ééééé".to_string())),
+            ("ééééé".to_string(), None)
+        );
+    }
 
-        unsafe {
-            if let Some(home) = home_backup {
-                env::set_var("HOME", home);
-            }
-            if let Some(xdg) = xdg_backup {
-                env::set_var("XDG_STATE_HOME", xdg);
-            }
-        }
+    #[test]
+    fn test_log_error_no_panic_without_env() {
+        let error = Error::command("Test error");
+        log_error(&error, Path::new("/"), &Env::new());
     }
 
     #[test]
-    #[serial]
     fn test_custom_branch_icon() {
-        let backup = env::var(ENV_BRANCH_ICON).ok();
-
-        unsafe {
-            env::set_var(ENV_BRANCH_ICON, "🔀");
-        }
+        let icons = icons_from(&Env::new().with(ENV_BRANCH_ICON, "🔀"));
 
-        assert_eq!(format_output_regular("main"), "🔀 main");
+        assert_eq!(format_output_regular(&icons, &styles(), "main"), "🔀 main");
         assert_eq!(
-            format_output_worktree("feature/test", "main"),
+            format_output_worktree(
+                &icons,
+                &styles(),
+                &Equivalence::default(),
+                "feature/test",
+                "main"
+            ),
             "🌳 feature/test → 🔀 main"
         );
-
-        unsafe {
-            match backup {
-                Some(val) => env::set_var(ENV_BRANCH_ICON, val),
-                None => env::remove_var(ENV_BRANCH_ICON),
-            }
-        }
     }
 
     #[test]
-    #[serial]
     fn test_custom_worktree_icon() {
-        let backup = env::var(ENV_WORKTREE_ICON).ok();
-
-        unsafe {
-            env::set_var(ENV_WORKTREE_ICON, "📁");
-        }
+        let icons = icons_from(&Env::new().with(ENV_WORKTREE_ICON, "📁"));
 
         assert_eq!(
-            format_output_worktree("feature/test", "feature-test"),
+            format_output_worktree(
+                &icons,
+                &styles(),
+                &Equivalence::default(),
+                "feature/test",
+                "feature-test"
+            ),
             "📁 feature/test"
         );
         assert_eq!(
-            format_output_worktree("feature/test", "main"),
+            format_output_worktree(
+                &icons,
+                &styles(),
+                &Equivalence::default(),
+                "feature/test",
+                "main"
+            ),
             "📁 feature/test → ⎇ main"
         );
-
-        unsafe {
-            match backup {
-                Some(val) => env::set_var(ENV_WORKTREE_ICON, val),
-                None => env::remove_var(ENV_WORKTREE_ICON),
-            }
-        }
     }
 
     #[test]
-    #[serial]
     fn test_custom_both_icons() {
-        let branch_backup = env::var(ENV_BRANCH_ICON).ok();
-        let worktree_backup = env::var(ENV_WORKTREE_ICON).ok();
+        let env = Env::new()
+            .with(ENV_BRANCH_ICON, "")
+            .with(ENV_WORKTREE_ICON, "→");
+        let icons = icons_from(&env);
 
-        unsafe {
-            env::set_var(ENV_BRANCH_ICON, "");
-            env::set_var(ENV_WORKTREE_ICON, "→");
-        }
-
-        assert_eq!(format_output_regular("main"), " main");
+        assert_eq!(format_output_regular(&icons, &styles(), "main"), " main");
         assert_eq!(
-            format_output_worktree("feature/test", "main"),
+            format_output_worktree(
+                &icons,
+                &styles(),
+                &Equivalence::default(),
+                "feature/test",
+                "main"
+            ),
             "→ feature/test →  main"
         );
-
-        unsafe {
-            match branch_backup {
-                Some(val) => env::set_var(ENV_BRANCH_ICON, val),
-                None => env::remove_var(ENV_BRANCH_ICON),
-            }
-            match worktree_backup {
-                Some(val) => env::set_var(ENV_WORKTREE_ICON, val),
-                None => env::remove_var(ENV_WORKTREE_ICON),
-            }
-        }
     }
 }
 
@@ -0,0 +1,398 @@
+use crate::budget::Budget;
+use crate::cache::fnv1a;
+use crate::pointers::{read_back_pointer, same_file};
+use crate::refs;
+use crate::settings::{self, RepoSettings};
+use crate::status::run_git;
+use crate::upstream::{self, Upstream};
+use crate::{Discovery, Env, Error, IoContext, Layout, discover, get_runtime_dir, read_git_head};
+use std::fmt;
+use std::fs;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+// ============================================================================
+// Merged Worktree Detection and Pruning
+// ============================================================================
+//
+// A worktree is done with when its branch is fully merged into the default
+// branch, or when the upstream it tracked was deleted and pruned by a fetch.
+// With GIT_WORKTREE_PROMPT_MERGED=1 (or `worktree-prompt.merged`) the prompt
+// marks such worktrees, and `git-worktree-prompt prune-merged` lists them and
+// removes them after confirmation.
+//
+// The upstream check reads config and refs only. The merge check needs the
+// commit graph, so it asks `git merge-base --is-ancestor` under the time
+// budget and remembers the answer per pair of commits in the runtime
+// directory; a branch that was just created from the default branch and has
+// no commits of its own does not count as merged.
+
+/// Environment variable enabling the merged marker
+pub(crate) const ENV_MERGED: &str = "GIT_WORKTREE_PROMPT_MERGED";
+
+/// Environment variable naming the default branch, e.g. `main` or `origin/main`
+pub(crate) const ENV_DEFAULT_BRANCH: &str = "GIT_WORKTREE_PROMPT_DEFAULT_BRANCH";
+
+/// Subdirectory of the runtime directory remembering ancestry answers
+const ANCESTRY_DIR: &str = "merged";
+
+/// Longest `prune-merged` waits for one git command
+const GIT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Why a worktree's branch is considered done with
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Reason {
+    /// The default branch contains the branch tip and has moved past it
+    Merged { into: String },
+    /// The configured upstream no longer exists as a remote-tracking ref
+    UpstreamGone { upstream: String },
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reason::Merged { into } => write!(f, "merged into {}", into),
+            Reason::UpstreamGone { upstream } => write!(f, "upstream {} is gone", upstream),
+        }
+    }
+}
+
+/// True when the repository or the environment turns the marker on
+pub(crate) fn enabled(settings: &RepoSettings, env: &Env) -> bool {
+    let value = settings
+        .get_or_env("merged", env, ENV_MERGED)
+        .unwrap_or_default();
+    matches!(value.to_lowercase().as_str(), "1" | "true" | "yes" | "on")
+}
+
+/// Checks the discovered worktree's branch under the budget, for the prompt
+pub(crate) fn collect(discovery: &Discovery, env: &Env, budget: &mut Budget) -> Option<Reason> {
+    let started = Instant::now();
+    let branch = discovery
+        .head
+        .symref
+        .as_deref()?
+        .strip_prefix("refs/heads/")?;
+    if matches!(discovery.layout, Layout::BareParent(_)) {
+        return None;
+    }
+    if budget.exhausted() {
+        budget.record("merged", started, true);
+        return None;
+    }
+
+    let reason = check(
+        &discovery.work_dir,
+        &discovery.common_dir,
+        branch,
+        &discovery.settings,
+        discovery.upstream.as_ref(),
+        env,
+        budget.deadline(),
+    );
+    budget.record("merged", started, budget.exhausted());
+    reason
+}
+
+/// Decides whether `branch` is done with, giving up on the merge check at `deadline`
+pub(crate) fn check(
+    work_dir: &Path,
+    common_dir: &Path,
+    branch: &str,
+    settings: &RepoSettings,
+    upstream: Option<&Upstream>,
+    env: &Env,
+    deadline: Instant,
+) -> Option<Reason> {
+    if let Some(gone @ Upstream::Gone(_)) = upstream {
+        return Some(Reason::UpstreamGone {
+            upstream: gone.name()?.to_string(),
+        });
+    }
+
+    let default = default_branch(common_dir, settings, env)?;
+    if branch_name(&default) == branch {
+        return None;
+    }
+    let tip = refs::resolve(common_dir, &format!("refs/heads/{}", branch))?;
+    let default_tip = refs::resolve(common_dir, &default)?;
+    if tip == default_tip {
+        return None;
+    }
+
+    is_ancestor(work_dir, &tip, &default_tip, env, deadline)?.then(|| Reason::Merged {
+        into: short_name(&default).to_string(),
+    })
+}
+
+/// Full ref of the default branch: the repository's `defaultBranch`, the
+/// environment, the remote's `origin/HEAD`, then `main` or `master`
+pub(crate) fn default_branch(
+    common_dir: &Path,
+    settings: &RepoSettings,
+    env: &Env,
+) -> Option<String> {
+    let configured = settings
+        .get_or_env("defaultBranch", env, ENV_DEFAULT_BRANCH)
+        .filter(|name| !name.is_empty());
+    if let Some(name) = configured {
+        return [
+            format!("refs/heads/{}", name),
+            format!("refs/remotes/{}", name),
+            name.to_string(),
+        ]
+        .into_iter()
+        .find(|candidate| refs::exists(common_dir, candidate));
+    }
+
+    refs::symref_target(common_dir, "refs/remotes/origin/HEAD").or_else(|| {
+        ["refs/heads/main", "refs/heads/master"]
+            .into_iter()
+            .find(|candidate| refs::exists(common_dir, candidate))
+            .map(str::to_string)
+    })
+}
+
+/// `main` for `refs/heads/main`, `origin/main` for `refs/remotes/origin/main`
+fn short_name(refname: &str) -> &str {
+    refname
+        .strip_prefix("refs/heads/")
+        .or_else(|| refname.strip_prefix("refs/remotes/"))
+        .unwrap_or(refname)
+}
+
+/// Branch name without the remote, so `main` is never merged into `origin/main`
+fn branch_name(refname: &str) -> &str {
+    match refname.strip_prefix("refs/remotes/") {
+        Some(rest) => rest.split_once('/').map_or(rest, |(_, name)| name),
+        None => short_name(refname),
+    }
+}
+
+/// Asks git whether `tip` is reachable from `target`, remembering the answer
+///
+/// Only git's yes (0) and no (1) are answers; other exits, such as 128 for an
+/// object missing from a partial clone, are failures and are not remembered.
+pub(crate) fn is_ancestor(
+    work_dir: &Path,
+    tip: &str,
+    target: &str,
+    env: &Env,
+    deadline: Instant,
+) -> Option<bool> {
+    let memo = get_runtime_dir(env).map(|dir| {
+        dir.join(ANCESTRY_DIR).join(format!(
+            "{:016x}",
+            fnv1a(format!("{} {}", tip, target).as_bytes())
+        ))
+    });
+    if let Some(answer) = memo.as_ref().and_then(|path| fs::read_to_string(path).ok()) {
+        return Some(answer == "1");
+    }
+
+    let (exit, _) = run_git(
+        work_dir,
+        &["merge-base", "--is-ancestor", tip, target],
+        deadline,
+    )?;
+    let ancestor = match exit.code()? {
+        0 => true,
+        1 => false,
+        _ => return None,
+    };
+    if let Some(path) = memo
+        && let Some(parent) = path.parent()
+        && fs::create_dir_all(parent).is_ok()
+    {
+        let _ = fs::write(&path, if ancestor { "1" } else { "0" });
+    }
+    Some(ancestor)
+}
+
+// ============================================================================
+// prune-merged
+// ============================================================================
+
+/// A linked worktree whose branch is done with
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Candidate {
+    pub(crate) path: PathBuf,
+    pub(crate) branch: String,
+    pub(crate) reason: Reason,
+    /// Why the worktree must be left alone, if it must
+    pub(crate) blocker: Option<&'static str>,
+}
+
+/// Lists the repository's linked worktrees whose branches are done with
+pub(crate) fn candidates(start: &Path, env: &Env) -> Result<Vec<Candidate>, Error> {
+    let discovery = discover(start, env)?
+        .ok_or_else(|| Error::command("prune-merged: not in a git repository"))?;
+    let worktrees = discovery.common_dir.join("worktrees");
+    let Ok(entries) = fs::read_dir(&worktrees) else {
+        return Ok(Vec::new());
+    };
+    let mut admin_dirs: Vec<PathBuf> = entries.flatten().map(|e| e.path()).collect();
+    admin_dirs.sort();
+
+    let mut candidates = Vec::new();
+    for admin_dir in admin_dirs {
+        let Some(work_dir) = read_back_pointer(&admin_dir)
+            .parent()
+            .map(Path::to_path_buf)
+        else {
+            continue;
+        };
+        if !work_dir.is_dir() {
+            continue; // Already gone; `git worktree prune` cleans up after it
+        }
+        let Ok(head) = read_git_head(&admin_dir) else {
+            continue;
+        };
+        let Some(branch) = head
+            .symref
+            .as_deref()
+            .and_then(|r| r.strip_prefix("refs/heads/"))
+        else {
+            continue;
+        };
+        let config = settings::load_config(&admin_dir, &discovery.common_dir, &head, env)?;
+        let settings = RepoSettings::from_config(&config);
+        let upstream = upstream::resolve(&config, &discovery.common_dir, &head);
+        let deadline = Instant::now() + GIT_TIMEOUT;
+        let Some(reason) = check(
+            &work_dir,
+            &discovery.common_dir,
+            branch,
+            &settings,
+            upstream.as_ref(),
+            env,
+            deadline,
+        ) else {
+            continue;
+        };
+
+        candidates.push(Candidate {
+            blocker: blocker(&admin_dir, &work_dir, &discovery.work_dir),
+            path: work_dir,
+            branch: branch.to_string(),
+            reason,
+        });
+    }
+    Ok(candidates)
+}
+
+/// Reason to keep a worktree even though its branch is done with
+fn blocker(admin_dir: &Path, work_dir: &Path, current: &Path) -> Option<&'static str> {
+    if admin_dir.join("locked").exists() {
+        return Some("locked");
+    }
+    if admin_dir.join("index.lock").exists() {
+        return Some("index.lock present");
+    }
+    if same_file(work_dir, current) {
+        return Some("current worktree");
+    }
+    match run_git(
+        work_dir,
+        &["status", "--porcelain"],
+        Instant::now() + GIT_TIMEOUT,
+    ) {
+        Some((exit, output)) if exit.success() && output.is_empty() => None,
+        Some((exit, _)) if exit.success() => Some("has local changes"),
+        _ => Some("git status failed"),
+    }
+}
+
+/// Lists candidates on `out`, asks on `input` unless `yes`, and removes the
+/// worktrees that have no blocker; branches are kept
+pub(crate) fn prune(
+    start: &Path,
+    env: &Env,
+    yes: bool,
+    input: &mut impl BufRead,
+    out: &mut impl Write,
+) -> Result<(), Error> {
+    let stdout = Path::new("stdout");
+    let candidates = candidates(start, env)?;
+    if candidates.is_empty() {
+        writeln!(out, "No merged worktrees.").with_path(stdout)?;
+        return Ok(());
+    }
+
+    for candidate in &candidates {
+        let skipped = candidate
+            .blocker
+            .map_or(String::new(), |blocker| format!(" (skipped: {})", blocker));
+        writeln!(
+            out,
+            "{}  {}  {}{}",
+            candidate.path.display(),
+            candidate.branch,
+            candidate.reason,
+            skipped
+        )
+        .with_path(stdout)?;
+    }
+
+    let removable: Vec<&Candidate> = candidates.iter().filter(|c| c.blocker.is_none()).collect();
+    if removable.is_empty() {
+        return Ok(());
+    }
+    if !yes {
+        let plural = if removable.len() == 1 { "" } else { "s" };
+        write!(out, "Remove {} worktree{}? [y/N] ", removable.len(), plural).with_path(stdout)?;
+        out.flush().with_path(stdout)?;
+        let mut answer = String::new();
+        input.read_line(&mut answer).with_path(Path::new("stdin"))?;
+        if !matches!(answer.trim().to_lowercase().as_str(), "y" | "yes") {
+            return Ok(());
+        }
+    }
+
+    for candidate in removable {
+        let path = candidate.path.to_string_lossy();
+        // Without --force git refuses on its own if the worktree changed meanwhile
+        match run_git(
+            start,
+            &["worktree", "remove", &path],
+            Instant::now() + GIT_TIMEOUT,
+        ) {
+            Some((exit, _)) if exit.success() => writeln!(
+                out,
+                "Removed {} (branch {} kept)",
+                candidate.path.display(),
+                candidate.branch
+            )
+            .with_path(stdout)?,
+            _ => {
+                return Err(Error::Command(format!(
+                    "prune-merged: git worktree remove {} failed",
+                    path
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_branch_names() {
+        for (refname, short, branch) in [
+            ("refs/heads/main", "main", "main"),
+            ("refs/remotes/origin/main", "origin/main", "main"),
+            (
+                "refs/remotes/origin/release/2.0",
+                "origin/release/2.0",
+                "release/2.0",
+            ),
+        ] {
+            assert_eq!(short_name(refname), short);
+            assert_eq!(branch_name(refname), branch);
+        }
+    }
+}
@@ -0,0 +1,214 @@
+use crate::gitconfig::Config;
+use crate::index;
+use crate::trace;
+use std::path::Path;
+
+// ============================================================================
+// Operation State
+// ============================================================================
+//
+// A merge, rebase, cherry-pick, revert, `git am` or bisect that stops halfway
+// leaves marker files in the worktree's git directory, the same ones
+// git-prompt.sh looks for:
+//
+//   rebase-merge/          REBASE, progress in `msgnum` of `end`
+//   rebase-apply/          REBASE or AM, progress in `next` of `last`
+//   MERGE_HEAD             MERGING
+//   CHERRY_PICK_HEAD       CHERRY-PICKING
+//   REVERT_HEAD            REVERTING
+//   BISECT_LOG             BISECTING
+//
+// While one is in progress the index is read as well, to count the paths
+// still conflicted: those with stage 1-3 entries instead of a stage-0 one.
+// Outside an operation the index is never read, so large repositories pay
+// nothing for this.
+
+/// Kind of operation in progress
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Kind {
+    Rebase,
+    Am,
+    Merge,
+    CherryPick,
+    Revert,
+    Bisect,
+}
+
+impl Kind {
+    /// Label git-prompt.sh uses for the operation
+    pub(crate) fn label(self) -> &'static str {
+        match self {
+            Kind::Rebase => "REBASE",
+            Kind::Am => "AM",
+            Kind::Merge => "MERGING",
+            Kind::CherryPick => "CHERRY-PICKING",
+            Kind::Revert => "REVERTING",
+            Kind::Bisect => "BISECTING",
+        }
+    }
+
+    pub(crate) fn parse(label: &str) -> Option<Kind> {
+        [
+            Kind::Rebase,
+            Kind::Am,
+            Kind::Merge,
+            Kind::CherryPick,
+            Kind::Revert,
+            Kind::Bisect,
+        ]
+        .into_iter()
+        .find(|kind| kind.label() == label)
+    }
+}
+
+/// An operation stopped halfway and what is left to resolve
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Operation {
+    pub(crate) kind: Kind,
+    /// Current step and total for rebases and `git am`
+    pub(crate) progress: Option<(u32, u32)>,
+    /// Paths with unmerged index entries
+    pub(crate) conflicts: usize,
+}
+
+/// Marker files whose appearance or removal changes the operation state
+pub(crate) const MARKERS: &[&str] = &[
+    "rebase-merge/msgnum",
+    "rebase-apply/next",
+    "MERGE_HEAD",
+    "CHERRY_PICK_HEAD",
+    "REVERT_HEAD",
+    "BISECT_LOG",
+];
+
+/// Operation in progress in the worktree owning `git_dir`, if any
+pub(crate) fn detect(config: &Config, git_dir: &Path) -> Option<Operation> {
+    let (kind, progress) = if trace::is_dir(&git_dir.join("rebase-merge")) {
+        let dir = git_dir.join("rebase-merge");
+        (Kind::Rebase, progress(&dir, "msgnum", "end"))
+    } else if trace::is_dir(&git_dir.join("rebase-apply")) {
+        let dir = git_dir.join("rebase-apply");
+        let kind = if trace::exists(&dir.join("rebasing")) {
+            Kind::Rebase
+        } else {
+            Kind::Am
+        };
+        (kind, progress(&dir, "next", "last"))
+    } else {
+        let kind = [
+            ("MERGE_HEAD", Kind::Merge),
+            ("CHERRY_PICK_HEAD", Kind::CherryPick),
+            ("REVERT_HEAD", Kind::Revert),
+            ("BISECT_LOG", Kind::Bisect),
+        ]
+        .into_iter()
+        .find(|(file, _)| trace::exists(&git_dir.join(file)))
+        .map(|(_, kind)| kind)?;
+        (kind, None)
+    };
+
+    let hash_len = if config.get("extensions.objectFormat") == Some("sha256") {
+        32
+    } else {
+        20
+    };
+    let conflicts = trace::read(&git_dir.join("index"))
+        .ok()
+        .and_then(|data| index::conflicts(&data, hash_len))
+        .unwrap_or(0);
+
+    Some(Operation {
+        kind,
+        progress,
+        conflicts,
+    })
+}
+
+/// Reads a `step`/`total` pair of counter files
+fn progress(dir: &Path, step: &str, total: &str) -> Option<(u32, u32)> {
+    let read = |name: &str| -> Option<u32> {
+        trace::read_to_string(&dir.join(name))
+            .ok()?
+            .trim()
+            .parse()
+            .ok()
+    };
+    Some((read(step)?, read(total)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_detect_each_operation() {
+        let dir = TempDir::new().unwrap();
+        let git_dir = dir.path();
+        let config = Config::parse(&git_dir.join("config"), "").unwrap();
+        assert_eq!(detect(&config, git_dir), None);
+
+        fs::write(git_dir.join("BISECT_LOG"), "").unwrap();
+        fs::write(git_dir.join("MERGE_HEAD"), "").unwrap();
+        fs::write(
+            git_dir.join("index"),
+            index::build(
+                2,
+                &[
+                    (0o100644, 1, 2, "a.txt"),
+                    (0o100644, 2, 3, "a.txt"),
+                    (0o100644, 3, 0, "b.txt"),
+                ],
+            ),
+        )
+        .unwrap();
+        assert_eq!(
+            detect(&config, git_dir),
+            Some(Operation {
+                kind: Kind::Merge,
+                progress: None,
+                conflicts: 1,
+            }),
+            "A merge wins over the bisect it happens in"
+        );
+
+        let apply = git_dir.join("rebase-apply");
+        fs::create_dir(&apply).unwrap();
+        fs::write(apply.join("next"), "2\n").unwrap();
+        fs::write(apply.join("last"), "3\n").unwrap();
+        let operation = detect(&config, git_dir).unwrap();
+        assert_eq!(
+            (operation.kind, operation.progress),
+            (Kind::Am, Some((2, 3)))
+        );
+        fs::write(apply.join("rebasing"), "").unwrap();
+        assert_eq!(detect(&config, git_dir).unwrap().kind, Kind::Rebase);
+
+        let merge = git_dir.join("rebase-merge");
+        fs::create_dir(&merge).unwrap();
+        fs::write(merge.join("msgnum"), "4\n").unwrap();
+        assert_eq!(
+            detect(&config, git_dir).unwrap().progress,
+            None,
+            "No total yet"
+        );
+        fs::write(merge.join("end"), "7\n").unwrap();
+        assert_eq!(detect(&config, git_dir).unwrap().progress, Some((4, 7)));
+    }
+
+    #[test]
+    fn test_labels_roundtrip() {
+        for kind in [
+            Kind::Rebase,
+            Kind::Am,
+            Kind::Merge,
+            Kind::CherryPick,
+            Kind::Revert,
+            Kind::Bisect,
+        ] {
+            assert_eq!(Kind::parse(kind.label()), Some(kind));
+        }
+        assert_eq!(Kind::parse("merging"), None);
+    }
+}
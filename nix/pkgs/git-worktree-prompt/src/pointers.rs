@@ -0,0 +1,144 @@
+use crate::trace;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// ============================================================================
+// Worktree Pointers
+// ============================================================================
+//
+// A linked worktree is held together by two pointers: its `.git` file names
+// the admin directory (`<common_dir>/worktrees/<name>`), and that directory's
+// `gitdir` file names the `.git` file back. Moving the `.bare` parent breaks
+// the first, moving the worktree breaks the second. Discovery checks both
+// and reports either as a broken worktree; repair.rs rewrites them.
+
+/// Which of the two worktree pointers is broken
+#[derive(Debug, Clone, PartialEq)]
+pub enum Breakage {
+    /// The worktree's `.git` file names an admin directory that does not exist
+    MissingAdminDir {
+        dot_git: PathBuf,
+        admin_dir: PathBuf,
+    },
+    /// The admin directory's `gitdir` file names a different location
+    StaleBackPointer {
+        admin_dir: PathBuf,
+        points_at: PathBuf,
+    },
+}
+
+impl fmt::Display for Breakage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Breakage::MissingAdminDir { dot_git, admin_dir } => write!(
+                f,
+                "broken worktree: {} points at missing {}",
+                dot_git.display(),
+                admin_dir.display()
+            ),
+            Breakage::StaleBackPointer {
+                admin_dir,
+                points_at,
+            } => write!(
+                f,
+                "broken worktree: {} points at {}",
+                admin_dir.join("gitdir").display(),
+                points_at.display()
+            ),
+        }
+    }
+}
+
+/// Checks both pointers of a `.git` file that resolved to `git_dir`
+pub fn check(dot_git: &Path, git_dir: &Path) -> Option<Breakage> {
+    if !trace::is_dir(git_dir) {
+        return Some(Breakage::MissingAdminDir {
+            dot_git: dot_git.to_path_buf(),
+            admin_dir: git_dir.to_path_buf(),
+        });
+    }
+
+    // Submodules and --separate-git-dir checkouts have no back-pointer to check
+    let content = trace::read_to_string(&git_dir.join("gitdir")).ok()?;
+    let points_at = resolve(git_dir, content.trim());
+    if same_file(&points_at, dot_git) {
+        None
+    } else {
+        Some(Breakage::StaleBackPointer {
+            admin_dir: git_dir.to_path_buf(),
+            points_at,
+        })
+    }
+}
+
+/// Path an admin directory's `gitdir` file names, i.e. its worktree's `.git` file
+pub fn read_back_pointer(admin_dir: &Path) -> PathBuf {
+    let content = fs::read_to_string(admin_dir.join("gitdir")).unwrap_or_default();
+    resolve(admin_dir, content.trim())
+}
+
+/// Resolves a path stored in a git admin file relative to the directory holding it
+pub fn resolve(base: &Path, path: &str) -> PathBuf {
+    let path = PathBuf::from(path);
+    if path.is_absolute() {
+        path
+    } else {
+        base.join(path)
+    }
+}
+
+/// Compares paths literally first, then after resolving symlinks
+pub fn same_file(a: &Path, b: &Path) -> bool {
+    if a == b {
+        return true;
+    }
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_check_detects_both_directions() {
+        let root = TempDir::new().unwrap();
+        let admin_dir = root.path().join(".bare/worktrees/feature");
+        let work_dir = root.path().join("feature");
+        fs::create_dir_all(&admin_dir).unwrap();
+        fs::create_dir_all(&work_dir).unwrap();
+        let dot_git = work_dir.join(".git");
+        fs::write(&dot_git, format!("gitdir: {}\n", admin_dir.display())).unwrap();
+        fs::write(admin_dir.join("gitdir"), format!("{}\n", dot_git.display())).unwrap();
+
+        assert_eq!(check(&dot_git, &admin_dir), None);
+
+        let missing = root.path().join("old/.bare/worktrees/feature");
+        assert!(matches!(
+            check(&dot_git, &missing),
+            Some(Breakage::MissingAdminDir { .. })
+        ));
+
+        fs::write(admin_dir.join("gitdir"), "/elsewhere/feature/.git\n").unwrap();
+        assert_eq!(
+            check(&dot_git, &admin_dir),
+            Some(Breakage::StaleBackPointer {
+                admin_dir: admin_dir.clone(),
+                points_at: PathBuf::from("/elsewhere/feature/.git"),
+            })
+        );
+    }
+
+    #[test]
+    fn test_check_ignores_git_dirs_without_back_pointer() {
+        let root = TempDir::new().unwrap();
+        let modules = root.path().join(".git/modules/sub");
+        fs::create_dir_all(&modules).unwrap();
+
+        assert_eq!(check(&root.path().join("sub/.git"), &modules), None);
+    }
+}
@@ -0,0 +1,377 @@
+use crate::budget::{Budget, Segment};
+use crate::cache::{Signature, fnv1a};
+use crate::status::{self, Status};
+use crate::trace;
+use crate::upstream::Upstream;
+use crate::{Discovery, Env, Error, IoContext, discover, get_state_dir};
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::ErrorKind;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Stdio};
+use std::time::{Duration, Instant, SystemTime};
+
+// ============================================================================
+// Background Status Refresh
+// ============================================================================
+//
+// In async mode the prompt never waits for `git status`. It renders the last
+// snapshot from $XDG_STATE_HOME/git-worktree-prompt/status/ and, when that
+// snapshot is missing or stale, spawns a detached
+// `git-worktree-prompt refresh --path <work_dir>` child to recompute it.
+// A per-worktree lock file keeps many shells opening at once from starting
+// a refresh each. A refresh that fails (git refusing the repository, or a
+// huge one hitting REFRESH_TIMEOUT) leaves its lock behind, so prompts back
+// off for LOCK_STALE_AFTER instead of forking a doomed `git status` each.
+
+/// Environment variable overriding how long a snapshot stays fresh, in seconds
+pub(crate) const ENV_REFRESH_SECS: &str = "GIT_WORKTREE_PROMPT_REFRESH_SECS";
+
+/// Default snapshot lifetime before a background refresh is triggered
+const DEFAULT_REFRESH_SECS: u64 = 10;
+
+/// Longest a background `git status` may run before it is killed
+const REFRESH_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Locks older than this are assumed to belong to a crashed refresh
+const LOCK_STALE_AFTER: Duration = Duration::from_secs(120);
+
+/// Subdirectory of the state directory holding status snapshots
+const SNAPSHOT_DIR: &str = "status";
+
+/// Version tag written as the first line of every snapshot
+const SNAPSHOT_FORMAT_VERSION: &str = "git-worktree-prompt-status v2";
+
+/// Last computed status for a worktree and the metadata it was computed against
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Snapshot {
+    pub(crate) status: Status,
+    /// Seconds since the Unix epoch when `git status` started
+    pub(crate) taken_at: u64,
+    pub(crate) head: Signature,
+    pub(crate) index: Signature,
+    /// Upstream tracking ref, moved by fetch and push without touching HEAD or the index
+    pub(crate) upstream: Signature,
+}
+
+impl Snapshot {
+    /// Fresh while HEAD, the index and the tracking ref are untouched and the
+    /// snapshot is young enough
+    fn is_fresh(&self, discovery: &Discovery, max_age: Duration) -> bool {
+        let age = unix_now().saturating_sub(self.taken_at);
+        age < max_age.as_secs()
+            && Signature::of(&discovery.git_dir.join("HEAD")) == self.head
+            && Signature::of(&discovery.git_dir.join("index")) == self.index
+            && upstream_signature(discovery) == self.upstream
+    }
+
+    fn encode(&self) -> String {
+        let optional = |n: Option<u32>| n.map_or("-".to_string(), |n| n.to_string());
+        format!(
+            "{}\ntaken_at={}\nhead={}\nindex={}\nupstream={}\nstaged={}\nmodified={}\nuntracked={}\nahead={}\nbehind={}\n",
+            SNAPSHOT_FORMAT_VERSION,
+            self.taken_at,
+            self.head.encode(),
+            self.index.encode(),
+            self.upstream.encode(),
+            self.status.staged,
+            self.status.modified,
+            self.status.untracked,
+            optional(self.status.ahead),
+            optional(self.status.behind),
+        )
+    }
+
+    fn decode(content: &str) -> Option<Snapshot> {
+        let mut lines = content.lines();
+        if lines.next()? != SNAPSHOT_FORMAT_VERSION {
+            return None;
+        }
+        let fields: std::collections::HashMap<&str, &str> =
+            lines.filter_map(|line| line.split_once('=')).collect();
+        let optional = |key: &str| -> Option<Option<u32>> {
+            match *fields.get(key)? {
+                "-" => Some(None),
+                n => Some(Some(n.parse().ok()?)),
+            }
+        };
+
+        Some(Snapshot {
+            status: Status {
+                staged: fields.get("staged")?.parse().ok()?,
+                modified: fields.get("modified")?.parse().ok()?,
+                untracked: fields.get("untracked")?.parse().ok()?,
+                ahead: optional("ahead")?,
+                behind: optional("behind")?,
+            },
+            taken_at: fields.get("taken_at")?.parse().ok()?,
+            head: Signature::decode(fields.get("head")?)?,
+            index: Signature::decode(fields.get("index")?)?,
+            upstream: Signature::decode(fields.get("upstream")?)?,
+        })
+    }
+}
+
+/// Lock file held for the duration of a refresh; removed on drop
+struct RefreshLock(PathBuf);
+
+impl RefreshLock {
+    /// Takes the lock, or returns None when another live refresh holds it
+    fn acquire(path: &Path) -> Option<RefreshLock> {
+        for _ in 0..2 {
+            match OpenOptions::new().write(true).create_new(true).open(path) {
+                Ok(_) => return Some(RefreshLock(path.to_path_buf())),
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                    if is_locked(path) {
+                        return None;
+                    }
+                    // Abandoned by a crashed refresh - reclaim it
+                    let _ = fs::remove_file(path);
+                }
+                Err(_) => return None,
+            }
+        }
+        None
+    }
+
+    /// Leaves the lock in place, restarting its lifetime, so prompts wait
+    /// LOCK_STALE_AFTER before retrying a refresh that just failed
+    fn hold(self) {
+        let _ = OpenOptions::new()
+            .write(true)
+            .open(&self.0)
+            .and_then(|file| file.set_modified(SystemTime::now()));
+        std::mem::forget(self);
+    }
+}
+
+impl Drop for RefreshLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+/// Returns true when a lock file exists and is recent enough to be live
+fn is_locked(path: &Path) -> bool {
+    trace::metadata(path)
+        .and_then(|meta| meta.modified())
+        .is_ok_and(|modified| {
+            SystemTime::now()
+                .duration_since(modified)
+                .map_or(true, |age| age < LOCK_STALE_AFTER)
+        })
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Signature of the upstream's loose tracking ref, Missing without an upstream
+fn upstream_signature(discovery: &Discovery) -> Signature {
+    match &discovery.upstream {
+        Some(Upstream::Gone(tracking_ref) | Upstream::Tracking(tracking_ref)) => {
+            Signature::of(&discovery.common_dir.join(tracking_ref))
+        }
+        _ => Signature::Missing,
+    }
+}
+
+/// Snapshot lifetime from GIT_WORKTREE_PROMPT_REFRESH_SECS or the default
+pub(crate) fn max_age(env: &Env) -> Duration {
+    let secs = env
+        .get(ENV_REFRESH_SECS)
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(DEFAULT_REFRESH_SECS);
+    Duration::from_secs(secs)
+}
+
+/// Snapshot file for a worktree, keyed by its work directory
+pub(crate) fn snapshot_path(state_dir: &Path, work_dir: &Path) -> PathBuf {
+    state_dir.join(SNAPSHOT_DIR).join(format!(
+        "{:016x}",
+        fnv1a(work_dir.as_os_str().as_encoded_bytes())
+    ))
+}
+
+/// Lock file guarding the refresh of one worktree's snapshot
+pub(crate) fn lock_path(state_dir: &Path, work_dir: &Path) -> PathBuf {
+    snapshot_path(state_dir, work_dir).with_extension("lock")
+}
+
+/// Returns the last snapshot instantly, triggering a background refresh when stale
+pub(crate) fn collect(discovery: &Discovery, env: &Env, budget: &mut Budget) -> Segment<Status> {
+    let started = Instant::now();
+    let Some(state_dir) = get_state_dir(env) else {
+        budget.record("status", started, true);
+        return Segment::Skipped;
+    };
+
+    let snapshot = trace::read_to_string(&snapshot_path(&state_dir, &discovery.work_dir))
+        .ok()
+        .and_then(|content| Snapshot::decode(&content));
+
+    let segment = match snapshot {
+        Some(snapshot) if snapshot.is_fresh(discovery, max_age(env)) => {
+            Segment::Ready(snapshot.status)
+        }
+        stale => {
+            if !is_locked(&lock_path(&state_dir, &discovery.work_dir)) {
+                spawn_refresh(&discovery.work_dir, env);
+            }
+            match stale {
+                Some(snapshot) => Segment::Stale(snapshot.status),
+                None => Segment::Skipped,
+            }
+        }
+    };
+
+    budget.record("status", started, false);
+    segment
+}
+
+/// Starts a detached `refresh` child in its own process group, with `env` as
+/// its whole environment so it writes where this process reads
+fn spawn_refresh(work_dir: &Path, env: &Env) {
+    let Ok(exe) = env::current_exe() else {
+        return;
+    };
+    let _ = Command::new(exe)
+        .arg("refresh")
+        .arg("--path")
+        .arg(work_dir)
+        .env_clear()
+        .envs(env.iter())
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .process_group(0)
+        .spawn();
+}
+
+/// Recomputes and stores the status snapshot for the worktree containing `path`
+pub(crate) fn refresh(path: &Path, env: &Env) -> Result<(), Error> {
+    let discovery =
+        discover(path, env)?.ok_or_else(|| Error::command("refresh: not in a git repository"))?;
+    let state_dir =
+        get_state_dir(env).ok_or_else(|| Error::command("refresh: no state directory"))?;
+
+    let snapshot_path = snapshot_path(&state_dir, &discovery.work_dir);
+    if let Some(parent) = snapshot_path.parent() {
+        fs::create_dir_all(parent).with_path(parent)?;
+    }
+
+    let Some(lock) = RefreshLock::acquire(&lock_path(&state_dir, &discovery.work_dir)) else {
+        return Ok(()); // Another refresh is already running, or one failed recently
+    };
+    let result = write_snapshot(&discovery, &snapshot_path);
+    if result.is_err() {
+        lock.hold();
+    }
+    result
+}
+
+/// Runs `git status` and atomically replaces the snapshot with its result
+fn write_snapshot(discovery: &Discovery, snapshot_path: &Path) -> Result<(), Error> {
+    // Capture signatures before running status so concurrent changes leave the snapshot stale
+    let taken_at = unix_now();
+    let head = Signature::of(&discovery.git_dir.join("HEAD"));
+    let index = Signature::of(&discovery.git_dir.join("index"));
+    let upstream = upstream_signature(discovery);
+
+    let output = status::run_git_status(&discovery.work_dir, Instant::now() + REFRESH_TIMEOUT)
+        .ok_or_else(|| Error::command("refresh: git status failed or timed out"))?;
+
+    let snapshot = Snapshot {
+        status: status::parse_porcelain_v2(&output),
+        taken_at,
+        head,
+        index,
+        upstream,
+    };
+
+    let tmp = snapshot_path.with_extension(format!("{}.tmp", process::id()));
+    fs::write(&tmp, snapshot.encode()).with_path(&tmp)?;
+    fs::rename(&tmp, snapshot_path).with_path(snapshot_path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_snapshot_roundtrip() {
+        let snapshot = Snapshot {
+            status: Status {
+                staged: 1,
+                modified: 2,
+                untracked: 3,
+                ahead: Some(4),
+                behind: None,
+            },
+            taken_at: 1_700_000_000,
+            head: Signature::File {
+                ino: 1,
+                mtime_ns: 2,
+                len: 3,
+            },
+            index: Signature::Missing,
+            upstream: Signature::Missing,
+        };
+
+        assert_eq!(Snapshot::decode(&snapshot.encode()), Some(snapshot));
+        assert_eq!(Snapshot::decode("garbage"), None);
+    }
+
+    #[test]
+    fn test_refresh_lock_prevents_stampede() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("worktree.lock");
+
+        let first = RefreshLock::acquire(&path);
+        assert!(first.is_some(), "First refresh should take the lock");
+        assert!(
+            RefreshLock::acquire(&path).is_none(),
+            "Second refresh should back off while the lock is held"
+        );
+
+        drop(first);
+        assert!(!path.exists(), "Dropping the lock should remove the file");
+        assert!(RefreshLock::acquire(&path).is_some());
+    }
+
+    #[test]
+    fn test_held_lock_backs_off_until_stale() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("worktree.lock");
+
+        let lock = RefreshLock::acquire(&path).unwrap();
+        fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(SystemTime::now() - LOCK_STALE_AFTER * 2)
+            .unwrap();
+        lock.hold();
+        assert!(is_locked(&path), "Holding restarts the lock's lifetime");
+        assert!(RefreshLock::acquire(&path).is_none());
+    }
+
+    #[test]
+    fn test_refresh_lock_reclaims_abandoned_lock() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("worktree.lock");
+
+        let file = fs::File::create(&path).unwrap();
+        file.set_modified(SystemTime::now() - LOCK_STALE_AFTER * 2)
+            .unwrap();
+        drop(file);
+
+        assert!(!is_locked(&path));
+        assert!(RefreshLock::acquire(&path).is_some());
+    }
+}
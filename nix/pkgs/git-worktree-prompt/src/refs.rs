@@ -0,0 +1,162 @@
+use crate::discovery::is_object_id;
+use crate::trace;
+use std::fs;
+use std::path::Path;
+
+// ============================================================================
+// Ref Lookup
+// ============================================================================
+//
+// Refs live either as loose files under `<common_dir>/refs/` or as lines in
+// `<common_dir>/packed-refs`; a loose file wins over its packed entry. Loose
+// files may be symbolic (`ref: refs/remotes/origin/main`), which is how
+// `refs/remotes/origin/HEAD` names a remote's default branch.
+
+/// Symbolic refs followed before giving up, as git does
+const MAX_SYMREF_DEPTH: usize = 5;
+
+/// Object id `name` (e.g. `refs/heads/main`) points at, following symbolic refs
+pub(crate) fn resolve(common_dir: &Path, name: &str) -> Option<String> {
+    let mut name = name.to_string();
+    for _ in 0..MAX_SYMREF_DEPTH {
+        match read_loose(common_dir, &name) {
+            Some(content) => match content.strip_prefix("ref: ") {
+                Some(target) => name = target.to_string(),
+                None => return is_object_id(&content).then_some(content),
+            },
+            None => return read_packed(common_dir, &name),
+        }
+    }
+    None
+}
+
+/// Target of a symbolic ref such as `refs/remotes/origin/HEAD`
+pub(crate) fn symref_target(common_dir: &Path, name: &str) -> Option<String> {
+    read_loose(common_dir, name)?
+        .strip_prefix("ref: ")
+        .map(str::to_string)
+}
+
+/// True when `name` exists as a loose or packed ref
+pub(crate) fn exists(common_dir: &Path, name: &str) -> bool {
+    resolve(common_dir, name).is_some()
+}
+
+/// Every branch and the object id it points at, loose and packed, sorted by name
+pub(crate) fn branches(common_dir: &Path) -> Vec<(String, String)> {
+    let mut branches = Vec::new();
+    collect_loose(common_dir, "refs/heads", &mut branches);
+
+    // A loose ref shadows its packed entry, which may point elsewhere
+    let packed = trace::read_to_string(&common_dir.join("packed-refs")).unwrap_or_default();
+    for line in packed.lines() {
+        if let Some((id, name)) = line.split_once(' ')
+            && is_object_id(id)
+            && name.starts_with("refs/heads/")
+            && !branches.iter().any(|(loose_name, _)| loose_name == name)
+        {
+            branches.push((name.to_string(), id.to_string()));
+        }
+    }
+
+    let mut branches: Vec<(String, String)> = branches
+        .into_iter()
+        .map(|(name, id)| (name["refs/heads/".len()..].to_string(), id))
+        .collect();
+    branches.sort();
+    branches
+}
+
+/// Branches pointing at object `id`, loose and packed, sorted by name
+pub(crate) fn branches_at(common_dir: &Path, id: &str) -> Vec<String> {
+    branches(common_dir)
+        .into_iter()
+        .filter(|(_, target)| target == id)
+        .map(|(name, _)| name)
+        .collect()
+}
+
+/// Collects `(name, content)` of every loose ref below `dir`
+fn collect_loose(common_dir: &Path, dir: &str, out: &mut Vec<(String, String)>) {
+    let Ok(entries) = fs::read_dir(common_dir.join(dir)) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let name = format!("{}/{}", dir, entry.file_name().to_string_lossy());
+        if entry.file_type().is_ok_and(|t| t.is_dir()) {
+            collect_loose(common_dir, &name, out);
+        } else if let Some(content) = read_loose(common_dir, &name) {
+            out.push((name, content));
+        }
+    }
+}
+
+fn read_loose(common_dir: &Path, name: &str) -> Option<String> {
+    let content = trace::read_to_string(&common_dir.join(name)).ok()?;
+    Some(content.trim().to_string())
+}
+
+fn read_packed(common_dir: &Path, name: &str) -> Option<String> {
+    let content = trace::read_to_string(&common_dir.join("packed-refs")).ok()?;
+    content.lines().find_map(|line| {
+        // Skip the `# pack-refs with:` header and `^<peeled>` lines
+        let (id, packed_name) = line.split_once(' ')?;
+        (packed_name == name && is_object_id(id)).then(|| id.to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_resolve_loose_packed_and_symbolic() {
+        let dir = TempDir::new().unwrap();
+        let common_dir = dir.path();
+        let (a, b) = ("a".repeat(40), "b".repeat(40));
+        fs::create_dir_all(common_dir.join("refs/heads")).unwrap();
+        fs::create_dir_all(common_dir.join("refs/remotes/origin")).unwrap();
+        fs::write(common_dir.join("refs/heads/main"), format!("{}\n", a)).unwrap();
+        fs::write(
+            common_dir.join("refs/remotes/origin/HEAD"),
+            "ref: refs/remotes/origin/main\n",
+        )
+        .unwrap();
+        fs::write(
+            common_dir.join("packed-refs"),
+            format!(
+                "# pack-refs with: peeled fully-peeled sorted\n{b} refs/heads/main\n{b} refs/remotes/origin/main\n^{a}\n"
+            ),
+        )
+        .unwrap();
+
+        assert_eq!(resolve(common_dir, "refs/heads/main"), Some(a));
+        assert_eq!(resolve(common_dir, "refs/remotes/origin/HEAD"), Some(b));
+        assert_eq!(
+            symref_target(common_dir, "refs/remotes/origin/HEAD").as_deref(),
+            Some("refs/remotes/origin/main")
+        );
+        assert_eq!(symref_target(common_dir, "refs/heads/main"), None);
+        assert!(!exists(common_dir, "refs/heads/gone"));
+    }
+
+    #[test]
+    fn test_branches_at_prefers_loose_over_packed() {
+        let dir = TempDir::new().unwrap();
+        let common_dir = dir.path();
+        let (a, b) = ("a".repeat(40), "b".repeat(40));
+        fs::create_dir_all(common_dir.join("refs/heads/feature")).unwrap();
+        fs::write(common_dir.join("refs/heads/main"), format!("{}\n", a)).unwrap();
+        fs::write(common_dir.join("refs/heads/feature/x"), format!("{}\n", a)).unwrap();
+        fs::write(common_dir.join("refs/heads/moved"), format!("{}\n", b)).unwrap();
+        fs::write(
+            common_dir.join("packed-refs"),
+            format!("{a} refs/heads/moved\n{a} refs/heads/packed\n{a} refs/tags/v1\n"),
+        )
+        .unwrap();
+
+        assert_eq!(branches_at(common_dir, &a), ["feature/x", "main", "packed"]);
+        assert_eq!(branches_at(common_dir, &b), ["moved"]);
+    }
+}
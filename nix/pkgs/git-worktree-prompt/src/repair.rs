@@ -0,0 +1,156 @@
+use crate::pointers::{Breakage, check, read_back_pointer, same_file};
+use crate::theme::{Glyph, Icons};
+use crate::{Error, IoContext, find_bare_parent, find_common_dir, find_git_dir, parse_gitdir_file};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// ============================================================================
+// Broken Worktree Repair
+// ============================================================================
+//
+// When discovery finds a worktree pointer dangling (see pointers.rs), the
+// prompt shows a marker instead of disappearing, and
+// `git-worktree-prompt repair` rewrites both pointers the way
+// `git worktree repair` does.
+
+/// Output shown in place of the prompt when a worktree's pointers are broken
+pub(crate) fn broken_output(icons: &Icons) -> String {
+    format!("{} broken worktree", icons.get(Glyph::Broken))
+}
+
+/// Finds breakage in the worktree containing `start`, if any
+pub(crate) fn detect(start: &Path) -> Option<Breakage> {
+    let dot_git = find_git_dir(start).ok()??;
+    if !dot_git.is_file() {
+        return None;
+    }
+    let git_dir = parse_gitdir_file(&dot_git).ok()?;
+    check(&dot_git, &git_dir)
+}
+
+/// Rewrites broken worktree pointers around `path`, returning one line per change
+///
+/// Inside a linked worktree both of its pointers are fixed. From the main
+/// worktree or a `.bare` parent, every registered worktree that still exists
+/// gets its `.git` file pointed back at its admin directory.
+pub(crate) fn repair(path: &Path) -> Result<Vec<String>, Error> {
+    let start = fs::canonicalize(path).with_path(path)?;
+    let dot_git =
+        find_git_dir(&start)?.ok_or_else(|| Error::command("repair: not in a git repository"))?;
+    let work_dir = dot_git.parent().ok_or_else(|| Error::InvalidGitdirFile {
+        path: dot_git.clone(),
+    })?;
+
+    let git_dir = if dot_git.is_file() {
+        parse_gitdir_file(&dot_git)?
+    } else {
+        dot_git.clone()
+    };
+
+    let mut changes = Vec::new();
+    match check(&dot_git, &git_dir) {
+        Some(Breakage::MissingAdminDir { .. }) => {
+            let admin_dir = find_admin_dir(work_dir, &git_dir).ok_or_else(|| {
+                Error::Command(format!(
+                    "repair: cannot find the admin directory for {}; run `git worktree repair {}` from the main worktree",
+                    work_dir.display(),
+                    work_dir.display()
+                ))
+            })?;
+            changes.push(write_gitdir_file(&dot_git, &admin_dir)?);
+            if check(&dot_git, &admin_dir).is_some() {
+                changes.push(write_back_pointer(&admin_dir, &dot_git)?);
+            }
+        }
+        Some(Breakage::StaleBackPointer { admin_dir, .. }) => {
+            changes.push(write_back_pointer(&admin_dir, &dot_git)?);
+        }
+        None if !git_dir.join("gitdir").exists() => {
+            changes.extend(repair_registered(&find_common_dir(&git_dir))?);
+        }
+        None => {}
+    }
+
+    Ok(changes)
+}
+
+/// Locates the admin directory a moved worktree's `.git` file should name
+fn find_admin_dir(work_dir: &Path, old_admin_dir: &Path) -> Option<PathBuf> {
+    let common_dir = find_bare_parent(work_dir)?.join(".bare");
+    let worktrees = fs::canonicalize(common_dir.join("worktrees")).ok()?;
+    let dot_git = work_dir.join(".git");
+
+    // The admin directory usually keeps its name when the whole layout moves
+    if let Some(name) = old_admin_dir.file_name() {
+        let candidate = worktrees.join(name);
+        if candidate.is_dir() && admin_dir_is_free(&candidate, &dot_git) {
+            return Some(candidate);
+        }
+    }
+
+    // Otherwise match an orphaned admin directory by the worktree's directory name
+    let mut candidates: Vec<PathBuf> = fs::read_dir(&worktrees)
+        .ok()?
+        .flatten()
+        .map(|e| e.path())
+        .filter(|admin_dir| admin_dir_is_free(admin_dir, &dot_git))
+        .filter(|admin_dir| {
+            let points_at = read_back_pointer(admin_dir);
+            points_at.parent().and_then(Path::file_name) == work_dir.file_name()
+        })
+        .collect();
+    candidates.sort();
+    candidates.into_iter().next()
+}
+
+/// An admin directory can be claimed if it points at us or at nothing that exists
+fn admin_dir_is_free(admin_dir: &Path, dot_git: &Path) -> bool {
+    let points_at = read_back_pointer(admin_dir);
+    same_file(&points_at, dot_git) || !points_at.exists()
+}
+
+/// Points every existing registered worktree's `.git` file back at its admin directory
+fn repair_registered(common_dir: &Path) -> Result<Vec<String>, Error> {
+    let Ok(entries) = fs::read_dir(common_dir.join("worktrees")) else {
+        return Ok(Vec::new());
+    };
+
+    let mut admin_dirs: Vec<PathBuf> = entries.flatten().map(|e| e.path()).collect();
+    admin_dirs.sort();
+
+    let mut changes = Vec::new();
+    for admin_dir in admin_dirs {
+        let dot_git = read_back_pointer(&admin_dir);
+        if !dot_git.is_file() {
+            continue; // Moved or deleted; only the worktree itself can say where it went
+        }
+        let points_at = parse_gitdir_file(&dot_git).ok();
+        if points_at.is_some_and(|p| same_file(&p, &admin_dir)) {
+            continue;
+        }
+        let admin_dir = fs::canonicalize(&admin_dir).with_path(&admin_dir)?;
+        changes.push(write_gitdir_file(&dot_git, &admin_dir)?);
+    }
+    Ok(changes)
+}
+
+/// Writes a worktree's `.git` file in the format git uses
+fn write_gitdir_file(dot_git: &Path, admin_dir: &Path) -> Result<String, Error> {
+    fs::write(dot_git, format!("gitdir: {}\n", admin_dir.display())).with_path(dot_git)?;
+    Ok(format!(
+        "{}: now points at {}",
+        dot_git.display(),
+        admin_dir.display()
+    ))
+}
+
+/// Writes an admin directory's `gitdir` back-pointer in the format git uses
+fn write_back_pointer(admin_dir: &Path, dot_git: &Path) -> Result<String, Error> {
+    let gitdir = admin_dir.join("gitdir");
+    fs::write(&gitdir, format!("{}\n", dot_git.display())).with_path(&gitdir)?;
+    Ok(format!(
+        "{}: now points at {}",
+        gitdir.display(),
+        dot_git.display()
+    ))
+}
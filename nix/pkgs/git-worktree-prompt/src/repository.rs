@@ -0,0 +1,267 @@
+use crate::discovery::{
+    find_bare_parent, find_common_dir, find_git_dir, normalize, parse_gitdir_file, read_checkout,
+};
+use crate::{Error, ignore, pointers, trace};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// ============================================================================
+// Library API
+// ============================================================================
+//
+// The prompt's repository discovery as a typed model for other tools (tmux
+// helpers, editor integrations), without the settings, upstream, status and
+// formatting only the prompt needs:
+//
+//   Repository   shared git directory, `.bare` parent, and the worktree
+//                containing the start path
+//   Worktree     work directory, its own git directory and checkout
+//   Checkout     branch, other symbolic ref, or detached commit
+//
+// Nothing here reads the current directory or the process environment:
+// callers pass the start path and an `Env` snapshot, usually
+// `Env::from_process()`. The prompt binary builds its discovery on
+// `Repository::discover` and hands the same snapshot to its settings and
+// rendering, so tests build one by hand and need no `#[serial]`. Each step
+// is traced under the name the prompt's --debug output shows.
+
+/// Snapshot of the process environment
+///
+/// Discovery consults `GIT_WORKTREE_PROMPT_IGNORE`, whose patterns make paths
+/// count as outside any repository, and `HOME`, which expands their leading
+/// `~/`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Env {
+    vars: BTreeMap<String, String>,
+}
+
+impl Env {
+    /// Empty snapshot: nothing ignored, no home directory
+    pub fn new() -> Env {
+        Env::default()
+    }
+
+    /// Captures the running process's environment, leaving out variables
+    /// that are not valid UTF-8
+    pub fn from_process() -> Env {
+        let vars = env::vars_os()
+            .filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)))
+            .collect();
+        Env { vars }
+    }
+
+    /// Returns the snapshot with `name` set to `value`
+    pub fn with(mut self, name: &str, value: &str) -> Env {
+        self.vars.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.vars.get(name).map(String::as_str)
+    }
+
+    /// Every variable in the snapshot, sorted by name
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.vars
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+}
+
+/// What a worktree has checked out, as recorded in its HEAD
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Checkout {
+    /// A branch, by short name (`main` for `refs/heads/main`)
+    Branch(String),
+    /// A symbolic ref outside `refs/heads/`, by full name
+    Ref(String),
+    /// A commit, by the object id HEAD holds
+    Detached(String),
+}
+
+impl Checkout {
+    /// Branch name, None for other refs and detached HEADs
+    pub fn branch(&self) -> Option<&str> {
+        match self {
+            Checkout::Branch(name) => Some(name),
+            _ => None,
+        }
+    }
+}
+
+/// A work directory checked out from a repository
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct Worktree {
+    /// Directory containing the `.git` file or directory
+    pub path: PathBuf,
+    /// Per-worktree git directory holding HEAD and the index
+    pub git_dir: PathBuf,
+    /// Name of a linked worktree's admin directory, None for the main worktree
+    pub name: Option<String>,
+    pub checkout: Checkout,
+}
+
+/// A repository found by walking up from a start path
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct Repository {
+    /// Shared git directory holding refs, config and objects
+    pub common_dir: PathBuf,
+    /// Directory holding `.bare` in a bare-worktree layout
+    pub bare_parent: Option<PathBuf>,
+    /// Worktree containing the start path, None in a `.bare` parent outside any
+    pub worktree: Option<Worktree>,
+}
+
+impl Repository {
+    /// Finds the repository containing `start`, or None outside one
+    ///
+    /// Bare git directories and paths matching the ignore patterns in `env`
+    /// count as outside. A worktree whose pointers were left dangling by a
+    /// move is `Error::BrokenWorktree`.
+    pub fn discover(start: &Path, env: &Env) -> Result<Option<Repository>, Error> {
+        if ignore::matching_pattern(env, start).is_some() {
+            return Ok(None);
+        }
+        let Some(dot_git) = trace::step("find_git_dir", || find_git_dir(start))? else {
+            return Ok(None);
+        };
+        let path = dot_git
+            .parent()
+            .ok_or_else(|| Error::InvalidGitdirFile {
+                path: dot_git.clone(),
+            })?
+            .to_path_buf();
+
+        let git_dir = if trace::is_file(&dot_git) {
+            let git_dir = trace::step("parse_gitdir_file", || parse_gitdir_file(&dot_git))?;
+            // A moved worktree or .bare parent leaves one of the two pointers dangling
+            if let Some(breakage) =
+                trace::step("check_pointers", || pointers::check(&dot_git, &git_dir))
+            {
+                return Err(Error::BrokenWorktree(breakage));
+            }
+            git_dir
+        } else {
+            dot_git
+        };
+        if !trace::exists(&git_dir.join("HEAD")) {
+            return Ok(None);
+        }
+
+        let checkout = trace::step("read_git_head", || read_checkout(&git_dir))?;
+        let common_dir = normalize(&trace::step("find_common_dir", || {
+            find_common_dir(&git_dir)
+        }));
+        let bare_parent = trace::step("find_bare_parent", || find_bare_parent(&path));
+        let worktree = (bare_parent.as_ref() != Some(&path)).then(|| Worktree {
+            name: linked_name(&git_dir, &common_dir),
+            path,
+            git_dir: normalize(&git_dir),
+            checkout,
+        });
+        Ok(Some(Repository {
+            common_dir,
+            bare_parent,
+            worktree,
+        }))
+    }
+
+    /// Every worktree of the repository: the main one first, unless the
+    /// repository is bare, then linked ones sorted by name
+    ///
+    /// Worktrees whose HEAD cannot be read are left out, as are linked ones
+    /// whose directory is gone.
+    pub fn worktrees(&self) -> Vec<Worktree> {
+        let mut worktrees = Vec::new();
+        if self
+            .common_dir
+            .file_name()
+            .is_some_and(|name| name == ".git")
+            && let Some(path) = self.common_dir.parent()
+            && let Ok(checkout) = read_checkout(&self.common_dir)
+        {
+            worktrees.push(Worktree {
+                path: path.to_path_buf(),
+                git_dir: self.common_dir.clone(),
+                name: None,
+                checkout,
+            });
+        }
+
+        let Ok(entries) = fs::read_dir(self.common_dir.join("worktrees")) else {
+            return worktrees;
+        };
+        let mut admin_dirs: Vec<PathBuf> = entries.flatten().map(|e| e.path()).collect();
+        admin_dirs.sort();
+        for admin_dir in admin_dirs {
+            let Some(path) = pointers::read_back_pointer(&admin_dir)
+                .parent()
+                .map(Path::to_path_buf)
+            else {
+                continue;
+            };
+            if !path.is_dir() {
+                continue; // `git worktree prune` removes these
+            }
+            let Ok(checkout) = read_checkout(&admin_dir) else {
+                continue;
+            };
+            worktrees.push(Worktree {
+                name: linked_name(&admin_dir, &self.common_dir),
+                path,
+                git_dir: admin_dir,
+                checkout,
+            });
+        }
+        worktrees
+    }
+}
+
+/// Admin directory name when `git_dir` is `<common_dir>/worktrees/<name>`
+fn linked_name(git_dir: &Path, common_dir: &Path) -> Option<String> {
+    let git_dir = normalize(git_dir);
+    (git_dir.parent()? == common_dir.join("worktrees"))
+        .then(|| git_dir.file_name())
+        .flatten()
+        .map(|name| name.to_string_lossy().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_env_snapshot() {
+        let env = Env::new()
+            .with(ignore::ENV_IGNORE, "~/vendor")
+            .with("HOME", "/home/me");
+        assert_eq!(env.get("HOME"), Some("/home/me"));
+        assert_eq!(env.get("PATH"), None);
+        assert_eq!(
+            env.iter().collect::<Vec<_>>(),
+            [(ignore::ENV_IGNORE, "~/vendor"), ("HOME", "/home/me")]
+        );
+        assert_eq!(
+            ignore::matching_pattern(&env, Path::new("/home/me/vendor/lib")).as_deref(),
+            Some("~/vendor")
+        );
+        assert_eq!(
+            ignore::matching_pattern(&Env::new(), Path::new("/home/me/vendor/lib")),
+            None
+        );
+    }
+
+    #[test]
+    fn test_checkout_branch() {
+        assert_eq!(
+            Checkout::Branch("feat/x".to_string()).branch(),
+            Some("feat/x")
+        );
+        assert_eq!(Checkout::Ref("refs/tags/v1".to_string()).branch(), None);
+        assert_eq!(Checkout::Detached("a".repeat(40)).branch(), None);
+    }
+}
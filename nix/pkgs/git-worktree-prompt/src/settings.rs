@@ -0,0 +1,92 @@
+use crate::gitconfig::{Conditions, Config};
+use crate::status::StatusMode;
+use crate::{Env, Error, Head};
+use std::path::{Path, PathBuf};
+
+// ============================================================================
+// Per-Repository Settings
+// ============================================================================
+//
+// A `[worktree-prompt]` section in the repository's config overrides the
+// environment for that repository only:
+//
+//   [worktree-prompt]
+//       status = off
+//       branchIcon = "B "
+//
+// The section is read from the shared config and, when
+// extensions.worktreeConfig is on, from the worktree's config.worktree, so a
+// single worktree can differ from its siblings. Settings travel with the
+// discovery results, and the files they came from are cache validators.
+
+/// Config section holding the overrides
+const SECTION: &str = "worktree-prompt";
+
+/// `[worktree-prompt]` keys of one repository, lowercased, later entries winning
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct RepoSettings {
+    pub(crate) entries: Vec<(String, String)>,
+    /// Config files read, including included ones, so edits invalidate caches
+    pub(crate) sources: Vec<PathBuf>,
+}
+
+impl RepoSettings {
+    /// Collects the `[worktree-prompt]` overrides of a loaded config
+    pub(crate) fn from_config(config: &Config) -> RepoSettings {
+        RepoSettings {
+            entries: config
+                .section(SECTION)
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+            sources: config.sources().to_vec(),
+        }
+    }
+
+    /// Value of `worktree-prompt.<key>`, if the repository sets it
+    pub(crate) fn get(&self, key: &str) -> Option<&str> {
+        let key = key.to_lowercase();
+        self.entries
+            .iter()
+            .rev()
+            .find(|(k, _)| *k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Value of `worktree-prompt.<key>`, else of the environment variable `var`
+    pub(crate) fn get_or_env<'a>(&'a self, key: &str, env: &'a Env, var: &str) -> Option<&'a str> {
+        self.get(key).or_else(|| env.get(var))
+    }
+
+    /// Status mode override; unrecognized values leave the environment in charge
+    pub(crate) fn status_mode(&self) -> Option<StatusMode> {
+        self.get("status").and_then(StatusMode::parse)
+    }
+}
+
+/// Reads the repository's config, evaluating `includeIf` against its git dir
+/// and branch and expanding `~/` to the HOME in `env`
+pub(crate) fn load_config(
+    git_dir: &Path,
+    common_dir: &Path,
+    head: &Head,
+    env: &Env,
+) -> Result<Config, Error> {
+    let conditions = Conditions {
+        home: env.get("HOME").map(Path::new),
+        git_dir: Some(git_dir),
+        branch: head
+            .symref
+            .as_deref()
+            .and_then(|symref| symref.strip_prefix("refs/heads/")),
+    };
+
+    let mut config = Config::load_with(&common_dir.join("config"), conditions)?;
+    if config.get_bool("extensions.worktreeconfig") == Some(true) {
+        config.extend(Config::load_with(
+            &git_dir.join("config.worktree"),
+            conditions,
+        )?);
+    }
+    Ok(config)
+}
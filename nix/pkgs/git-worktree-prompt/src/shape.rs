@@ -0,0 +1,169 @@
+use crate::gitconfig::Config;
+use crate::trace;
+use std::path::Path;
+
+// ============================================================================
+// Clone Shape
+// ============================================================================
+//
+// Shallow clones (`--depth`), partial clones (`--filter=blob:none`) and sparse
+// checkouts look like any other repository, yet `git log`, `blame` or a plain
+// `ls` behave differently in them. Each leaves a trace discovery can read
+// without running git:
+//
+//   shallow   a `shallow` file in the common directory listing the cut-off
+//             commits
+//   partial   a remote with `promisor = true` or a `partialclonefilter`, or
+//             `extensions.partialClone` naming one
+//   sparse    `core.sparseCheckout` plus the worktree's `info/sparse-checkout`
+//             patterns; `core.sparseCheckoutCone` selects cone mode
+//
+// The shape is part of discovery, so it is cached and served by the daemon;
+// `fields` prints it as `shallow`, `partial`, `partial_filter` and `sparse`.
+
+/// Sparse checkout pattern style
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Sparse {
+    /// Whole directories, as written by `git sparse-checkout set --cone`
+    Cone,
+    /// Arbitrary gitignore-style patterns
+    Patterns,
+}
+
+impl Sparse {
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Sparse::Cone => "cone",
+            Sparse::Patterns => "patterns",
+        }
+    }
+
+    pub(crate) fn parse(s: &str) -> Option<Sparse> {
+        match s {
+            "cone" => Some(Sparse::Cone),
+            "patterns" => Some(Sparse::Patterns),
+            _ => None,
+        }
+    }
+}
+
+/// What of the repository's history and tree is actually present
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct Shape {
+    /// History is cut off at the commits listed in `shallow`
+    pub(crate) shallow: bool,
+    /// Objects are fetched on demand with this filter; empty when none is recorded
+    pub(crate) partial: Option<String>,
+    /// Only part of the tree is checked out
+    pub(crate) sparse: Option<Sparse>,
+}
+
+/// Reads the shape from the config and the shallow and sparse-checkout files
+pub(crate) fn read(config: &Config, git_dir: &Path, common_dir: &Path) -> Shape {
+    // Sparse patterns are per worktree; without them git checks out everything
+    let sparse = if config.get_bool("core.sparseCheckout") == Some(true)
+        && trace::exists(&git_dir.join("info/sparse-checkout"))
+    {
+        if config.get_bool("core.sparseCheckoutCone") == Some(true) {
+            Some(Sparse::Cone)
+        } else {
+            Some(Sparse::Patterns)
+        }
+    } else {
+        None
+    };
+
+    Shape {
+        shallow: trace::exists(&common_dir.join("shallow")),
+        partial: partial_filter(config),
+        sparse,
+    }
+}
+
+/// Filter of the first promisor remote, or None for a complete clone
+fn partial_filter(config: &Config) -> Option<String> {
+    let extension = config.get("extensions.partialClone");
+    config.subsections("remote").into_iter().find_map(|remote| {
+        let filter = config.get(&format!("remote.{}.partialCloneFilter", remote));
+        let promisor = config.get_bool(&format!("remote.{}.promisor", remote)) == Some(true)
+            || extension == Some(remote);
+        (promisor || filter.is_some()).then(|| filter.unwrap_or_default().to_string())
+    })
+}
+
+/// Directories a cone-mode sparse-checkout file includes recursively
+///
+/// Cone files list each included directory as `/dir/` and each of its parents
+/// as `/parent/` followed by `!/parent/*/`; only the former are active cones,
+/// matching `git sparse-checkout list`.
+pub(crate) fn cone_dirs(patterns: &str) -> Vec<String> {
+    let lines: Vec<&str> = patterns
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect();
+    lines
+        .iter()
+        .filter_map(|line| {
+            let dir = line.strip_prefix('/')?.strip_suffix('/')?;
+            let parent_only = lines.contains(&format!("!/{}/*/", dir).as_str());
+            (!dir.is_empty() && !parent_only).then(|| dir.to_string())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_read_detects_each_shape() {
+        let dir = TempDir::new().unwrap();
+        let git_dir = dir.path();
+        let plain = Config::parse(&git_dir.join("config"), "").unwrap();
+        assert_eq!(read(&plain, git_dir, git_dir), Shape::default());
+
+        fs::write(git_dir.join("shallow"), format!("{}\n", "a".repeat(40))).unwrap();
+        fs::create_dir_all(git_dir.join("info")).unwrap();
+        fs::write(git_dir.join("info/sparse-checkout"), "/*\n!/*/\n/src/\n").unwrap();
+        let config = Config::parse(
+            &git_dir.join("config"),
+            "[core]\n\tsparseCheckout = true\n\tsparseCheckoutCone = true\n\
+             [remote \"origin\"]\n\turl = /src/origin\n\
+             [remote \"mirror\"]\n\tpromisor = true\n\tpartialclonefilter = blob:none\n",
+        )
+        .unwrap();
+        assert_eq!(
+            read(&config, git_dir, git_dir),
+            Shape {
+                shallow: true,
+                partial: Some("blob:none".to_string()),
+                sparse: Some(Sparse::Cone),
+            }
+        );
+    }
+
+    #[test]
+    fn test_partial_filter() {
+        let parse = |text: &str| Config::parse(Path::new("config"), text).unwrap();
+        assert_eq!(
+            partial_filter(&parse(
+                "[extensions]\n\tpartialClone = origin\n[remote \"origin\"]\n\turl = x\n"
+            )),
+            Some(String::new())
+        );
+        assert_eq!(
+            partial_filter(&parse("[remote \"origin\"]\n\tpromisor = false\n")),
+            None
+        );
+    }
+
+    #[test]
+    fn test_cone_dirs() {
+        let patterns = "/*\n!/*/\n/docs/\n/src/\n!/src/*/\n/src/app/\n/src/lib/\n";
+        assert_eq!(cone_dirs(patterns), ["docs", "src/app", "src/lib"]);
+        assert!(cone_dirs("/*\n!/*/\n").is_empty(), "Root files only");
+    }
+}
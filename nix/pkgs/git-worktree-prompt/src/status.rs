@@ -0,0 +1,290 @@
+use crate::Env;
+use crate::budget::{Budget, Segment};
+use crate::theme::{Glyph, Icons};
+use std::io::Read;
+use std::path::Path;
+use std::process::{Command, ExitStatus, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// ============================================================================
+// Working Tree Status Segment
+// ============================================================================
+//
+// Status and ahead/behind counts come from a single
+// `git status --porcelain=v2 --branch` call. This is the one phase whose cost
+// grows with repository size, so it runs under the invocation's time budget
+// and the child is killed when the deadline passes.
+
+/// Environment variable enabling the status segment: "1" inline, "async" in the background
+pub(crate) const ENV_STATUS: &str = "GIT_WORKTREE_PROMPT_STATUS";
+
+/// How often the child process is polled while waiting for the deadline
+const POLL_INTERVAL: Duration = Duration::from_micros(250);
+
+/// Counts parsed from porcelain v2 status output
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct Status {
+    pub(crate) staged: u32,
+    pub(crate) modified: u32,
+    pub(crate) untracked: u32,
+    /// Commits ahead of/behind upstream, None without an upstream
+    pub(crate) ahead: Option<u32>,
+    pub(crate) behind: Option<u32>,
+}
+
+/// How the status segment is computed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum StatusMode {
+    Off,
+    /// Run `git status` inline under the time budget
+    Inline,
+    /// Show the last snapshot and refresh it in a detached child
+    Async,
+}
+
+impl StatusMode {
+    /// Parses a mode as written in the environment or `worktree-prompt.status`
+    pub(crate) fn parse(value: &str) -> Option<StatusMode> {
+        match value.to_lowercase().as_str() {
+            "1" | "true" | "on" | "inline" => Some(StatusMode::Inline),
+            "async" => Some(StatusMode::Async),
+            "0" | "false" | "off" | "" => Some(StatusMode::Off),
+            _ => None,
+        }
+    }
+}
+
+/// Reads the status mode from GIT_WORKTREE_PROMPT_STATUS
+pub(crate) fn mode(env: &Env) -> StatusMode {
+    env.get(ENV_STATUS)
+        .and_then(StatusMode::parse)
+        .unwrap_or(StatusMode::Off)
+}
+
+/// Runs `git status` in `work_dir`, skipping it if the budget runs out first
+pub(crate) fn collect(work_dir: &Path, budget: &mut Budget) -> Segment<Status> {
+    let started = Instant::now();
+    if budget.exhausted() {
+        budget.record("status", started, true);
+        return Segment::Skipped;
+    }
+
+    match run_git_status(work_dir, budget.deadline()) {
+        Some(output) => {
+            budget.record("status", started, false);
+            Segment::Ready(parse_porcelain_v2(&output))
+        }
+        None => {
+            budget.record("status", started, true);
+            Segment::Skipped
+        }
+    }
+}
+
+/// Spawns `git status` and waits until `deadline`, returning None on timeout or failure
+pub(crate) fn run_git_status(work_dir: &Path, deadline: Instant) -> Option<String> {
+    let args = [
+        "status",
+        "--porcelain=v2",
+        "--branch",
+        "--untracked-files=normal",
+    ];
+    let (exit, output) = run_git(work_dir, &args, deadline)?;
+    exit.success().then_some(output)
+}
+
+/// Spawns `git <args>` and waits until `deadline`, returning its exit status
+/// and stdout, or None when it timed out or could not start
+pub(crate) fn run_git(
+    work_dir: &Path,
+    args: &[&str],
+    deadline: Instant,
+) -> Option<(ExitStatus, String)> {
+    let mut child = Command::new("git")
+        .arg("--no-optional-locks")
+        .args(args)
+        .current_dir(work_dir)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .ok()?;
+
+    // Drain stdout on a thread so a large status can't fill the pipe and stall the child
+    let mut stdout = child.stdout.take()?;
+    let reader = thread::spawn(move || {
+        let mut buf = String::new();
+        stdout.read_to_string(&mut buf).map(|_| buf)
+    });
+
+    loop {
+        match child.try_wait() {
+            Ok(Some(exit)) => {
+                let output = reader.join().ok()?.ok()?;
+                return Some((exit, output));
+            }
+            Ok(None) if Instant::now() < deadline => thread::sleep(POLL_INTERVAL),
+            _ => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return None;
+            }
+        }
+    }
+}
+
+/// Parses `git status --porcelain=v2 --branch` output
+pub(crate) fn parse_porcelain_v2(output: &str) -> Status {
+    let mut status = Status::default();
+
+    for line in output.lines() {
+        if let Some(ab) = line.strip_prefix("# branch.ab ") {
+            let mut counts = ab.split(' ');
+            status.ahead = counts
+                .next()
+                .and_then(|a| a.trim_start_matches('+').parse().ok());
+            status.behind = counts
+                .next()
+                .and_then(|b| b.trim_start_matches('-').parse().ok());
+            continue;
+        }
+
+        match line.as_bytes().first() {
+            // Ordinary and renamed/copied entries carry an XY field
+            Some(b'1') | Some(b'2') => {
+                let xy = line.as_bytes().get(2..4).unwrap_or(b"..");
+                if xy[0] != b'.' {
+                    status.staged += 1;
+                }
+                if xy[1] != b'.' {
+                    status.modified += 1;
+                }
+            }
+            // Unmerged entries count as modified until resolved
+            Some(b'u') => status.modified += 1,
+            Some(b'?') => status.untracked += 1,
+            _ => {}
+        }
+    }
+
+    status
+}
+
+/// Renders the status segment; empty for a clean tree in sync with upstream
+pub(crate) fn format_segment(segment: &Segment<Status>, icons: &Icons) -> String {
+    match segment {
+        Segment::Ready(status) => format_status(status, icons),
+        Segment::Stale(status) => format!(
+            "{}{}",
+            icons.get(Glyph::Stale),
+            format_status(status, icons)
+        ),
+        Segment::Skipped => icons.get(Glyph::Skipped).to_string(),
+    }
+}
+
+/// Renders status counts, omitting zero values
+fn format_status(status: &Status, icons: &Icons) -> String {
+    let mut parts = Vec::new();
+    if let Some(ahead) = status.ahead.filter(|n| *n > 0) {
+        parts.push(format!("{}{}", icons.get(Glyph::Ahead), ahead));
+    }
+    if let Some(behind) = status.behind.filter(|n| *n > 0) {
+        parts.push(format!("{}{}", icons.get(Glyph::Behind), behind));
+    }
+    if status.staged > 0 {
+        parts.push(format!("{}{}", icons.get(Glyph::Staged), status.staged));
+    }
+    if status.modified > 0 {
+        parts.push(format!("{}{}", icons.get(Glyph::Modified), status.modified));
+    }
+    if status.untracked > 0 {
+        parts.push(format!(
+            "{}{}",
+            icons.get(Glyph::Untracked),
+            status.untracked
+        ));
+    }
+    parts.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::RepoSettings;
+
+    #[test]
+    fn test_parse_porcelain_v2() {
+        let output = "\
+# branch.oid 1234567890abcdef1234567890abcdef12345678
+# branch.head main
+# branch.upstream origin/main
+# branch.ab +2 -1
+1 M. N... 100644 100644 100644 abc abc staged.txt
+1 .M N... 100644 100644 100644 abc abc modified.txt
+1 MM N... 100644 100644 100644 abc abc both.txt
+2 R. N... 100644 100644 100644 abc abc R100 new.txt\told.txt
+u UU N... 100644 100644 100644 100644 abc abc abc conflict.txt
+? untracked.txt
+? other.txt
+";
+        assert_eq!(
+            parse_porcelain_v2(output),
+            Status {
+                staged: 3,
+                modified: 3,
+                untracked: 2,
+                ahead: Some(2),
+                behind: Some(1),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_porcelain_v2_without_upstream() {
+        let output = "# branch.oid (initial)\n# branch.head main\n";
+        assert_eq!(parse_porcelain_v2(output), Status::default());
+    }
+
+    #[test]
+    fn test_format_segment() {
+        let icons = Icons::resolve(&RepoSettings::default(), &Env::new());
+        let format = |segment: &Segment<Status>| format_segment(segment, &icons);
+        assert_eq!(format(&Segment::Skipped), "?");
+        assert_eq!(format(&Segment::Ready(Status::default())), "");
+        assert_eq!(format(&Segment::Stale(Status::default())), "~");
+        assert_eq!(
+            format(&Segment::Stale(Status {
+                modified: 2,
+                ..Status::default()
+            })),
+            "~!2"
+        );
+        assert_eq!(
+            format(&Segment::Ready(Status {
+                staged: 1,
+                modified: 2,
+                untracked: 3,
+                ahead: Some(4),
+                behind: Some(0),
+            })),
+            "⇡4 +1 !2 %3"
+        );
+    }
+
+    #[test]
+    fn test_format_segment_follows_theme() {
+        let settings = RepoSettings {
+            entries: vec![("theme".to_string(), "ascii".to_string())],
+            sources: Vec::new(),
+        };
+        let icons = Icons::resolve(&settings, &Env::new());
+        let status = Status {
+            ahead: Some(1),
+            behind: Some(2),
+            ..Status::default()
+        };
+        assert_eq!(format_segment(&Segment::Ready(status), &icons), "^1 v2");
+    }
+}
@@ -0,0 +1,235 @@
+use crate::Env;
+use crate::settings::RepoSettings;
+use crate::wildmatch::wildmatch;
+
+// ============================================================================
+// Branch Styles and Shell Escaping
+// ============================================================================
+//
+// Branch names can be colored by pattern, first match wins:
+//
+//   GIT_WORKTREE_PROMPT_BRANCH_STYLES='main=bold,red master=red release/*=red **=green'
+//
+// Patterns use the same globs as `includeIf "onbranch:"`: `*` stops at `/`,
+// so `**` matches every branch. A worktree whose directory is not named after
+// its branch gets a warning glyph when that branch matches one of
+// GIT_WORKTREE_PROMPT_PROTECTED, e.g. `main` checked out in `feature-x/`.
+//
+// Escape sequences must not count towards the prompt width, which each shell
+// marks differently; GIT_WORKTREE_PROMPT_SHELL selects how. In zsh mode `%`
+// in plain text is doubled so prompt expansion leaves it alone.
+
+/// Environment variable holding whitespace-separated `pattern=style` rules
+pub(crate) const ENV_BRANCH_STYLES: &str = "GIT_WORKTREE_PROMPT_BRANCH_STYLES";
+
+/// Environment variable holding whitespace-separated protected branch patterns
+pub(crate) const ENV_PROTECTED: &str = "GIT_WORKTREE_PROMPT_PROTECTED";
+
+/// Environment variable selecting the escaping mode: ansi, bash, zsh or none
+pub(crate) const ENV_SHELL: &str = "GIT_WORKTREE_PROMPT_SHELL";
+
+/// How escape sequences are written for the consuming shell
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Shell {
+    /// Raw ANSI sequences (fish, tmux, plain terminals)
+    Ansi,
+    /// Sequences wrapped in \001/\002, which readline treats as zero-width
+    Bash,
+    /// Sequences wrapped in `%{ %}`, with `%` escaped
+    Zsh,
+    /// No escape sequences at all
+    None,
+}
+
+impl Shell {
+    pub(crate) fn parse(name: &str) -> Option<Shell> {
+        match name.to_lowercase().as_str() {
+            "ansi" | "fish" => Some(Shell::Ansi),
+            "bash" => Some(Shell::Bash),
+            "zsh" => Some(Shell::Zsh),
+            "none" | "plain" => Some(Shell::None),
+            _ => None,
+        }
+    }
+}
+
+/// SGR parameters of one style, e.g. `1;31` for `bold,red`
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Style(String);
+
+impl Style {
+    /// Parses comma-separated attribute and color names, or 256-color numbers
+    pub(crate) fn parse(spec: &str) -> Option<Style> {
+        let codes: Option<Vec<String>> = spec.split(',').map(sgr).collect();
+        let codes = codes?;
+        (!codes.is_empty()).then(|| Style(codes.join(";")))
+    }
+}
+
+fn sgr(name: &str) -> Option<String> {
+    const COLORS: [&str; 8] = [
+        "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+    ];
+    let name = name.trim().to_lowercase();
+    let code = match name.as_str() {
+        "bold" => 1,
+        "dim" => 2,
+        "italic" => 3,
+        "underline" => 4,
+        _ => {
+            if let Some(index) = COLORS.iter().position(|c| *c == name) {
+                30 + index
+            } else if let Some(index) = name
+                .strip_prefix("bright-")
+                .and_then(|color| COLORS.iter().position(|c| *c == color))
+            {
+                90 + index
+            } else {
+                let number: u8 = name.parse().ok()?;
+                return Some(format!("38;5;{}", number));
+            }
+        }
+    };
+    Some(code.to_string())
+}
+
+/// Branch rules, protected patterns and escaping mode for one prompt
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Styles {
+    rules: Vec<(String, Style)>,
+    protected: Vec<String>,
+    shell: Shell,
+}
+
+impl Styles {
+    /// Reads rules and protected patterns from the repository, then the
+    /// environment; the shell mode only comes from the environment
+    pub(crate) fn resolve(settings: &RepoSettings, env: &Env) -> Styles {
+        let setting = |key: &str, var: &str| settings.get_or_env(key, env, var).unwrap_or_default();
+        let shell = env
+            .get(ENV_SHELL)
+            .and_then(Shell::parse)
+            .unwrap_or(Shell::Ansi);
+        Styles::new(
+            setting("branchStyles", ENV_BRANCH_STYLES),
+            setting("protected", ENV_PROTECTED),
+            shell,
+        )
+    }
+
+    /// Builds styles from rule and pattern lists; malformed rules are skipped
+    pub(crate) fn new(rules: &str, protected: &str, shell: Shell) -> Styles {
+        Styles {
+            rules: rules
+                .split_whitespace()
+                .filter_map(|rule| {
+                    let (pattern, style) = rule.rsplit_once('=')?;
+                    Some((pattern.to_string(), Style::parse(style)?))
+                })
+                .collect(),
+            protected: protected.split_whitespace().map(str::to_string).collect(),
+            shell,
+        }
+    }
+
+    /// True when `branch` matches one of the protected patterns
+    pub(crate) fn is_protected(&self, branch: &str) -> bool {
+        self.protected
+            .iter()
+            .any(|pattern| wildmatch(pattern.as_bytes(), branch.as_bytes()))
+    }
+
+    /// Escapes `text` and colors it with the first rule matching `branch`
+    pub(crate) fn branch(&self, branch: &str, text: &str) -> String {
+        let style = self
+            .rules
+            .iter()
+            .find(|(pattern, _)| wildmatch(pattern.as_bytes(), branch.as_bytes()))
+            .map(|(_, style)| style);
+        match style {
+            Some(style) => self.paint(style, text),
+            None => self.escape(text),
+        }
+    }
+
+    /// Wraps escaped `text` in the style's sequences, marked as zero-width
+    pub(crate) fn paint(&self, style: &Style, text: &str) -> String {
+        let (open, close) = match self.shell {
+            Shell::Ansi => ("", ""),
+            Shell::Bash => ("\u{1}", "\u{2}"),
+            Shell::Zsh => ("%{", "%}"),
+            Shell::None => return self.escape(text),
+        };
+        format!(
+            "{open}\u{1b}[{}m{close}{}{open}\u{1b}[0m{close}",
+            style.0,
+            self.escape(text)
+        )
+    }
+
+    /// Escapes plain text for the shell mode
+    pub(crate) fn escape(&self, text: &str) -> String {
+        match self.shell {
+            Shell::Zsh => text.replace('%', "%%"),
+            _ => text.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_style_parse() {
+        for (spec, expected) in [
+            ("red", Some("31")),
+            ("bold,red", Some("1;31")),
+            ("bright-green", Some("92")),
+            ("208", Some("38;5;208")),
+            ("Underline , Blue", Some("4;34")),
+            ("pink", None),
+            ("red,", None),
+            ("300", None),
+        ] {
+            assert_eq!(
+                Style::parse(spec).map(|s| s.0).as_deref(),
+                expected,
+                "{}",
+                spec
+            );
+        }
+    }
+
+    #[test]
+    fn test_first_matching_rule_wins() {
+        let styles = Styles::new("main=red release/*=bold **=green bad=pink", "", Shell::Ansi);
+        assert_eq!(styles.branch("main", "x"), "\u{1b}[31mx\u{1b}[0m");
+        assert_eq!(styles.branch("release/1.0", "x"), "\u{1b}[1mx\u{1b}[0m");
+        assert_eq!(styles.branch("feature/a", "x"), "\u{1b}[32mx\u{1b}[0m");
+        assert_eq!(Styles::new("", "", Shell::Ansi).branch("main", "x"), "x");
+    }
+
+    #[test]
+    fn test_shell_escaping() {
+        let red = Style::parse("red").unwrap();
+        for (shell, expected) in [
+            (Shell::Ansi, "\u{1b}[31m100%\u{1b}[0m"),
+            (Shell::Bash, "\u{1}\u{1b}[31m\u{2}100%\u{1}\u{1b}[0m\u{2}"),
+            (Shell::Zsh, "%{\u{1b}[31m%}100%%%{\u{1b}[0m%}"),
+            (Shell::None, "100%"),
+        ] {
+            let styles = Styles::new("", "", shell);
+            assert_eq!(styles.paint(&red, "100%"), expected, "{:?}", shell);
+        }
+    }
+
+    #[test]
+    fn test_protected_patterns() {
+        let styles = Styles::new("", "main master release/*", Shell::Ansi);
+        assert!(styles.is_protected("main"));
+        assert!(styles.is_protected("release/2.0"));
+        assert!(!styles.is_protected("maintenance"));
+        assert!(!Styles::new("", "", Shell::Ansi).is_protected("main"));
+    }
+}
@@ -0,0 +1,140 @@
+use crate::index;
+use crate::refs;
+use crate::trace;
+use crate::{find_git_dir, parse_gitdir_file};
+use std::path::{Path, PathBuf};
+
+// ============================================================================
+// Submodule Context
+// ============================================================================
+//
+// A submodule's `.git` file points into its superproject's git directory,
+// e.g. `../.git/modules/libs/foo`. When the resolved git dir sits below a
+// `modules/` directory of another git dir, the prompt shows
+// `parent › libs/foo@ref` instead of the bare branch, and flags a submodule
+// whose HEAD no longer matches the commit the superproject's index records
+// for it (the gitlink), i.e. one that `git status` in the parent reports as
+// having new commits.
+
+/// Where a submodule sits in its superproject
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Submodule {
+    /// Work directory of the superproject
+    pub(crate) superproject: PathBuf,
+    /// Git directory holding the superproject's index
+    pub(crate) super_git_dir: PathBuf,
+    /// Path of the submodule relative to the superproject, as in its index
+    pub(crate) path: String,
+    /// Full object id of the submodule's HEAD
+    pub(crate) head: Option<String>,
+    /// Commit the superproject's index records for the submodule
+    pub(crate) recorded: Option<String>,
+}
+
+impl Submodule {
+    /// Name shown for the superproject: its directory name
+    pub(crate) fn parent_name(&self) -> String {
+        self.superproject
+            .file_name()
+            .map_or_else(String::new, |name| name.to_string_lossy().to_string())
+    }
+
+    /// True when HEAD moved away from the commit the superproject records
+    pub(crate) fn out_of_sync(&self) -> bool {
+        matches!((&self.head, &self.recorded), (Some(head), Some(recorded)) if head != recorded)
+    }
+}
+
+/// Submodule context for a work directory whose `.git` file resolved to `git_dir`
+pub(crate) fn detect(work_dir: &Path, git_dir: &Path, common_dir: &Path) -> Option<Submodule> {
+    if !is_module_dir(git_dir) {
+        return None;
+    }
+
+    let super_dot_git = find_git_dir(work_dir.parent()?).ok()??;
+    let superproject = super_dot_git.parent()?.to_path_buf();
+    let super_git_dir = if trace::is_file(&super_dot_git) {
+        parse_gitdir_file(&super_dot_git).ok()?
+    } else {
+        super_dot_git
+    };
+    let path = work_dir
+        .strip_prefix(&superproject)
+        .ok()?
+        .to_string_lossy()
+        .to_string();
+
+    let head = head_id(git_dir, common_dir);
+    let recorded = head.as_ref().and_then(|head| {
+        let index = trace::read(&super_git_dir.join("index")).ok()?;
+        gitlink(&index, &path, head.len() / 2)
+    });
+
+    Some(Submodule {
+        superproject,
+        super_git_dir,
+        path,
+        head,
+        recorded,
+    })
+}
+
+/// True for git dirs below a `modules/` directory of another git dir
+fn is_module_dir(git_dir: &Path) -> bool {
+    git_dir.ancestors().skip(1).any(|dir| {
+        dir.file_name().is_some_and(|name| name == "modules")
+            && dir
+                .parent()
+                .is_some_and(|owner| trace::exists(&owner.join("HEAD")))
+    })
+}
+
+/// Full object id HEAD points at, following a symbolic HEAD through refs
+fn head_id(git_dir: &Path, common_dir: &Path) -> Option<String> {
+    let content = trace::read_to_string(&git_dir.join("HEAD")).ok()?;
+    let content = content.trim();
+    match content.strip_prefix("ref: ") {
+        Some(symref) => refs::resolve(common_dir, symref),
+        None => Some(content.to_string()),
+    }
+}
+
+/// Object id the index records for `path` if it is a merged gitlink entry
+///
+/// `hash_len` is 20 for SHA-1 repositories and 32 for SHA-256 ones.
+pub(crate) fn gitlink(data: &[u8], path: &str, hash_len: usize) -> Option<String> {
+    index::entries(data, hash_len)?
+        .find(|entry| entry.stage == 0 && entry.path == path.as_bytes())
+        .filter(|entry| entry.is_gitlink())
+        .map(|entry| entry.hex_id())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gitlink_in_each_index_version() {
+        let entries = [
+            (0o100644, 0x11, 0, "README.md"),
+            (0o160000, 0xab, 0, "libs/foo"),
+            (0o100644, 0x22, 0, "libs/foo-notes.txt"),
+            (0o160000, 0xcd, 0, "vendor/bar"),
+            (0o160000, 0xee, 2, "vendor/conflicted"),
+        ];
+        for version in [2, 3, 4] {
+            let data = index::build(version, &entries);
+            assert_eq!(
+                gitlink(&data, "libs/foo", 20),
+                Some("ab".repeat(20)),
+                "v{}",
+                version
+            );
+            assert_eq!(gitlink(&data, "vendor/bar", 20), Some("cd".repeat(20)));
+            assert_eq!(gitlink(&data, "README.md", 20), None, "not a gitlink");
+            assert_eq!(gitlink(&data, "libs", 20), None);
+            assert_eq!(gitlink(&data, "vendor/conflicted", 20), None);
+        }
+        assert_eq!(gitlink(b"garbage", "x", 20), None);
+    }
+}
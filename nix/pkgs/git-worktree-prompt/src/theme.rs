@@ -0,0 +1,387 @@
+use crate::settings::RepoSettings;
+use crate::{ENV_BRANCH_ICON, ENV_WORKTREE_ICON, Env};
+
+// ============================================================================
+// Icon Themes
+// ============================================================================
+//
+// Every glyph the prompt emits comes from the GLYPHS table, in one of three
+// themes selected with GIT_WORKTREE_PROMPT_THEME or `worktree-prompt.theme`:
+//
+//   unicode (default)  🌳 feature → ⎇ main ⇡1 +2
+//   ascii              wt feature -> @ main ^1 +2
+//   nerd               (Nerd Font glyphs)
+//
+// Each glyph can still be set on its own, through the repository's
+// `[worktree-prompt]` section first and then its environment variable; both
+// win over the theme. A new marker needs a Glyph variant and a GLYPHS row.
+
+/// Environment variable selecting the theme
+pub(crate) const ENV_THEME: &str = "GIT_WORKTREE_PROMPT_THEME";
+
+/// A named set of glyphs
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Theme {
+    Unicode,
+    Ascii,
+    Nerd,
+}
+
+impl Theme {
+    pub(crate) fn parse(name: &str) -> Option<Theme> {
+        match name.to_lowercase().as_str() {
+            "unicode" => Some(Theme::Unicode),
+            "ascii" => Some(Theme::Ascii),
+            "nerd" | "nerd-font" => Some(Theme::Nerd),
+            _ => None,
+        }
+    }
+}
+
+/// Everything the prompt draws besides names and counts
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Glyph {
+    Branch,
+    Worktree,
+    /// Between a worktree and the branch it has checked out, when they differ
+    Arrow,
+    /// Shown in the `.bare` parent outside any worktree
+    Bare,
+    /// Before "broken worktree" when worktree pointers dangle
+    Broken,
+    /// After a protected branch checked out in another branch's worktree
+    Protected,
+    /// After a worktree whose branch is merged or whose upstream is gone
+    Merged,
+    /// After a branch with no upstream configured
+    NoUpstream,
+    /// After a branch whose configured upstream ref no longer exists
+    UpstreamGone,
+    /// After a branch tracking an existing upstream; empty in every theme
+    Tracking,
+    /// After the prompt when the last fetch is older than the threshold
+    StaleFetch,
+    /// Fallback `{remote_icon}` when no host icon matches; empty outside nerd
+    Remote,
+    /// Between a superproject and the submodule inside it
+    Submodule,
+    /// After a submodule whose HEAD differs from the commit its parent records
+    SubmoduleMoved,
+    /// After the prompt in a shallow clone
+    Shallow,
+    /// After the prompt in a partial (filtered) clone
+    Partial,
+    /// After the prompt in a sparse checkout
+    Sparse,
+    /// Before the number of conflicted paths during a merge or rebase
+    Conflicts,
+    /// Before the working-copy change ID in a jj colocated repository
+    Jj,
+    Ahead,
+    Behind,
+    Staged,
+    Modified,
+    Untracked,
+    /// Before a status segment showing last-known values
+    Stale,
+    /// In place of a segment skipped for lack of time
+    Skipped,
+}
+
+/// One row per glyph
+struct Row {
+    glyph: Glyph,
+    /// `[worktree-prompt]` key overriding the theme
+    key: &'static str,
+    /// Environment variable overriding the theme
+    env_var: &'static str,
+    unicode: &'static str,
+    ascii: &'static str,
+    nerd: &'static str,
+}
+
+const GLYPHS: &[Row] = &[
+    Row {
+        glyph: Glyph::Branch,
+        key: "branchIcon",
+        env_var: ENV_BRANCH_ICON,
+        unicode: "⎇",
+        ascii: "@",
+        nerd: "\u{e0a0}",
+    },
+    Row {
+        glyph: Glyph::Worktree,
+        key: "worktreeIcon",
+        env_var: ENV_WORKTREE_ICON,
+        unicode: "🌳",
+        ascii: "wt",
+        nerd: "\u{f1bb}",
+    },
+    Row {
+        glyph: Glyph::Arrow,
+        key: "arrowIcon",
+        env_var: "GIT_WORKTREE_PROMPT_ARROW_ICON",
+        unicode: "→",
+        ascii: "->",
+        nerd: "\u{f061}",
+    },
+    Row {
+        glyph: Glyph::Bare,
+        key: "bareIcon",
+        env_var: "GIT_WORKTREE_PROMPT_BARE_ICON",
+        unicode: "[bare]",
+        ascii: "[bare]",
+        nerd: "\u{f1c0}",
+    },
+    Row {
+        glyph: Glyph::Broken,
+        key: "brokenIcon",
+        env_var: "GIT_WORKTREE_PROMPT_BROKEN_ICON",
+        unicode: "⚠",
+        ascii: "!!",
+        nerd: "\u{f071}",
+    },
+    Row {
+        glyph: Glyph::Protected,
+        key: "protectedIcon",
+        env_var: "GIT_WORKTREE_PROMPT_PROTECTED_ICON",
+        unicode: "⛔",
+        ascii: "[!]",
+        nerd: "\u{f023}",
+    },
+    Row {
+        glyph: Glyph::Merged,
+        key: "mergedIcon",
+        env_var: "GIT_WORKTREE_PROMPT_MERGED_ICON",
+        unicode: "✔",
+        ascii: "[merged]",
+        nerd: "\u{f419}",
+    },
+    Row {
+        glyph: Glyph::NoUpstream,
+        key: "noUpstreamIcon",
+        env_var: "GIT_WORKTREE_PROMPT_NO_UPSTREAM_ICON",
+        unicode: "◌",
+        ascii: "[local]",
+        nerd: "\u{f0ee}",
+    },
+    Row {
+        glyph: Glyph::UpstreamGone,
+        key: "upstreamGoneIcon",
+        env_var: "GIT_WORKTREE_PROMPT_UPSTREAM_GONE_ICON",
+        unicode: "⊘",
+        ascii: "[gone]",
+        nerd: "\u{f127}",
+    },
+    Row {
+        glyph: Glyph::Tracking,
+        key: "trackingIcon",
+        env_var: "GIT_WORKTREE_PROMPT_TRACKING_ICON",
+        unicode: "",
+        ascii: "",
+        nerd: "",
+    },
+    Row {
+        glyph: Glyph::StaleFetch,
+        key: "staleFetchIcon",
+        env_var: "GIT_WORKTREE_PROMPT_STALE_FETCH_ICON",
+        unicode: "⌛",
+        ascii: "[fetch]",
+        nerd: "\u{f0ed}",
+    },
+    Row {
+        glyph: Glyph::Remote,
+        key: "remoteIcon",
+        env_var: "GIT_WORKTREE_PROMPT_REMOTE_ICON",
+        unicode: "",
+        ascii: "",
+        nerd: "\u{e702}",
+    },
+    Row {
+        glyph: Glyph::Submodule,
+        key: "submoduleIcon",
+        env_var: "GIT_WORKTREE_PROMPT_SUBMODULE_ICON",
+        unicode: "›",
+        ascii: ">",
+        nerd: "\u{f105}",
+    },
+    Row {
+        glyph: Glyph::SubmoduleMoved,
+        key: "submoduleMovedIcon",
+        env_var: "GIT_WORKTREE_PROMPT_SUBMODULE_MOVED_ICON",
+        unicode: "≠",
+        ascii: "!=",
+        nerd: "\u{f126}",
+    },
+    Row {
+        glyph: Glyph::Shallow,
+        key: "shallowIcon",
+        env_var: "GIT_WORKTREE_PROMPT_SHALLOW_ICON",
+        unicode: "⇊",
+        ascii: "[shallow]",
+        nerd: "\u{f149}",
+    },
+    Row {
+        glyph: Glyph::Partial,
+        key: "partialIcon",
+        env_var: "GIT_WORKTREE_PROMPT_PARTIAL_ICON",
+        unicode: "◐",
+        ascii: "[partial]",
+        nerd: "\u{f042}",
+    },
+    Row {
+        glyph: Glyph::Sparse,
+        key: "sparseIcon",
+        env_var: "GIT_WORKTREE_PROMPT_SPARSE_ICON",
+        unicode: "⋯",
+        ascii: "[sparse]",
+        nerd: "\u{f0b0}",
+    },
+    Row {
+        glyph: Glyph::Conflicts,
+        key: "conflictsIcon",
+        env_var: "GIT_WORKTREE_PROMPT_CONFLICTS_ICON",
+        unicode: "✖",
+        ascii: "x",
+        nerd: "\u{f00d}",
+    },
+    Row {
+        glyph: Glyph::Jj,
+        key: "jjIcon",
+        env_var: "GIT_WORKTREE_PROMPT_JJ_ICON",
+        unicode: "◉",
+        ascii: "jj",
+        nerd: "\u{f1d2}",
+    },
+    Row {
+        glyph: Glyph::Ahead,
+        key: "aheadIcon",
+        env_var: "GIT_WORKTREE_PROMPT_AHEAD_ICON",
+        unicode: "⇡",
+        ascii: "^",
+        nerd: "\u{f062}",
+    },
+    Row {
+        glyph: Glyph::Behind,
+        key: "behindIcon",
+        env_var: "GIT_WORKTREE_PROMPT_BEHIND_ICON",
+        unicode: "⇣",
+        ascii: "v",
+        nerd: "\u{f063}",
+    },
+    Row {
+        glyph: Glyph::Staged,
+        key: "stagedIcon",
+        env_var: "GIT_WORKTREE_PROMPT_STAGED_ICON",
+        unicode: "+",
+        ascii: "+",
+        nerd: "\u{f067}",
+    },
+    Row {
+        glyph: Glyph::Modified,
+        key: "modifiedIcon",
+        env_var: "GIT_WORKTREE_PROMPT_MODIFIED_ICON",
+        unicode: "!",
+        ascii: "!",
+        nerd: "\u{f040}",
+    },
+    Row {
+        glyph: Glyph::Untracked,
+        key: "untrackedIcon",
+        env_var: "GIT_WORKTREE_PROMPT_UNTRACKED_ICON",
+        unicode: "%",
+        ascii: "%",
+        nerd: "\u{f128}",
+    },
+    Row {
+        glyph: Glyph::Stale,
+        key: "staleIcon",
+        env_var: "GIT_WORKTREE_PROMPT_STALE_ICON",
+        unicode: "~",
+        ascii: "~",
+        nerd: "\u{f017}",
+    },
+    Row {
+        glyph: Glyph::Skipped,
+        key: "skippedIcon",
+        env_var: "GIT_WORKTREE_PROMPT_SKIPPED_ICON",
+        unicode: "?",
+        ascii: "?",
+        nerd: "\u{f252}",
+    },
+];
+
+/// Glyphs in effect for one prompt, in GLYPHS order
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Icons {
+    glyphs: Vec<(Glyph, String)>,
+}
+
+impl Icons {
+    /// Resolves every glyph: repository key, then environment variable, then theme
+    pub(crate) fn resolve(settings: &RepoSettings, env: &Env) -> Icons {
+        let theme = settings
+            .get("theme")
+            .and_then(Theme::parse)
+            .or_else(|| env.get(ENV_THEME).and_then(Theme::parse))
+            .unwrap_or(Theme::Unicode);
+
+        let glyphs = GLYPHS
+            .iter()
+            .map(|row| {
+                let value = settings
+                    .get_or_env(row.key, env, row.env_var)
+                    .unwrap_or_else(|| row.themed(theme));
+                (row.glyph, value.to_string())
+            })
+            .collect();
+        Icons { glyphs }
+    }
+
+    pub(crate) fn get(&self, glyph: Glyph) -> &str {
+        self.glyphs
+            .iter()
+            .find(|(g, _)| *g == glyph)
+            .map_or("", |(_, value)| value)
+    }
+}
+
+impl Row {
+    fn themed(&self, theme: Theme) -> &'static str {
+        match theme {
+            Theme::Unicode => self.unicode,
+            Theme::Ascii => self.ascii,
+            Theme::Nerd => self.nerd,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_glyph_has_one_row() {
+        for (index, row) in GLYPHS.iter().enumerate() {
+            assert_eq!(row.glyph as usize, index, "{:?} is out of order", row.glyph);
+            assert!(row.ascii.is_ascii(), "{:?} ascii glyph", row.glyph);
+        }
+        assert_eq!(GLYPHS.len(), Glyph::Skipped as usize + 1);
+    }
+
+    #[test]
+    fn test_repository_settings_beat_theme() {
+        let settings = RepoSettings {
+            entries: vec![
+                ("theme".to_string(), "ascii".to_string()),
+                ("arrowicon".to_string(), "=>".to_string()),
+            ],
+            sources: Vec::new(),
+        };
+        let icons = Icons::resolve(&settings, &Env::new().with(ENV_THEME, "nerd"));
+        assert_eq!(icons.get(Glyph::Arrow), "=>");
+        assert_eq!(icons.get(Glyph::Ahead), "^");
+        assert_eq!(Theme::parse("Nerd-Font"), Some(Theme::Nerd));
+        assert_eq!(Theme::parse("emoji"), None);
+    }
+}
@@ -0,0 +1,131 @@
+use std::cell::RefCell;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+// ============================================================================
+// Discovery Tracing
+// ============================================================================
+//
+// With --debug and in `bench`, each discovery step records its duration and
+// every filesystem call on the hot path goes through the counting wrappers
+// below. Tracing is per thread and off unless enabled, so the daemon and
+// normal prompts pay only a thread-local check.
+
+thread_local! {
+    static TRACE: RefCell<Option<Trace>> = const { RefCell::new(None) };
+}
+
+/// Filesystem calls made while tracing, by kind
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct FsCalls {
+    /// stat-like calls (exists, is_file, is_dir, metadata): one syscall each
+    pub stat: u32,
+    /// Whole-file reads: open, fstat, read and close
+    pub read: u32,
+}
+
+impl FsCalls {
+    pub fn total(&self) -> u32 {
+        self.stat + self.read
+    }
+}
+
+/// Step timings and filesystem calls collected on one thread
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Trace {
+    pub steps: Vec<(&'static str, Duration)>,
+    pub fs: FsCalls,
+}
+
+/// Starts a fresh trace on the current thread
+pub fn enable() {
+    TRACE.with(|trace| *trace.borrow_mut() = Some(Trace::default()));
+}
+
+/// Stops tracing on the current thread and returns what was collected
+pub fn take() -> Option<Trace> {
+    TRACE.with(|trace| trace.borrow_mut().take())
+}
+
+fn with_trace(f: impl FnOnce(&mut Trace)) {
+    TRACE.with(|trace| {
+        if let Some(trace) = trace.borrow_mut().as_mut() {
+            f(trace);
+        }
+    });
+}
+
+/// Runs one discovery step, recording its duration when tracing
+pub fn step<T>(name: &'static str, f: impl FnOnce() -> T) -> T {
+    let started = Instant::now();
+    let result = f();
+    let elapsed = started.elapsed();
+    with_trace(|trace| trace.steps.push((name, elapsed)));
+    result
+}
+
+fn count_stat() {
+    with_trace(|trace| trace.fs.stat += 1);
+}
+
+fn count_read() {
+    with_trace(|trace| trace.fs.read += 1);
+}
+
+pub fn exists(path: &Path) -> bool {
+    count_stat();
+    path.exists()
+}
+
+pub fn is_file(path: &Path) -> bool {
+    count_stat();
+    path.is_file()
+}
+
+pub fn is_dir(path: &Path) -> bool {
+    count_stat();
+    path.is_dir()
+}
+
+pub fn metadata(path: &Path) -> io::Result<fs::Metadata> {
+    count_stat();
+    fs::metadata(path)
+}
+
+pub fn read_to_string(path: &Path) -> io::Result<String> {
+    count_read();
+    fs::read_to_string(path)
+}
+
+pub fn read(path: &Path) -> io::Result<Vec<u8>> {
+    count_read();
+    fs::read(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trace_counts_only_while_enabled() {
+        let dir = tempfile::TempDir::new().unwrap();
+        exists(dir.path());
+        assert_eq!(take(), None);
+
+        enable();
+        step("probe", || {
+            exists(dir.path());
+            is_dir(dir.path());
+            let _ = read_to_string(&dir.path().join("missing"));
+        });
+        let trace = take().unwrap();
+        assert_eq!(trace.fs, FsCalls { stat: 2, read: 1 });
+        assert_eq!(trace.steps.len(), 1);
+        assert_eq!(trace.steps[0].0, "probe");
+
+        exists(dir.path());
+        assert_eq!(take(), None, "take() should stop tracing");
+    }
+}
@@ -0,0 +1,218 @@
+use crate::gitconfig::Config;
+use crate::refs;
+use crate::settings::RepoSettings;
+use crate::{Env, Head};
+use std::path::Path;
+
+// ============================================================================
+// Upstream Tracking State
+// ============================================================================
+//
+// A branch's upstream is `branch.<name>.remote` plus `branch.<name>.merge`,
+// mapped through the remote's fetch refspecs to a remote-tracking ref such as
+// `refs/remotes/origin/foo`. That gives three states:
+//
+//   unset     no upstream configured yet, e.g. a branch never pushed
+//   gone      configured, but the tracking ref is missing, usually because
+//             the remote branch was deleted after a merge and fetch pruned it
+//   tracking  configured and the tracking ref exists (loose or packed)
+//
+// Everything comes from config and refs, so the state is part of discovery
+// and cached with it; GIT_WORKTREE_PROMPT_UPSTREAM=1 shows it as a marker,
+// and `fields` prints it as `upstream` and `upstream_ref`.
+
+/// Environment variable enabling the upstream markers
+pub(crate) const ENV_UPSTREAM: &str = "GIT_WORKTREE_PROMPT_UPSTREAM";
+
+/// Upstream state of the checked-out branch
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Upstream {
+    /// No upstream configured
+    Unset,
+    /// Configured, but this remote-tracking ref does not exist
+    Gone(String),
+    /// Configured and this remote-tracking ref exists
+    Tracking(String),
+}
+
+impl Upstream {
+    /// Short name of the remote-tracking ref, e.g. `origin/foo`
+    pub(crate) fn name(&self) -> Option<&str> {
+        match self {
+            Upstream::Unset => None,
+            Upstream::Gone(tracking_ref) | Upstream::Tracking(tracking_ref) => Some(
+                tracking_ref
+                    .strip_prefix("refs/remotes/")
+                    .or_else(|| tracking_ref.strip_prefix("refs/heads/"))
+                    .unwrap_or(tracking_ref),
+            ),
+        }
+    }
+
+    /// Encodes the state as `unset`, `gone <ref>` or `tracking <ref>`
+    pub(crate) fn encode(&self) -> String {
+        match self {
+            Upstream::Unset => "unset".to_string(),
+            Upstream::Gone(tracking_ref) => format!("gone {}", tracking_ref),
+            Upstream::Tracking(tracking_ref) => format!("tracking {}", tracking_ref),
+        }
+    }
+
+    pub(crate) fn decode(s: &str) -> Option<Upstream> {
+        match s.split_once(' ') {
+            None if s == "unset" => Some(Upstream::Unset),
+            Some(("gone", tracking_ref)) => Some(Upstream::Gone(tracking_ref.to_string())),
+            Some(("tracking", tracking_ref)) => Some(Upstream::Tracking(tracking_ref.to_string())),
+            _ => None,
+        }
+    }
+}
+
+/// True when the repository or the environment turns the markers on
+pub(crate) fn enabled(settings: &RepoSettings, env: &Env) -> bool {
+    let value = settings
+        .get_or_env("upstream", env, ENV_UPSTREAM)
+        .unwrap_or_default();
+    matches!(value.to_lowercase().as_str(), "1" | "true" | "yes" | "on")
+}
+
+/// Upstream state of the branch HEAD names, or None when HEAD is detached
+pub(crate) fn resolve(config: &Config, common_dir: &Path, head: &Head) -> Option<Upstream> {
+    let branch = head.symref.as_deref()?.strip_prefix("refs/heads/")?;
+    let remote = config.get(&format!("branch.{}.remote", branch));
+    let merge = config.get(&format!("branch.{}.merge", branch));
+    let (Some(remote), Some(merge)) = (remote, merge) else {
+        return Some(Upstream::Unset);
+    };
+
+    // An upstream the fetch refspecs don't store locally is no upstream to git either
+    let Some(tracking_ref) = tracking_ref(config, remote, merge) else {
+        return Some(Upstream::Unset);
+    };
+    if refs::exists(common_dir, &tracking_ref) {
+        Some(Upstream::Tracking(tracking_ref))
+    } else {
+        Some(Upstream::Gone(tracking_ref))
+    }
+}
+
+/// Local ref holding the last fetched state of `merge` on `remote`
+fn tracking_ref(config: &Config, remote: &str, merge: &str) -> Option<String> {
+    // `git branch --track` onto a local branch records the remote as `.`
+    if remote == "." {
+        return Some(merge.to_string());
+    }
+    let refspecs = config.get_all(&format!("remote.{}.fetch", remote));
+    if refspecs.is_empty() {
+        let branch = merge.strip_prefix("refs/heads/")?;
+        return Some(format!("refs/remotes/{}/{}", remote, branch));
+    }
+    refspecs
+        .into_iter()
+        .find_map(|refspec| map_refspec(refspec, merge))
+}
+
+/// Maps `name` through a fetch refspec such as `+refs/heads/*:refs/remotes/origin/*`
+fn map_refspec(refspec: &str, name: &str) -> Option<String> {
+    let refspec = refspec.strip_prefix('+').unwrap_or(refspec);
+    if refspec.starts_with('^') {
+        return None; // Negative refspecs only exclude
+    }
+    let (src, dst) = refspec.split_once(':')?;
+    match (src.split_once('*'), dst.split_once('*')) {
+        (Some((src_prefix, src_suffix)), Some((dst_prefix, dst_suffix))) => {
+            let matched = name.strip_prefix(src_prefix)?.strip_suffix(src_suffix)?;
+            Some(format!("{}{}{}", dst_prefix, matched, dst_suffix))
+        }
+        (None, None) if src == name && !dst.is_empty() => Some(dst.to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn head(branch: &str) -> Head {
+        Head {
+            name: branch.to_string(),
+            symref: Some(format!("refs/heads/{}", branch)),
+        }
+    }
+
+    #[test]
+    fn test_resolve_distinguishes_unset_gone_and_tracking() {
+        let dir = TempDir::new().unwrap();
+        let common_dir = dir.path();
+        fs::create_dir_all(common_dir.join("refs/remotes/origin")).unwrap();
+        fs::write(
+            common_dir.join("refs/remotes/origin/kept"),
+            format!("{}\n", "a".repeat(40)),
+        )
+        .unwrap();
+        fs::write(
+            common_dir.join("packed-refs"),
+            format!("{} refs/remotes/upstream/mirrored/packed\n", "b".repeat(40)),
+        )
+        .unwrap();
+        let config = Config::parse(
+            &common_dir.join("config"),
+            "[remote \"origin\"]\n\tfetch = +refs/heads/*:refs/remotes/origin/*\n\
+             [remote \"upstream\"]\n\tfetch = ^refs/heads/tmp/*\n\
+             \tfetch = +refs/heads/*:refs/remotes/upstream/mirrored/*\n\
+             [branch \"kept\"]\n\tremote = origin\n\tmerge = refs/heads/kept\n\
+             [branch \"gone\"]\n\tremote = origin\n\tmerge = refs/heads/gone\n\
+             [branch \"packed\"]\n\tremote = upstream\n\tmerge = refs/heads/packed\n\
+             [branch \"local\"]\n\tremote = .\n\tmerge = refs/heads/main\n",
+        )
+        .unwrap();
+
+        for (branch, expected) in [
+            (
+                "kept",
+                Upstream::Tracking("refs/remotes/origin/kept".to_string()),
+            ),
+            (
+                "gone",
+                Upstream::Gone("refs/remotes/origin/gone".to_string()),
+            ),
+            (
+                "packed",
+                Upstream::Tracking("refs/remotes/upstream/mirrored/packed".to_string()),
+            ),
+            ("local", Upstream::Gone("refs/heads/main".to_string())),
+            ("new", Upstream::Unset),
+        ] {
+            assert_eq!(
+                resolve(&config, common_dir, &head(branch)),
+                Some(expected),
+                "{}",
+                branch
+            );
+        }
+
+        let detached = Head {
+            name: "abc1234".to_string(),
+            symref: None,
+        };
+        assert_eq!(resolve(&config, common_dir, &detached), None);
+    }
+
+    #[test]
+    fn test_encoding_and_names() {
+        for upstream in [
+            Upstream::Unset,
+            Upstream::Gone("refs/remotes/origin/a b".to_string()),
+            Upstream::Tracking("refs/heads/main".to_string()),
+        ] {
+            assert_eq!(Upstream::decode(&upstream.encode()), Some(upstream));
+        }
+        assert_eq!(
+            Upstream::Gone("refs/remotes/origin/feature/x".to_string()).name(),
+            Some("origin/feature/x")
+        );
+        assert_eq!(Upstream::decode("tracking"), None);
+    }
+}
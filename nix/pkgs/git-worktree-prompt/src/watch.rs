@@ -0,0 +1,167 @@
+use crate::budget::Budget;
+use crate::cache::validated_paths;
+use crate::inotify::{self, Inotify};
+use crate::{Discovery, Env, Error, IoContext, Layout, discover, log_error, render};
+use std::collections::HashSet;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+// ============================================================================
+// Watch Mode
+// ============================================================================
+//
+// `git-worktree-prompt watch --path <dir>` streams the rendered prompt for
+// tmux and window-manager bars: one line up front and another whenever the
+// output changes. Re-rendering is driven by inotify on the directories
+// discovery depends on (the worktree's HEAD and .git pointer, the common
+// dir's refs and the .bare parent) instead of polling.
+
+/// Upper bound on ref directories watched in repos with deep ref hierarchies
+const MAX_REF_WATCHES: usize = 256;
+
+/// Streams rendered output for `path` to `out` until the directory disappears
+pub(crate) fn watch(path: &Path, env: &Env, out: &mut impl Write) -> Result<(), Error> {
+    let path = fs::canonicalize(path).with_path(path)?;
+    let inotify = Inotify::new().with_path(Path::new("inotify"))?;
+    let mut watched = HashSet::new();
+    let mut last = None;
+
+    inotify
+        .add_watch(&path, inotify::DIR_CHANGES)
+        .with_path(&path)?;
+
+    loop {
+        if !path.is_dir() {
+            return Ok(());
+        }
+
+        let rendered = match render(&path, env, &mut Budget::from_env(env)) {
+            Ok(rendered) => rendered.unwrap_or_default(),
+            Err(e) => {
+                log_error(&e, &path, env);
+                String::new()
+            }
+        };
+        if last.as_ref() != Some(&rendered) {
+            // A closed pipe means the bar went away - stop quietly
+            if writeln!(out, "{}", rendered)
+                .and_then(|_| out.flush())
+                .is_err()
+            {
+                return Ok(());
+            }
+            last = Some(rendered);
+        }
+
+        // Discovery can change which directories matter, so re-arm every round
+        if let Ok(Some(discovery)) = discover(&path, env) {
+            for dir in watch_dirs(&path, &discovery) {
+                if !watched.contains(&dir) && inotify.add_watch(&dir, inotify::DIR_CHANGES).is_ok()
+                {
+                    watched.insert(dir);
+                }
+            }
+        }
+
+        let events = inotify.read_events().with_path(Path::new("inotify"))?;
+        if events.iter().any(|e| e.mask & inotify::IN_IGNORED != 0) {
+            // Watched directories were removed; let the next round re-add them
+            watched.clear();
+        }
+    }
+}
+
+/// Directories whose changes can alter the rendered output
+fn watch_dirs(start: &Path, discovery: &Discovery) -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = validated_paths(start, discovery)
+        .iter()
+        .filter_map(|path| path.parent().map(Path::to_path_buf))
+        .collect();
+
+    if let Layout::BareParent(bare_parent) | Layout::Worktree { bare_parent, .. } =
+        &discovery.layout
+    {
+        dirs.push(bare_parent.clone());
+    }
+
+    // Every directory below refs/, so new and nested branches are noticed
+    let mut pending = vec![discovery.common_dir.join("refs")];
+    let mut ref_dirs = 0;
+    while let Some(dir) = pending.pop() {
+        if ref_dirs >= MAX_REF_WATCHES {
+            break;
+        }
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        pending.extend(
+            entries
+                .flatten()
+                .filter(|e| e.file_type().is_ok_and(|t| t.is_dir()))
+                .map(|e| e.path()),
+        );
+        dirs.push(dir);
+        ref_dirs += 1;
+    }
+
+    dirs.sort();
+    dirs.dedup();
+    dirs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Head;
+    use crate::settings::RepoSettings;
+    use crate::shape::Shape;
+
+    #[test]
+    fn test_watch_dirs_cover_refs_and_bare_parent() {
+        let root = tempfile::TempDir::new().unwrap();
+        let bare = root.path().join(".bare");
+        fs::create_dir_all(bare.join("refs/heads/feature")).unwrap();
+        fs::create_dir_all(bare.join("worktrees/topic")).unwrap();
+        let work_dir = root.path().join("topic");
+
+        let discovery = Discovery {
+            work_dir: work_dir.clone(),
+            git_dir: bare.join("worktrees/topic"),
+            common_dir: bare.clone(),
+            head: Head {
+                name: "topic".to_string(),
+                symref: Some("refs/heads/topic".to_string()),
+            },
+            layout: Layout::Worktree {
+                bare_parent: root.path().to_path_buf(),
+                path: "topic".to_string(),
+            },
+            settings: RepoSettings::default(),
+            upstream: None,
+            last_fetch: None,
+            remotes: Vec::new(),
+            submodule: None,
+            shape: Shape::default(),
+            operation: None,
+            jj: None,
+        };
+
+        let dirs = watch_dirs(&work_dir, &discovery);
+        for expected in [
+            root.path().to_path_buf(),
+            work_dir.clone(),
+            bare.join("worktrees/topic"),
+            bare.join("refs"),
+            bare.join("refs/heads"),
+            bare.join("refs/heads/feature"),
+        ] {
+            assert!(
+                dirs.contains(&expected),
+                "missing {:?} in {:?}",
+                expected,
+                dirs
+            );
+        }
+    }
+}
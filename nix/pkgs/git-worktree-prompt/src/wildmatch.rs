@@ -0,0 +1,89 @@
+use std::collections::HashSet;
+
+// ============================================================================
+// Glob Matching
+// ============================================================================
+//
+// The subset of git's wildmatch that `includeIf` conditions, ignore patterns,
+// branch styles and host icons need: `*` and `?` never cross a `/`, `**/`
+// matches any number of directories, and a trailing `**` matches the rest.
+
+/// Glob match where `*` and `?` stop at `/` and `**/` spans directories
+pub fn wildmatch(pattern: &[u8], text: &[u8]) -> bool {
+    matches(pattern, text, &mut HashSet::new())
+}
+
+/// Matches one pattern suffix against one text suffix
+///
+/// `failed` holds the suffix pairs, by remaining length, already known not to
+/// match. Each pair is tried at most once, so patterns like `*a*a*a*b` cannot
+/// backtrack exponentially on long names.
+fn matches(pattern: &[u8], text: &[u8], failed: &mut HashSet<(usize, usize)>) -> bool {
+    if failed.contains(&(pattern.len(), text.len())) {
+        return false;
+    }
+    let matched = match pattern {
+        [] => text.is_empty(),
+        [b'*', b'*', rest @ ..] => match rest {
+            [] => true,
+            [b'/', rest @ ..] => {
+                matches(rest, text, failed)
+                    || (0..text.len())
+                        .filter(|&i| text[i] == b'/')
+                        .any(|i| matches(rest, &text[i + 1..], failed))
+            }
+            _ => (0..=text.len()).any(|i| matches(rest, &text[i..], failed)),
+        },
+        [b'*', rest @ ..] => (0..=text.len())
+            .take_while(|&i| i == 0 || text[i - 1] != b'/')
+            .any(|i| matches(rest, &text[i..], failed)),
+        [b'?', rest @ ..] => {
+            text.first().is_some_and(|&c| c != b'/') && matches(rest, &text[1..], failed)
+        }
+        [c, rest @ ..] => text.first() == Some(c) && matches(rest, &text[1..], failed),
+    };
+    if !matched {
+        failed.insert((pattern.len(), text.len()));
+    }
+    matched
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wildmatch() {
+        for (pattern, text, expected) in [
+            ("**/work/**", "/home/me/work/project/.git", true),
+            ("**/work/**", "/home/me/homework/project/.git", false),
+            ("/home/*/work/**", "/home/me/work/x/.git", true),
+            ("/home/*/work/**", "/home/a/b/work/x/.git", false),
+            ("release/**", "release/1.0", true),
+            ("feat?", "feat/", false),
+            ("main", "main", true),
+            ("main", "maint", false),
+        ] {
+            assert_eq!(
+                wildmatch(pattern.as_bytes(), text.as_bytes()),
+                expected,
+                "{} ~ {}",
+                pattern,
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn test_wildmatch_pathological_patterns() {
+        // Without the memo these take exponential time and the test hangs
+        let name = "a".repeat(200);
+        let pattern = "*a".repeat(20) + "b";
+        assert!(!wildmatch(pattern.as_bytes(), name.as_bytes()));
+
+        let path = "/a".repeat(100);
+        let pattern = "**/a".repeat(20) + "/b";
+        assert!(!wildmatch(pattern.as_bytes(), path.as_bytes()));
+        assert!(wildmatch(("**/a".repeat(20)).as_bytes(), path.as_bytes()));
+    }
+}